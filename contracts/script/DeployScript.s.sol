@@ -29,11 +29,11 @@ contract DeployFlashSwap is Script {
         fees[5] = 9975; // 0.25%
         fees[6] = 9984; // 0.16%
 
-        // Deploy with WETH address for Base
-        address WETH = 0x4200000000000000000000000000000000000006;
+        // Deploy with the Aave addresses provider for Base
+        address AAVE_ADDRESS_PROVIDER = 0xe20fCBdBfFC4Dd138cE8b2E6FBb6CB49777ad64D;
         
         FlashSwap flashSwap = new FlashSwap(
-            WETH,
+            AAVE_ADDRESS_PROVIDER,
             factories,
             fees
         );
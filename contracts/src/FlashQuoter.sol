@@ -24,65 +24,140 @@ interface IUniswapV3Pool {
     function swap(address, bool, int256, uint160, bytes calldata) external returns (int256, int256);
 }
 
+interface IBalancerPool {
+    function getPoolId() external view returns (bytes32);
+}
+
+interface IBalancerVault {
+    struct SingleSwap {
+        bytes32 poolId;
+        uint8 kind;            // 0 for an exact input
+        address assetIn;
+        address assetOut;
+        uint256 amount;
+        bytes userData;
+    }
+
+    struct FundManagement {
+        address sender;
+        bool fromInternalBalance;
+        address payable recipient;
+        bool toInternalBalance;
+    }
+
+    function swap(SingleSwap memory, FundManagement memory, uint256, uint256) external returns (uint256);
+}
+
 contract FlashQuoter {
     struct SwapParams {
+        address tokenIn;       // token the path starts and ends in
         address[] pools;
-        uint8[] poolVersions;  // 0 for V2, 1 for V3
+        uint8[] poolVersions;  // 0 for V2, 1 for V3, 2 for Balancer
+        address[] tokensOut;   // token each pool swaps into, only read for Balancer pools
+        uint16[] splits;       // share of its leg's input per pool in bps, empty if no leg is split
         uint256 amountIn;
     }
 
+    // Chain specific state. The quoter is injected into a local database without running a
+    // constructor, so the bot writes these slots directly from its chain profile
+    mapping(address => uint16) private factoryFees;     // slot 0
+
     // Constants to avoid multiple memory allocations
     bytes private constant EMPTY_BYTES = new bytes(0);
     uint256 private constant PRECISION = 10000;
-    address private constant WETH = 0x4200000000000000000000000000000000000006;
     uint160 constant MIN_SQRT_RATIO = 4295128739;
     uint160 constant MAX_SQRT_RATIO = 1461446703485210103287273052203988822378723970342;
+    address constant BALANCER_VAULT = 0xBA12222222228d8Ba445958a75a0704d566BF2C8;
 
-    // Top function that is called to quote an arbitarge path. The path is a valid path that is starting and ending in WETH
+    // Top function that is called to quote an arbitarge path. The path is a valid path that is starting and ending in tokenIn.
+    // Consecutive pools whose splits add up to PRECISION form a leg that divides its input between them, the
+    // returned amounts are the input and then the output of each leg
     function quoteArbitrage(SwapParams calldata params) external returns (uint256[] memory) {
-        IERC20(WETH).transferFrom(msg.sender, address(this), params.amountIn);
+        IERC20(params.tokenIn).transferFrom(msg.sender, address(this), params.amountIn);
 
-        uint256[] memory amounts = new uint256[](params.pools.length + 1);
+        uint256[] memory amounts = new uint256[](_legCount(params.splits, params.pools.length) + 1);
         amounts[0] = params.amountIn;
         
         // Track the input token for each swap
-        address currentTokenIn = WETH;
+        address currentTokenIn = params.tokenIn;
+        uint256 leg;
+        uint256 filled;
+        uint256 legSpent;
 
         unchecked {
             for (uint256 i = 0; i < params.pools.length; i++) {
                 address pool = params.pools[i];
-                bool isV3 = params.poolVersions[i] == 1;
+                uint8 version = params.poolVersions[i];
                 
-                address token0;
-                address token1;
-                if (isV3) {
-                    IUniswapV3Pool v3Pool = IUniswapV3Pool(pool);
-                    token0 = v3Pool.token0();
-                    token1 = v3Pool.token1();
+                // Balancer pools hold more than two tokens, so the output token is given
+                address tokenOut;
+                bool zeroForOne;
+                if (version == 2) {
+                    tokenOut = params.tokensOut[i];
                 } else {
-                    IUniswapV2Pair v2Pool = IUniswapV2Pair(pool);
-                    token0 = v2Pool.token0();
-                    token1 = v2Pool.token1();
+                    address token0;
+                    address token1;
+                    if (version == 1) {
+                        IUniswapV3Pool v3Pool = IUniswapV3Pool(pool);
+                        token0 = v3Pool.token0();
+                        token1 = v3Pool.token1();
+                    } else {
+                        IUniswapV2Pair v2Pool = IUniswapV2Pair(pool);
+                        token0 = v2Pool.token0();
+                        token1 = v2Pool.token1();
+                    }
+
+                    // Determine if we're going token0 -> token1
+                    zeroForOne = currentTokenIn == token0;
+                    tokenOut = zeroForOne ? token1 : token0;
                 }
                 
-                // Determine if we're going token0 -> token1
-                bool zeroForOne = currentTokenIn == token0;
-                
-                // Approve and swap
-                IERC20(currentTokenIn).approve(pool, amounts[i]);
+                // The last pool of a leg takes the remainder so rounding never strands tokens
+                filled += params.splits.length == 0 ? PRECISION : params.splits[i];
+                uint256 swapIn = filled >= PRECISION
+                    ? amounts[leg] - legSpent
+                    : amounts[leg] * params.splits[i] / PRECISION;
+                legSpent += swapIn;
+
+                // Approve and swap, balancer pools are swapped through the Vault
+                IERC20(currentTokenIn).approve(version == 2 ? BALANCER_VAULT : pool, swapIn);
                 
-                amounts[i + 1] = isV3 ? 
-                    _swapV3(pool, amounts[i], currentTokenIn, zeroForOne) : 
-                    _swapV2(pool, amounts[i], zeroForOne);
+                if (version == 2) {
+                    amounts[leg + 1] += _swapBalancer(pool, swapIn, currentTokenIn, tokenOut);
+                } else {
+                    amounts[leg + 1] += version == 1 ? 
+                        _swapV3(pool, swapIn, currentTokenIn, zeroForOne) : 
+                        _swapV2(pool, swapIn, zeroForOne);
+                }
                 
-                // Set up the input token for the next swap
-                currentTokenIn = zeroForOne ? token1 : token0;
+                // Set up the input token for the next leg
+                if (filled >= PRECISION) {
+                    currentTokenIn = tokenOut;
+                    leg++;
+                    filled = 0;
+                    legSpent = 0;
+                }
             }
         }
 
         return amounts;
     }
 
+    // Number of legs in the path, every pool is its own leg without splits
+    function _legCount(uint16[] memory splits, uint256 pools) private pure returns (uint256 legs) {
+        if (splits.length == 0) return pools;
+        uint256 filled;
+        unchecked {
+            for (uint256 i = 0; i < splits.length; i++) {
+                filled += splits[i];
+                if (filled >= PRECISION) {
+                    legs++;
+                    filled = 0;
+                }
+            }
+        }
+    }
+
     function _swapV2(
         address poolAddress, 
         uint256 amountIn,
@@ -142,6 +217,31 @@ contract FlashQuoter {
         return uint256(-(zeroForOne ? amount1 : amount0));
     }
 
+    function _swapBalancer(
+        address poolAddress,
+        uint256 amountIn,
+        address tokenIn,
+        address tokenOut
+    ) private returns (uint256) {
+        IBalancerVault.SingleSwap memory singleSwap = IBalancerVault.SingleSwap({
+            poolId: IBalancerPool(poolAddress).getPoolId(),
+            kind: 0,
+            assetIn: tokenIn,
+            assetOut: tokenOut,
+            amount: amountIn,
+            userData: EMPTY_BYTES
+        });
+        IBalancerVault.FundManagement memory funds = IBalancerVault.FundManagement({
+            sender: address(this),
+            fromInternalBalance: false,
+            recipient: payable(address(this)),
+            toInternalBalance: false
+        });
+
+        // No output limit, like the other swaps
+        return IBalancerVault(BALANCER_VAULT).swap(singleSwap, funds, 0, block.timestamp);
+    }
+
     function uniswapV3SwapCallback(
         int256 amount0Delta,
         int256 amount1Delta,
@@ -154,15 +254,10 @@ contract FlashQuoter {
 
 
     // Get the fee for the factory
-    function _getFee(address factory) private pure returns (uint16) {
-        if (factory == 0x8909Dc15e40173Ff4699343b6eB8132c65e18eC6) return 9970;
-        if (factory == 0x71524B4f93c58fcbF659783284E38825f0622859) return 9970;
-        if (factory == 0x02a84c1b3BBD7401a5f7fa98a384EBC70bB5749E) return 9975;
-        if (factory == 0x04C9f118d21e8B767D2e50C946f0cC9F6C367300) return 9970;
-        if (factory == 0xFDa619b6d20975be80A10332cD39b9a4b0FAa8BB) return 9975;
-        if (factory == 0x591f122D1df761E616c13d265006fcbf4c6d6551) return 9975;
-        if (factory == 0x3E84D913803b02A4a7f027165E8cA42C14C0FdE7) return 9984;
-        return 9970; // Default fee
+    function _getFee(address factory) private view returns (uint16) {
+        uint16 fee = factoryFees[factory];
+        if (fee == 0) return 9970; // Default fee
+        return fee;
     }
 
     receive() external payable {}
@@ -24,20 +24,45 @@ interface IUniswapV3Pool {
     function swap(address, bool, int256, uint160, bytes calldata) external returns (int256, int256);
 }
 
-address constant AAVE_ADDRESS_PROVIDER = 0xe20fCBdBfFC4Dd138cE8b2E6FBb6CB49777ad64D;
+interface IBalancerPool {
+    function getPoolId() external view returns (bytes32);
+}
+
+interface IBalancerVault {
+    struct SingleSwap {
+        bytes32 poolId;
+        uint8 kind;            // 0 for an exact input
+        address assetIn;
+        address assetOut;
+        uint256 amount;
+        bytes userData;
+    }
+
+    struct FundManagement {
+        address sender;
+        bool fromInternalBalance;
+        address payable recipient;
+        bool toInternalBalance;
+    }
+
+    function swap(SingleSwap memory, FundManagement memory, uint256, uint256) external returns (uint256);
+}
+
 error InsufficientFundsToRepayFlashLoan(uint256 finalBalance);
 
 contract FlashSwap is FlashLoanSimpleReceiverBase {
 
     struct SwapParams {
+        address tokenIn;        // Token the path starts and ends in, borrowed from aave
         address[] pools;        // Array of pool addresses in swap order
-        uint8[] poolVersions;   // 0 = V2, 1 = V3
+        uint8[] poolVersions;   // 0 = V2, 1 = V3, 2 = Balancer
+        address[] tokensOut;    // Token each pool swaps into, only read for Balancer pools
+        uint16[] splits;        // Share of its leg's input per pool in bps, empty if no leg is split
         uint256 amountIn;
     }
 
     // Mapping from a factory to its fee
     mapping(address => uint16) private factoryFees;
-    address private immutable WETH;
     address public owner;
 
     // Constants to avoid multiple memory allocations
@@ -45,14 +70,15 @@ contract FlashSwap is FlashLoanSimpleReceiverBase {
     uint256 private constant PRECISION = 10000;
     uint160 constant MIN_SQRT_RATIO = 4295128739;
     uint160 constant MAX_SQRT_RATIO = 1461446703485210103287273052203988822378723970342;
+    address constant BALANCER_VAULT = 0xBA12222222228d8Ba445958a75a0704d566BF2C8;
 
-    // Construct a new flashswap contract. This will take in weth, the factories of the protoocls and their respective fees
+    // Construct a new flashswap contract. This will take in the aave addresses provider for the chain,
+    // the factories of the protoocls and their respective fees
     constructor(
-        address weth, 
+        address addressesProvider,
         address[] memory factories,
         uint16[] memory fees
-    ) FlashLoanSimpleReceiverBase(IPoolAddressesProvider(AAVE_ADDRESS_PROVIDER)) {
-        WETH = weth;
+    ) FlashLoanSimpleReceiverBase(IPoolAddressesProvider(addressesProvider)) {
         unchecked {
             // assign all the factories and their fees
             for (uint256 i = 0; i < factories.length; i++) {
@@ -67,7 +93,7 @@ contract FlashSwap is FlashLoanSimpleReceiverBase {
     function executeArbitrage(SwapParams calldata arb) external {
         // Encode the params of the swap
         bytes memory params = abi.encode(arb, msg.sender);
-        POOL.flashLoanSimple(address(this), WETH, arb.amountIn, params, 0);
+        POOL.flashLoanSimple(address(this), arb.tokenIn, arb.amountIn, params, 0);
     }
 
     // Callback from the flashswap
@@ -82,41 +108,69 @@ contract FlashSwap is FlashLoanSimpleReceiverBase {
 
         (SwapParams memory arb, address caller) = abi.decode(params, (SwapParams, address));
 
-        uint256[] memory amounts = new uint256[](arb.pools.length + 1);
+        // Consecutive pools whose splits add up to PRECISION form a leg that divides its input between them
+        uint256[] memory amounts = new uint256[](_legCount(arb.splits, arb.pools.length) + 1);
         amounts[0] = arb.amountIn;
 
          // Track the input token for each swap
-        address currentTokenIn = WETH;
+        address currentTokenIn = asset;
+        uint256 leg;
+        uint256 filled;
+        uint256 legSpent;
 
         unchecked {
             for (uint256 i = 0; i < arb.pools.length; i++) {
                 address pool = arb.pools[i];
-                bool isV3 = arb.poolVersions[i] == 1;
+                uint8 version = arb.poolVersions[i];
                 
-                address token0;
-                address token1;
-                if (isV3) {
-                    IUniswapV3Pool v3Pool = IUniswapV3Pool(pool);
-                    token0 = v3Pool.token0();
-                    token1 = v3Pool.token1();
+                // Balancer pools hold more than two tokens, so the output token is given
+                address tokenOut;
+                bool zeroForOne;
+                if (version == 2) {
+                    tokenOut = arb.tokensOut[i];
                 } else {
-                    IUniswapV2Pair v2Pool = IUniswapV2Pair(pool);
-                    token0 = v2Pool.token0();
-                    token1 = v2Pool.token1();
+                    address token0;
+                    address token1;
+                    if (version == 1) {
+                        IUniswapV3Pool v3Pool = IUniswapV3Pool(pool);
+                        token0 = v3Pool.token0();
+                        token1 = v3Pool.token1();
+                    } else {
+                        IUniswapV2Pair v2Pool = IUniswapV2Pair(pool);
+                        token0 = v2Pool.token0();
+                        token1 = v2Pool.token1();
+                    }
+
+                    // Determine if we're going token0 -> token1
+                    zeroForOne = currentTokenIn == token0;
+                    tokenOut = zeroForOne ? token1 : token0;
                 }
                 
-                // Determine if we're going token0 -> token1
-                bool zeroForOne = currentTokenIn == token0;
-                
-                // Approve and swap
-                IERC20(currentTokenIn).approve(pool, amounts[i]);
+                // The last pool of a leg takes the remainder so rounding never strands tokens
+                filled += arb.splits.length == 0 ? PRECISION : arb.splits[i];
+                uint256 swapIn = filled >= PRECISION
+                    ? amounts[leg] - legSpent
+                    : amounts[leg] * arb.splits[i] / PRECISION;
+                legSpent += swapIn;
+
+                // Approve and swap, balancer pools are swapped through the Vault
+                IERC20(currentTokenIn).approve(version == 2 ? BALANCER_VAULT : pool, swapIn);
                 
-                amounts[i + 1] = isV3 ? 
-                    _swapV3(pool, amounts[i], currentTokenIn, zeroForOne) : 
-                    _swapV2(pool, amounts[i], zeroForOne);
+                if (version == 2) {
+                    amounts[leg + 1] += _swapBalancer(pool, swapIn, currentTokenIn, tokenOut);
+                } else {
+                    amounts[leg + 1] += version == 1 ? 
+                        _swapV3(pool, swapIn, currentTokenIn, zeroForOne) : 
+                        _swapV2(pool, swapIn, zeroForOne);
+                }
                 
-                // Set up the input token for the next swap
-                currentTokenIn = zeroForOne ? token1 : token0;
+                // Set up the input token for the next leg
+                if (filled >= PRECISION) {
+                    currentTokenIn = tokenOut;
+                    leg++;
+                    filled = 0;
+                    legSpent = 0;
+                }
             }
         }
 
@@ -132,6 +186,21 @@ contract FlashSwap is FlashLoanSimpleReceiverBase {
         return true;
     }
 
+    // Number of legs in the path, every pool is its own leg without splits
+    function _legCount(uint16[] memory splits, uint256 pools) private pure returns (uint256 legs) {
+        if (splits.length == 0) return pools;
+        uint256 filled;
+        unchecked {
+            for (uint256 i = 0; i < splits.length; i++) {
+                filled += splits[i];
+                if (filled >= PRECISION) {
+                    legs++;
+                    filled = 0;
+                }
+            }
+        }
+    }
+
     function _swapV2(
         address poolAddress, 
         uint256 amountIn,
@@ -191,6 +260,31 @@ contract FlashSwap is FlashLoanSimpleReceiverBase {
         return uint256(-(zeroForOne ? amount1 : amount0));
     }
 
+    function _swapBalancer(
+        address poolAddress,
+        uint256 amountIn,
+        address tokenIn,
+        address tokenOut
+    ) private returns (uint256) {
+        IBalancerVault.SingleSwap memory singleSwap = IBalancerVault.SingleSwap({
+            poolId: IBalancerPool(poolAddress).getPoolId(),
+            kind: 0,
+            assetIn: tokenIn,
+            assetOut: tokenOut,
+            amount: amountIn,
+            userData: EMPTY_BYTES
+        });
+        IBalancerVault.FundManagement memory funds = IBalancerVault.FundManagement({
+            sender: address(this),
+            fromInternalBalance: false,
+            recipient: payable(address(this)),
+            toInternalBalance: false
+        });
+
+        // No output limit, like the other swaps
+        return IBalancerVault(BALANCER_VAULT).swap(singleSwap, funds, 0, block.timestamp);
+    }
+
     function uniswapV3SwapCallback(
         int256 amount0Delta,
         int256 amount1Delta,
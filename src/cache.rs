@@ -19,10 +19,13 @@ impl Hasher for CacheHasher {
     }
 }
 
-// Efficient cache key
+// Efficient cache key. The direction is part of it since a pool can be swapped from any of
+// its tokens into any other
 #[derive(PartialEq, Eq, Clone, Copy)]
 struct CacheKey {
     pool_address: Address,
+    token_in: Address,
+    token_out: Address,
     amount_in: U256,
 }
 
@@ -30,6 +33,8 @@ impl Hash for CacheKey {
     #[inline]
     fn hash<H: Hasher>(&self, state: &mut H) {
         self.pool_address.hash(state);
+        self.token_in.hash(state);
+        self.token_out.hash(state);
         self.amount_in.hash(state);
     }
 }
@@ -54,14 +59,40 @@ impl Cache {
     }
 
     #[inline]
-    pub fn get(&self, amount_in: U256, pool_address: Address) -> Option<U256> {
+    pub fn get(
+        &self,
+        amount_in: U256,
+        pool_address: Address,
+        token_in: Address,
+        token_out: Address,
+    ) -> Option<U256> {
         let key = CacheKey {
             pool_address,
+            token_in,
+            token_out,
             amount_in,
         };
         self.entries.get(&key).map(|entry| entry.output_amount)
     }
 
+    #[inline]
+    pub fn set(
+        &self,
+        amount_in: U256,
+        pool_address: Address,
+        token_in: Address,
+        token_out: Address,
+        output_amount: U256,
+    ) {
+        let key = CacheKey {
+            pool_address,
+            token_in,
+            token_out,
+            amount_in,
+        };
+        self.entries.insert(key, CacheEntry { output_amount });
+    }
+
     #[inline]
     pub fn invalidate(&self, pool_address: Address) {
         self.entries
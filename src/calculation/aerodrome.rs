@@ -6,6 +6,8 @@ use alloy::primitives::U256;
 use alloy::providers::Provider;
 use alloy::transports::Transport;
 
+use crate::errors::CalculationError;
+
 sol! {
     #[sol(rpc)]
     contract v2state {
@@ -20,14 +22,19 @@ where
     P: Provider<T, N>,
 {
     // Amount out calculation for aerodrome pools
-    pub fn aerodrome_out(&self, amount_in: U256, token_in: Address, pool_address: Address) -> U256 {
+    pub fn aerodrome_out(
+        &self,
+        amount_in: U256,
+        token_in: Address,
+        pool_address: Address,
+    ) -> Result<U256, CalculationError> {
         // get all of the state
         let db_read = self.market_state.db.read().unwrap();
-        let (reserve0, reserve1) = db_read.get_reserves(&pool_address);
+        let (reserve0, reserve1) = db_read.get_reserves(&pool_address)?;
         let pool_fee = db_read.get_fee(&pool_address);
         let (dec_0, dec_1) = db_read.get_decimals(&pool_address);
         let stable = db_read.get_stable(&pool_address);
-        let token0 = db_read.get_token0(pool_address);
+        let token0 = db_read.get_token0(pool_address)?;
 
         let mut _reserve0 = U256::from(reserve0);
         let mut _reserve1 = U256::from(reserve1);
@@ -68,9 +75,9 @@ where
                     token1_decimals,
                 );
             if token_in == token0 {
-                (y * token1_decimals) / U256::from(1e18)
+                Ok((y * token1_decimals) / U256::from(1e18))
             } else {
-                (y * token0_decimals) / U256::from(1e18)
+                Ok((y * token0_decimals) / U256::from(1e18))
             }
         } else {
             let (reserve_a, reserve_b) = if token_in == token0 {
@@ -78,7 +85,9 @@ where
             } else {
                 (_reserve1, _reserve0)
             };
-            (amount_in * reserve_b) / (reserve_a + amount_in)
+            (amount_in * reserve_b)
+                .checked_div(reserve_a + amount_in)
+                .ok_or_else(|| CalculationError::Math(pool_address, "empty reserves".to_string()))
         }
     }
 
@@ -1,114 +1,165 @@
-use alloy::primitives::{U256, I256};
+use super::Calculator;
+use alloy::network::Network;
+use alloy::primitives::{Address, I256, U256};
+use alloy::providers::Provider;
+use alloy::transports::Transport;
 use std::ops::Neg;
-use alloy::primitives::Address;
 use std::str::FromStr;
-use super::Calculator;
 
-impl Calculator {
+use crate::errors::CalculationError;
 
+impl<T, N, P> Calculator<T, N, P>
+where
+    T: Transport + Clone,
+    N: Network,
+    P: Provider<T, N>,
+{
+    // Amount out for a swap through a balancer weighted pool, with the state decoded from the
+    // Vault and the pool
     pub fn balancer_v2_out(
-        &self, 
+        &self,
         amount_in: U256,
+        pool_address: Address,
         token_in: Address,
         token_out: Address,
-        pool_address: Address,
-    ) -> U256 {
-        let pool = self.pool_manager.get_balancer_pool(&pool_address);
-        let token_in_index = pool.get_token_index(&token_in).unwrap();
-        let token_out_index = pool.get_token_index(&token_out).unwrap();
-        let balance_in = pool.balances[token_in_index];
-        let balance_out = pool.balances[token_out_index];
-        let weight_in = pool.weights[token_in_index];
-        let weight_out = pool.weights[token_out_index];
-        let swap_fee_percentage = pool.swap_fee;
-
-        let scaling_factor = 18 - pool.token0_decimals as i8;
-        let scaled_amount_in = Self::scale(amount_in, scaling_factor);
-        let scaled_amount_in_without_fees = Self::sub(scaled_amount_in, Self::mul_up(scaled_amount_in, swap_fee_percentage));
-        let amount_in = Self::scale(scaled_amount_in_without_fees, scaling_factor);
-
-        let denominator = Self::add(balance_in, amount_in);
-        let base = Self::div_up(balance_in, denominator);
-        let exponent = Self::div_down(weight_in, weight_out);
-        let power = Self::pow_up(base, exponent);
-
-        Self::mul_down(balance_out, Self::complement(power))
+    ) -> Result<U256, CalculationError> {
+        let db_read = self.market_state.db.read().unwrap();
+        let pool = db_read.balancer_state(&pool_address)?;
+        let missing = |token: Address| {
+            CalculationError::Math(pool_address, format!("token {token} is not in the pool"))
+        };
+        let index_in = pool.token_index(&token_in).ok_or_else(|| missing(token_in))?;
+        let index_out = pool.token_index(&token_out).ok_or_else(|| missing(token_out))?;
+
+        weighted_out(
+            amount_in,
+            WeightedToken {
+                balance: pool.balances[index_in],
+                weight: pool.weights[index_in],
+                decimals: pool.decimals[index_in],
+            },
+            WeightedToken {
+                balance: pool.balances[index_out],
+                weight: pool.weights[index_out],
+                decimals: pool.decimals[index_out],
+            },
+            pool.swap_fee,
+        )
+        .ok_or_else(|| CalculationError::Math(pool_address, "input over the max in ratio".to_string()))
     }
+}
 
-    fn scale(value: U256, decimals: i8) -> U256 {
-        value * (U256::from(10).pow(U256::from(decimals)))
-    }
+// One side of a weighted pool swap, the balance in token units and the weight at 18 decimals
+#[derive(Debug, Clone, Copy)]
+pub struct WeightedToken {
+    pub balance: U256,
+    pub weight: U256,
+    pub decimals: u8,
+}
 
-    fn add(a: U256, b: U256) -> U256 {
-        a + b
+// Amount out of a weighted pool for an exact input, the same way the pool computes it. The fee
+// comes off the input, then balances and amounts are scaled to 18 decimals for the weighted
+// math and the output is scaled back down. None for an input over 30% of the balance in,
+// which the pool rejects
+pub fn weighted_out(
+    amount_in: U256,
+    token_in: WeightedToken,
+    token_out: WeightedToken,
+    swap_fee: U256,
+) -> Option<U256> {
+    let amount_in = sub(amount_in, mul_up(amount_in, swap_fee));
+    let scale_in = scaling_factor(token_in.decimals);
+    let scale_out = scaling_factor(token_out.decimals);
+    let balance_in = token_in.balance * scale_in;
+    let balance_out = token_out.balance * scale_out;
+    let amount_in = amount_in * scale_in;
+
+    // _MAX_IN_RATIO of the weighted math
+    if amount_in > mul_down(balance_in, U256::from(3e17)) || token_out.weight.is_zero() {
+        return None;
     }
 
-    fn sub(a: U256, b: U256) -> U256 {
-        a - b
-    }
+    let denominator = add(balance_in, amount_in);
+    let base = div_up(balance_in, denominator);
+    let exponent = div_down(token_in.weight, token_out.weight);
+    let power = pow_up(base, exponent);
 
-    fn div_up(a: U256, b: U256) -> U256 {
-        let one = U256::from(1e18);
-        if a == U256::ZERO {
-            return U256::ZERO;
-        }
-        let a_inflated = a * one;
-        ((a_inflated - U256::from(1)) / b) + U256::from(1)
-    }
+    Some(mul_down(balance_out, complement(power)) / scale_out)
+}
 
-    fn div_down(a: U256, b: U256) -> U256 {
-        let one = U256::from(1e18);
-        if a == U256::ZERO {
-            return U256::ZERO;
-        }
-        let a_inflated = a * one;
-        a_inflated / b
-    }
+fn scaling_factor(decimals: u8) -> U256 {
+    U256::from(10).pow(U256::from(18 - decimals.min(18)))
+}
 
-    fn mul_up(a: U256, b: U256) -> U256 {
-        let one = U256::from(1e18);
-        let product = a * b;
+fn add(a: U256, b: U256) -> U256 {
+    a + b
+}
 
-        if product == U256::ZERO {
-            U256::ZERO
-        } else {
-            ((product - U256::from(1)) / one) + U256::from(1)
-        }
+fn sub(a: U256, b: U256) -> U256 {
+    a - b
+}
+
+fn div_up(a: U256, b: U256) -> U256 {
+    let one = U256::from(1e18);
+    if a == U256::ZERO {
+        return U256::ZERO;
     }
+    let a_inflated = a * one;
+    ((a_inflated - U256::from(1)) / b) + U256::from(1)
+}
 
-    fn mul_down(a: U256, b: U256) -> U256 {
-        let one = U256::from(1e18);
-        let product = a * b;
-        product / one
+fn div_down(a: U256, b: U256) -> U256 {
+    let one = U256::from(1e18);
+    if a == U256::ZERO {
+        return U256::ZERO;
     }
+    let a_inflated = a * one;
+    a_inflated / b
+}
 
-    fn pow_up(x: U256, y: U256) -> U256 {
-        let max_pow_relative_error = U256::from(10000);
-        let one = U256::from(1e18);
-        let two = one * U256::from(2);
-        let four = one * U256::from(4);
-        if y == one {
-            x
-        } else if y == two {
-            Self::mul_up(x, x)
-        } else if y == four {
-            let square = Self::mul_up(x, x);
-            return Self::mul_up(square, square);
-        } else {
-            let raw = LogExpMath::pow(x, y);
+fn mul_up(a: U256, b: U256) -> U256 {
+    let one = U256::from(1e18);
+    let product = a * b;
 
-            let max_error = Self::add(Self::mul_up(raw, max_pow_relative_error), U256::from(1));
-            return Self::add(raw, max_error);
-        }
+    if product == U256::ZERO {
+        U256::ZERO
+    } else {
+        ((product - U256::from(1)) / one) + U256::from(1)
     }
+}
 
-    fn complement(x: U256) -> U256 {
-        let one = U256::from(1e18);
-        if x < one {
-            one - x
-        } else {
-            U256::ZERO
-        }
+fn mul_down(a: U256, b: U256) -> U256 {
+    let one = U256::from(1e18);
+    let product = a * b;
+    product / one
+}
+
+fn pow_up(x: U256, y: U256) -> U256 {
+    let max_pow_relative_error = U256::from(10000);
+    let one = U256::from(1e18);
+    let two = one * U256::from(2);
+    let four = one * U256::from(4);
+    if y == one {
+        x
+    } else if y == two {
+        mul_up(x, x)
+    } else if y == four {
+        let square = mul_up(x, x);
+        mul_up(square, square)
+    } else {
+        let raw = LogExpMath::pow(x, y);
+
+        let max_error = add(mul_up(raw, max_pow_relative_error), U256::from(1));
+        add(raw, max_error)
+    }
+}
+
+fn complement(x: U256) -> U256 {
+    let one = U256::from(1e18);
+    if x < one {
+        one - x
+    } else {
+        U256::ZERO
     }
 }
 
@@ -185,8 +236,8 @@ impl LogExpMath {
             "PRODUCT_OUT_OF_BOUNDS"
         );
 
-        let exp_result = Self::exp(logx_times_y);
-        U256::try_from(exp_result.abs()).expect("Conversion to U256 failed")
+        // exp is never negative
+        Self::exp(logx_times_y).into_raw()
     }
 
     pub fn exp(x: I256) -> I256 {
@@ -461,3 +512,48 @@ impl LogExpMath {
         series_sum * I256::from_raw(U256::from(2))
     }
 }
+
+#[cfg(test)]
+mod balancer_tests {
+    use super::*;
+
+    fn token(balance: u128, weight: f64, decimals: u8) -> WeightedToken {
+        WeightedToken {
+            balance: U256::from(balance),
+            weight: U256::from(weight),
+            decimals,
+        }
+    }
+
+    // An even pool has an integer exponent and matches the closed form exactly
+    #[test]
+    fn test_even_pool() {
+        let one = 1_000_000_000_000_000_000u128;
+        let balance = token(1000 * one, 5e17, 18);
+        let out = weighted_out(U256::from(one), balance, balance, U256::ZERO).unwrap();
+        assert_eq!(out, U256::from(999_000_999_000_999_000u128));
+
+        // a 1% fee comes off the input first
+        let out = weighted_out(U256::from(one), balance, balance, U256::from(1e16)).unwrap();
+        assert!(out < U256::from(990_000_000_000_000_000u128));
+
+        // the pool rejects more than 30% of its balance in
+        assert!(weighted_out(U256::from(301 * one), balance, balance, U256::ZERO).is_none());
+    }
+
+    // An 80/20 pool goes through LogExpMath and agrees with the float formula, with the
+    // smaller token scaled to 18 decimals and back
+    #[test]
+    fn test_weighted_pool() {
+        let weth = token(100_000_000_000_000_000_000, 8e17, 18);
+        let usdc = token(100_000_000_000, 2e17, 6);
+        let amount_in = 1_000_000_000u128;
+        let out = weighted_out(U256::from(amount_in), usdc, weth, U256::ZERO).unwrap();
+
+        let expected = 100.0 * (1.0 - (100_000.0f64 / 101_000.0).powf(0.25)) * 1e18;
+        let relative = (f64::from(out) - expected).abs() / expected;
+        assert!(relative < 1e-9, "{out} vs {expected}");
+        // pow rounds up so the output never overshoots
+        assert!(f64::from(out) <= expected);
+    }
+}
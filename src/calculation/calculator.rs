@@ -7,9 +7,13 @@ use std::collections::HashSet;
 use std::sync::Arc;
 
 use crate::cache::Cache;
+use crate::config::BotConfig;
+use crate::errors::CalculationError;
 use crate::market_state::MarketState;
 use crate::swap::*;
-use crate::AMOUNT;
+
+// Chunks a split leg's input is handed out in, 5% each
+const SPLIT_CHUNKS: u16 = 20;
 
 // Calculator for getting the amount out
 pub struct Calculator<T, N, P>
@@ -20,6 +24,7 @@ where
 {
     pub market_state: Arc<MarketState<T, N, P>>,
     pub cache: Arc<Cache>,
+    pub config: Arc<BotConfig>,
 }
 
 impl<T, N, P> Calculator<T, N, P>
@@ -30,97 +35,132 @@ where
 {
     // construct a new calculator
     // contains the market state to access pool info and a cache for calculations
-    pub fn new(market_state: Arc<MarketState<T, N, P>>) -> Self {
+    pub fn new(market_state: Arc<MarketState<T, N, P>>, config: Arc<BotConfig>) -> Self {
         Self {
             market_state,
             cache: Arc::new(Cache::new(500)),
+            config,
         }
     }
 
     // calculate the output amount
     // we can get read access to the db since we know it will not change for duration of calculation
     #[inline]
-    pub fn calculate_output(&self, path: &SwapPath) -> U256 {
-        let mut amount = *AMOUNT;
-
-        // for each step, calculate the amount out
-        for swap_step in &path.steps {
-            let pool_address = swap_step.pool_address;
-
-            // check to see if we have a up to date cache
-            if let Some(cached_amount) = self.cache.get(amount, pool_address) {
-                amount = cached_amount;
-            } else {
-                // compute the output amount and then store it in cache
-                let output_amount = self.compute_amount_out(
-                    amount,
-                    pool_address,
-                    swap_step.token_in,
-                    swap_step.protocol,
-                    swap_step.fee,
-                );
-                self.cache.set(amount, pool_address, output_amount);
-                amount = output_amount;
-            }
+    pub fn calculate_output(&self, path: &SwapPath) -> Result<U256, CalculationError> {
+        let mut amount = self.config.base_amount(&path.base_token());
 
+        // for each leg, calculate the amount out of every pool it is split across
+        for leg in path.legs() {
+            amount = self.leg_output(amount, &leg)?;
             if amount == U256::ZERO {
-               return U256::ZERO;
+               return Ok(U256::ZERO);
             }
         }
 
         // all good, return the output amount of the path
-        amount
+        Ok(amount)
     }
 
-    pub fn debug_calculation(&self, path: &SwapPath) -> Vec<U256> {
+    // The input and then the output of every leg, the same amounts the quoter returns
+    pub fn debug_calculation(&self, path: &SwapPath) -> Result<Vec<U256>, CalculationError> {
         let mut path_calc: Vec<U256> = Vec::new();
-        let mut amount = *AMOUNT;
+        let mut amount = self.config.base_amount(&path.base_token());
         path_calc.push(amount);
 
-        for swap_step in &path.steps {
-            let pool_address = swap_step.pool_address;
-            let output_amount = self.compute_amount_out(
-                amount,
-                pool_address,
-                swap_step.token_in,
-                swap_step.protocol,
-                swap_step.fee,
-            );
-            path_calc.push(output_amount);
-            amount = output_amount;
+        for leg in path.legs() {
+            amount = self.leg_output(amount, &leg)?;
+            path_calc.push(amount);
+        }
+
+        Ok(path_calc)
+    }
+
+    // Output of a leg with its input divided between its pools
+    fn leg_output(&self, amount: U256, leg: &[(&SwapStep, u16)]) -> Result<U256, CalculationError> {
+        let shares: Vec<u16> = leg.iter().map(|(_, share)| *share).collect();
+        let mut output = U256::ZERO;
+        for ((swap_step, _), part) in leg.iter().zip(split_amount(amount, &shares)) {
+            output += self.step_output(part, swap_step)?;
+        }
+        Ok(output)
+    }
+
+    fn step_output(&self, amount: U256, swap_step: &SwapStep) -> Result<U256, CalculationError> {
+        let pool_address = swap_step.pool_address;
+        let (token_in, token_out) = (swap_step.token_in, swap_step.token_out);
+
+        // check to see if we have a up to date cache
+        if let Some(cached_amount) = self.cache.get(amount, pool_address, token_in, token_out) {
+            return Ok(cached_amount);
         }
 
-        path_calc
+        // compute the output amount and then store it in cache
+        let output_amount = self.compute_amount_out(
+            amount,
+            pool_address,
+            token_in,
+            token_out,
+            swap_step.protocol,
+            swap_step.fee,
+        )?;
+        self.cache.set(amount, pool_address, token_in, token_out, output_amount);
+        Ok(output_amount)
+    }
+
+    // Split each step of a path across the parallel pools for the same tokens where that
+    // pays more. Returns the split path and its output if any leg was split
+    pub fn split_path<F>(
+        &self,
+        path: &SwapPath,
+        parallel: F,
+    ) -> Result<Option<(SwapPath, U256)>, CalculationError>
+    where
+        F: Fn(&SwapStep, &HashSet<Address>) -> Vec<SwapStep>,
+    {
+        let amount = self.config.base_amount(&path.base_token());
+        find_split_path(path, amount, parallel, |input, step| self.step_output(input, step))
     }
 
-    pub fn compute_pool_output(&self, pool_addr: Address, token_in: Address, protocol: PoolType, fee: u32, input: U256) -> U256 {
+    pub fn compute_pool_output(
+        &self,
+        pool_addr: Address,
+        token_in: Address,
+        token_out: Address,
+        protocol: PoolType,
+        fee: u32,
+        input: U256,
+    ) -> Result<U256, CalculationError> {
         self.compute_amount_out(
             input,
             pool_addr,
             token_in,
+            token_out,
             protocol,
             fee
         )
     }
 
-    // calculate the ratio for the pool
+    // calculate the ratio for the pool. Two token pools only need the input token, multi
+    // token pools also need the output
     pub fn compute_amount_out(
         &self,
         input_amount: U256,
         pool_address: Address,
         token_in: Address,
+        token_out: Address,
         pool_type: PoolType,
         fee: u32,
-    ) -> U256 {
+    ) -> Result<U256, CalculationError> {
         match pool_type {
-            PoolType::UniswapV2 | PoolType::SushiSwapV2 | PoolType::SwapBasedV2 => {
-                self.uniswap_v2_out(input_amount, &pool_address, &token_in, U256::from(9970))
-            }
-            PoolType::PancakeSwapV2 | PoolType::BaseSwapV2 | PoolType::DackieSwapV2 => {
-                self.uniswap_v2_out(input_amount, &pool_address, &token_in, U256::from(9975))
-            }
-            PoolType::AlienBaseV2 => {
-                self.uniswap_v2_out(input_amount, &pool_address, &token_in, U256::from(9984))
+            PoolType::UniswapV2
+            | PoolType::SushiSwapV2
+            | PoolType::SwapBasedV2
+            | PoolType::PancakeSwapV2
+            | PoolType::BaseSwapV2
+            | PoolType::DackieSwapV2
+            | PoolType::AlienBaseV2 => {
+                let fee = self.config.chain.v2_fee(pool_type);
+                self.uniswap_v2_out(input_amount, &pool_address, &token_in, fee)
             }
             PoolType::UniswapV3
             | PoolType::SushiSwapV3
@@ -129,13 +169,17 @@ where
             | PoolType::PancakeSwapV3
             | PoolType::AlienBaseV3
             | PoolType::SwapBasedV3
-            | PoolType::DackieSwapV3 => self
-                .uniswap_v3_out(input_amount, &pool_address, &token_in, fee)
-                .unwrap(),
+            | PoolType::DackieSwapV3 => {
+                self.uniswap_v3_out(input_amount, &pool_address, &token_in, fee)
+            }
             PoolType::Aerodrome => self.aerodrome_out(input_amount, token_in, pool_address),
-            PoolType::MaverickV1 | PoolType::MaverickV2 => todo!(),
-            PoolType::BalancerV2 => todo!(),
-            PoolType::CurveTwoCrypto | PoolType::CurveTriCrypto => todo!()
+            PoolType::BalancerV2 => {
+                self.balancer_v2_out(input_amount, pool_address, token_in, token_out)
+            }
+            PoolType::MaverickV1
+            | PoolType::MaverickV2
+            | PoolType::CurveTwoCrypto
+            | PoolType::CurveTriCrypto => Err(CalculationError::UnsupportedProtocol(pool_type)),
         }
     }
 
@@ -146,3 +190,175 @@ where
         }
     }
 }
+
+// Split each step of a path starting with amount across the parallel pools for the same
+// tokens where that pays more. Returns the split path and its output if any leg was split.
+// Parallel pools are asked for with the pools the path already swaps through, including
+// the ones earlier legs were split into, since each pool can only be priced once
+pub fn find_split_path<F, O>(
+    path: &SwapPath,
+    mut amount: U256,
+    parallel: F,
+    output: O,
+) -> Result<Option<(SwapPath, U256)>, CalculationError>
+where
+    F: Fn(&SwapStep, &HashSet<Address>) -> Vec<SwapStep>,
+    O: Fn(U256, &SwapStep) -> Result<U256, CalculationError>,
+{
+    let mut used: HashSet<Address> = path.steps.iter().map(|step| step.pool_address).collect();
+    let mut steps = Vec::new();
+    let mut splits = Vec::new();
+    let mut split_any = false;
+    for swap_step in &path.steps {
+        let mut candidates = vec![swap_step.clone()];
+        candidates.extend(parallel(swap_step, &used));
+        let single = output(amount, swap_step)?;
+        let (leg, leg_output) = find_best_split(amount, candidates, &output);
+        if leg.len() > 1 && leg_output > single {
+            split_any = true;
+            for (step, share) in leg {
+                used.insert(step.pool_address);
+                steps.push(step);
+                splits.push(share);
+            }
+            amount = leg_output;
+        } else {
+            steps.push(swap_step.clone());
+            splits.push(SPLIT_PRECISION);
+            amount = single;
+        }
+    }
+
+    if !split_any {
+        return Ok(None);
+    }
+    Ok(Some((SwapPath::with_splits(steps, splits), amount)))
+}
+
+// Divide an amount between pools for the same tokens. The amount is handed out a chunk
+// at a time to whichever pool adds the most output for it, which is optimal up to the
+// chunk size since outputs have diminishing returns. Pools that can not be calculated
+// get nothing, and pools that get nothing are left out of the leg
+pub fn find_best_split<O>(amount: U256, candidates: Vec<SwapStep>, output: O) -> (Vec<(SwapStep, u16)>, U256)
+where
+    O: Fn(U256, &SwapStep) -> Result<U256, CalculationError>,
+{
+    let chunk_share = SPLIT_PRECISION / SPLIT_CHUNKS;
+    let mut shares = vec![0u16; candidates.len()];
+    let mut outputs = vec![U256::ZERO; candidates.len()];
+    for _ in 0..SPLIT_CHUNKS {
+        let best = candidates
+            .iter()
+            .enumerate()
+            .filter_map(|(i, step)| {
+                let share = shares[i] + chunk_share;
+                let input = amount * U256::from(share) / U256::from(SPLIT_PRECISION);
+                let out = output(input, step).ok()?;
+                Some((i, out, out.saturating_sub(outputs[i])))
+            })
+            .max_by_key(|(_, _, gain)| *gain);
+        let Some((i, out, _)) = best else {
+            return (Vec::new(), U256::ZERO);
+        };
+        shares[i] += chunk_share;
+        outputs[i] = out;
+    }
+
+    // recalculate with the exact amounts the contract will swap
+    let leg: Vec<(SwapStep, u16)> = candidates
+        .into_iter()
+        .zip(shares)
+        .filter(|(_, share)| *share > 0)
+        .collect();
+    let parts = split_amount(amount, &leg.iter().map(|(_, share)| *share).collect::<Vec<_>>());
+    let mut total = U256::ZERO;
+    for ((step, _), part) in leg.iter().zip(parts) {
+        match output(part, step) {
+            Ok(out) => total += out,
+            Err(_) => return (Vec::new(), U256::ZERO),
+        }
+    }
+    (leg, total)
+}
+
+#[cfg(test)]
+mod calculator_tests {
+    use super::*;
+    use crate::config::CycleConfig;
+    use crate::graph::ArbGraph;
+    use crate::tests::helpers::fixtures::pools::{pool, USDC, WETH};
+
+    fn step(pool: u8, token_in: Address, token_out: Address) -> SwapStep {
+        SwapStep {
+            pool_address: Address::with_last_byte(pool),
+            token_in,
+            token_out,
+            protocol: PoolType::UniswapV2,
+            fee: 0,
+        }
+    }
+
+    // Constant product pools without fees, pool 1 is four times as deep as pool 2, pool 4
+    // twice as deep, and pool 3 can not be calculated
+    fn output(amount: U256, step: &SwapStep) -> Result<U256, CalculationError> {
+        let reserve = match step.pool_address[19] {
+            1 => U256::from(4_000_000_000u64),
+            2 => U256::from(1_000_000_000u64),
+            4 => U256::from(2_000_000_000u64),
+            _ => return Err(CalculationError::Math(step.pool_address, "no state".to_string())),
+        };
+        Ok(reserve * amount / (reserve + amount))
+    }
+
+    // Parallel pools of different depth get unequal shares of the whole input and together
+    // beat either pool alone, a pool that fails is left out
+    #[test]
+    fn test_find_best_split() {
+        let amount = U256::from(1_000_000_000u64);
+        let (leg, split) = find_best_split(amount, vec![step(1, WETH, USDC), step(2, WETH, USDC)], output);
+        assert_eq!(leg.len(), 2);
+        let shares: Vec<u16> = leg.iter().map(|(_, share)| *share).collect();
+        assert!(shares[0] > shares[1] && shares[1] > 0);
+        assert_eq!(shares.iter().sum::<u16>(), SPLIT_PRECISION);
+        assert!(split > output(amount, &step(1, WETH, USDC)).unwrap());
+        assert!(split > output(amount, &step(2, WETH, USDC)).unwrap());
+
+        let candidates = vec![step(1, WETH, USDC), step(3, WETH, USDC), step(2, WETH, USDC)];
+        let (with_failing, output_with_failing) = find_best_split(amount, candidates, output);
+        assert_eq!(with_failing, leg);
+        assert_eq!(output_with_failing, split);
+
+        let (none, nothing) = find_best_split(amount, vec![step(3, WETH, USDC)], output);
+        assert!(none.is_empty());
+        assert_eq!(nothing, U256::ZERO);
+    }
+
+    // The weth leg is split into the weth/usdc pool the path does not use, never into the
+    // pool of the usdc leg, which then has nothing left to split into. A path without any
+    // parallel pool is left alone
+    #[test]
+    fn test_find_split_path() {
+        let amount = U256::from(1_000_000_000u64);
+        let pools = vec![pool(1, WETH, USDC), pool(2, WETH, USDC), pool(3, WETH, USDC), pool(4, WETH, USDC)];
+        let cycle_config = CycleConfig {
+            max_hops: 2,
+            min_liquidity: U256::ZERO,
+            max_cycles_per_pool: None,
+            cache_dir: None,
+        };
+        let graph = ArbGraph::build(pools, vec![WETH], cycle_config);
+        let path = SwapPath::new(vec![step(1, WETH, USDC), step(2, USDC, WETH)]);
+        let single = output(output(amount, &path.steps[0]).unwrap(), &path.steps[1]).unwrap();
+        let parallel = |swap_step: &SwapStep, used: &HashSet<Address>| graph.parallel_steps(swap_step, used);
+
+        let (split, split_output) = find_split_path(&path, amount, parallel, output).unwrap().unwrap();
+        let split_pools: Vec<u8> = split.steps.iter().map(|step| step.pool_address[19]).collect();
+        assert_eq!(split_pools, vec![1, 4, 2]);
+        assert_eq!(split.legs().len(), 2);
+        assert_eq!(split.splits[0] + split.splits[1], SPLIT_PRECISION);
+        assert_eq!(split.splits[2], SPLIT_PRECISION);
+        assert!(split_output > single);
+
+        assert!(find_split_path(&path, amount, |_, _| Vec::new(), output).unwrap().is_none());
+    }
+}
@@ -4,10 +4,11 @@ use alloy::primitives::Address;
 use alloy::primitives::{I256, U256};
 use alloy::providers::Provider;
 use alloy::transports::Transport;
-use anyhow::Result;
 use std::collections::HashMap;
 use uniswap_v3_math::tick_math::{MAX_SQRT_RATIO, MAX_TICK, MIN_SQRT_RATIO, MIN_TICK};
 
+use crate::errors::CalculationError;
+
 pub const U256_1: U256 = U256::from_limbs([1, 0, 0, 0]);
 
 pub struct CurrentState {
@@ -29,6 +30,11 @@ pub struct StepComputations {
     pub fee_amount: U256,
 }
 
+// Wrap a math library error with the pool it came from
+fn math_err<E: std::fmt::Display>(pool: Address) -> impl Fn(E) -> CalculationError {
+    move |e| CalculationError::Math(pool, e.to_string())
+}
+
 //Computes the position in the mapping where the initialized bit for a tick lives
 pub fn position(tick: i32) -> (i16, u8) {
     ((tick >> 8) as i16, (tick % 256) as u8)
@@ -48,11 +54,11 @@ where
         pool_address: &Address,
         token_in: &Address,
         fee: U256,
-    ) -> U256 {
+    ) -> Result<U256, CalculationError> {
         // get read access to db
         let db_read = self.market_state.db.read().unwrap();
-        let zero_to_one = db_read.zero_to_one(pool_address, *token_in).unwrap();
-        let (reserve0, reserve1) = db_read.get_reserves(pool_address);
+        let zero_to_one = db_read.zero_to_one(pool_address, *token_in)?;
+        let (reserve0, reserve1) = db_read.get_reserves(pool_address)?;
 
         let scalar = U256::from(10000);
 
@@ -65,7 +71,9 @@ where
         let amount_in_with_fee = amount_in * fee;
         let numerator = amount_in_with_fee * reserve1;
         let denominator = reserve0 * scalar + amount_in_with_fee;
-        numerator / denominator
+        numerator
+            .checked_div(denominator)
+            .ok_or_else(|| CalculationError::Math(*pool_address, "empty reserves".to_string()))
     }
 
     // calculate the amount out for a uniswapv3 swap
@@ -76,14 +84,14 @@ where
         pool_address: &Address,
         token_in: &Address,
         fee: u32,
-    ) -> Result<U256> {
+    ) -> Result<U256, CalculationError> {
         if amount_in.is_zero() {
             return Ok(U256::ZERO);
         }
 
         // acquire db read access and get all our state information
         let db_read = self.market_state.db.read().unwrap();
-        let zero_to_one = db_read.zero_to_one(pool_address, *token_in).unwrap();
+        let zero_to_one = db_read.zero_to_one(pool_address, *token_in)?;
         let slot0 = db_read.slot0(*pool_address)?;
         let liquidity = db_read.liquidity(*pool_address)?;
         let tick_spacing = db_read.tick_spacing(pool_address)?;
@@ -130,7 +138,8 @@ where
                     current_state.tick,
                     tick_spacing,
                     zero_to_one,
-                )?;
+                )
+                .map_err(math_err(*pool_address))?;
 
             // ensure that we do not overshoot the min/max tick, as the tick bitmap is not aware of these bounds
             // Note: this could be removed as we are clamping in the batch contract
@@ -138,7 +147,7 @@ where
 
             // Get the next sqrt price from the input amount
             step.sqrt_price_next_x96 =
-                uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(step.tick_next)?;
+                uniswap_v3_math::tick_math::get_sqrt_ratio_at_tick(step.tick_next).map_err(math_err(*pool_address))?;
 
             // Target spot price
             let swap_target_sqrt_ratio = if zero_to_one {
@@ -161,7 +170,8 @@ where
                     current_state.liquidity,
                     current_state.amount_specified_remaining,
                     fee,
-                )?;
+                )
+                .map_err(math_err(*pool_address))?;
 
             // Update state using exact input logic from on-chain code
             current_state.amount_specified_remaining -= I256::from_raw(
@@ -182,10 +192,10 @@ where
                     
                     current_state.liquidity = if liquidity_net < 0 {
                         current_state.liquidity.checked_sub(-liquidity_net as u128)
-                            .ok_or_else(|| anyhow::anyhow!("Insufficient liquidity"))?
+                            .ok_or_else(|| CalculationError::Math(*pool_address, "insufficient liquidity".to_string()))?
                     } else {
                         current_state.liquidity.checked_add(liquidity_net as u128)
-                            .ok_or_else(|| anyhow::anyhow!("Liquidity overflow"))?
+                            .ok_or_else(|| CalculationError::Math(*pool_address, "liquidity overflow".to_string()))?
                     };
                 }
                 current_state.tick = if zero_to_one {
@@ -196,7 +206,8 @@ where
             } else if current_state.sqrt_price_x_96 != step.sqrt_price_start_x_96 {
                 current_state.tick = uniswap_v3_math::tick_math::get_tick_at_sqrt_ratio(
                     current_state.sqrt_price_x_96,
-                )?;
+                )
+                .map_err(math_err(*pool_address))?;
             }
         }
 
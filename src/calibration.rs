@@ -0,0 +1,177 @@
+use alloy::primitives::{Address, U256};
+use log::info;
+use pool_sync::PoolType;
+use std::collections::{HashMap, VecDeque};
+
+use crate::metrics::ESTIMATE_ERROR;
+use crate::swap::SwapPath;
+
+// Errors kept per protocol and token, older ones are dropped as the market drifts
+const WINDOW: usize = 256;
+// Errors a protocol needs before estimates through it are corrected
+const MIN_SAMPLES: usize = 16;
+// Largest correction in log space, about 2x either way, so a run of outliers can not switch
+// screening off
+const MAX_CORRECTION: f64 = 0.7;
+// Calculated paths between logged summaries
+const SUMMARY_INTERVAL: u64 = 100;
+// Tokens listed in a summary, the ones estimated worst first
+const SUMMARY_TOKENS: usize = 5;
+
+// Tracks how far estimates are from the calculated outputs and corrects later estimates by
+// it. Errors are ln(calculated / estimated). A path's error is split evenly over its steps,
+// and a path is corrected by the median step error of each protocol it swaps through. Tokens
+// keep the errors of the paths through them for the summaries
+#[derive(Debug, Default)]
+pub struct Calibration {
+    protocols: HashMap<PoolType, VecDeque<f64>>,
+    tokens: HashMap<Address, VecDeque<f64>>,
+    samples: u64,
+}
+
+impl Calibration {
+    // Record a path that was estimated and then calculated, returns the error. Paths with a
+    // zero estimate or output say nothing about the rates and are not recorded
+    pub fn record(&mut self, path: &SwapPath, estimated: U256, calculated: U256) -> Option<f64> {
+        if estimated.is_zero() || calculated.is_zero() || path.steps.is_empty() {
+            return None;
+        }
+        let error = (f64::from(calculated) / f64::from(estimated)).ln();
+        let step_error = error / path.steps.len() as f64;
+        for step in &path.steps {
+            push(self.protocols.entry(step.protocol).or_default(), step_error);
+            ESTIMATE_ERROR
+                .with_label_values(&[&format!("{:?}", step.protocol)])
+                .observe(step_error.exp());
+        }
+        let mut tokens: Vec<Address> = path.steps.iter().map(|step| step.token_in).collect();
+        tokens.sort();
+        tokens.dedup();
+        for token in tokens {
+            push(self.tokens.entry(token).or_default(), error);
+        }
+
+        self.samples += 1;
+        if self.samples % SUMMARY_INTERVAL == 0 {
+            self.log_summary();
+        }
+        Some(error)
+    }
+
+    // The estimate corrected by the typical error of the protocols on the path
+    pub fn correct(&self, path: &SwapPath, estimated: U256) -> U256 {
+        let factor = self.log_correction(path).exp();
+        estimated * U256::from((factor * 1e6) as u64) / U256::from(1_000_000)
+    }
+
+    // The correction in log space, added to a path's log score
+    pub fn log_correction(&self, path: &SwapPath) -> f64 {
+        let correction: f64 = path
+            .steps
+            .iter()
+            .filter_map(|step| self.protocols.get(&step.protocol))
+            .filter(|errors| errors.len() >= MIN_SAMPLES)
+            .map(median)
+            .sum();
+        correction.clamp(-MAX_CORRECTION, MAX_CORRECTION)
+    }
+
+    fn log_summary(&self) {
+        let mut protocols: Vec<String> = self
+            .protocols
+            .iter()
+            .map(|(protocol, errors)| {
+                format!("{:?} {:+.4} ({})", protocol, median(errors).exp() - 1.0, errors.len())
+            })
+            .collect();
+        protocols.sort();
+
+        let mut tokens: Vec<(&Address, f64, usize)> = self
+            .tokens
+            .iter()
+            .map(|(token, errors)| (token, median(errors), errors.len()))
+            .collect();
+        tokens.sort_by(|a, b| b.1.abs().total_cmp(&a.1.abs()));
+        let tokens: Vec<String> = tokens
+            .iter()
+            .take(SUMMARY_TOKENS)
+            .map(|(token, error, count)| format!("{} {:+.4} ({})", token, error.exp() - 1.0, count))
+            .collect();
+
+        info!(
+            "Estimate error after {} calculated paths. Per step by protocol: {}. Worst tokens: {}",
+            self.samples,
+            protocols.join(", "),
+            tokens.join(", ")
+        );
+    }
+}
+
+fn push(errors: &mut VecDeque<f64>, error: f64) {
+    if errors.len() == WINDOW {
+        errors.pop_front();
+    }
+    errors.push_back(error);
+}
+
+fn median(errors: &VecDeque<f64>) -> f64 {
+    let mut sorted: Vec<f64> = errors.iter().copied().collect();
+    sorted.sort_by(f64::total_cmp);
+    sorted.get(sorted.len() / 2).copied().unwrap_or_default()
+}
+
+#[cfg(test)]
+mod calibration_tests {
+    use super::*;
+    use crate::swap::SwapStep;
+
+    fn path(protocols: &[PoolType]) -> SwapPath {
+        let steps = protocols
+            .iter()
+            .enumerate()
+            .map(|(i, protocol)| SwapStep {
+                pool_address: Address::with_last_byte(i as u8),
+                token_in: Address::with_last_byte(10 + i as u8),
+                token_out: Address::with_last_byte(11 + i as u8),
+                protocol: *protocol,
+                fee: 0,
+            })
+            .collect();
+        SwapPath::new(steps)
+    }
+
+    // Estimates are left alone until a protocol has enough errors, then scaled by them
+    #[test]
+    fn test_correct() {
+        let mut calibration = Calibration::default();
+        let v2_path = path(&[PoolType::UniswapV2, PoolType::UniswapV2]);
+        let estimate = U256::from(1_000_000);
+
+        for _ in 0..MIN_SAMPLES - 1 {
+            calibration.record(&v2_path, estimate, U256::from(810_000));
+        }
+        assert_eq!(calibration.correct(&v2_path, estimate), estimate);
+
+        calibration.record(&v2_path, estimate, U256::from(810_000));
+        let corrected = calibration.correct(&v2_path, estimate);
+        assert!(corrected > U256::from(809_000) && corrected < U256::from(811_000));
+
+        // a path through an unseen protocol is only corrected for its known steps
+        let mixed = path(&[PoolType::UniswapV2, PoolType::UniswapV3]);
+        let corrected = calibration.correct(&mixed, estimate);
+        assert!(corrected > U256::from(899_000) && corrected < U256::from(901_000));
+    }
+
+    // Corrections are bounded and empty estimates are not recorded
+    #[test]
+    fn test_bounds() {
+        let mut calibration = Calibration::default();
+        let v2_path = path(&[PoolType::UniswapV2]);
+        assert!(calibration.record(&v2_path, U256::ZERO, U256::from(1)).is_none());
+        for _ in 0..MIN_SAMPLES {
+            calibration.record(&v2_path, U256::from(1), U256::from(1_000));
+        }
+        let corrected = calibration.correct(&v2_path, U256::from(1_000_000));
+        assert!(corrected < U256::from(2_100_000));
+    }
+}
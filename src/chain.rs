@@ -0,0 +1,186 @@
+use alloy::eips::eip1559::BaseFeeParams;
+use alloy::primitives::{address, Address, U256};
+use anyhow::{bail, Result};
+use pool_sync::{Chain, PoolType};
+use std::collections::HashMap;
+
+// How a router expects to be called when we test swap through it
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SwapType {
+    V2Basic,        // standard univ2 swap
+    V2Aerodrome,    // aerodrome swap
+    V3Basic,        // univ3 swap w/o deadline
+    V3Deadline,     // univ3 swap w/ deadline
+    V3DeadlineTick, // Slipstream v3 deadline and tick
+}
+
+// A router for a protocol along with its calling convention
+#[derive(Copy, Clone, Debug)]
+pub struct Router {
+    pub address: Address,
+    pub swap_type: SwapType,
+}
+
+// Everything about the pipeline that is specific to the chain we are running on
+#[derive(Clone, Debug)]
+pub struct ChainProfile {
+    // name the profile is selected by
+    pub name: String,
+    // chain used for pool syncing and token lists
+    pub chain: Chain,
+    // id used when signing transactions
+    pub chain_id: u64,
+    // wrapped native token
+    pub weth: Address,
+    // params used to predict the next base fee
+    pub base_fee_params: BaseFeeParams,
+    // endpoint signed transactions are submitted to
+    pub submission_url: String,
+    // protocols we sync pools for
+    pub pool_types: Vec<PoolType>,
+    // router used to test swap on each protocol
+    pub routers: HashMap<PoolType, Router>,
+    // v2 swap fee for each protocol, scaled by 10000
+    pub v2_fees: HashMap<PoolType, u32>,
+    // v2 factory => fee, scaled by 10000. Used by the quoter and swap contracts
+    pub v2_factories: Vec<(Address, u16)>,
+}
+
+// default v2 fee of 0.3%
+const DEFAULT_V2_FEE: u32 = 9970;
+
+impl ChainProfile {
+    // Select a profile by name
+    pub fn from_name(name: &str) -> Result<Self> {
+        match name.to_lowercase().as_str() {
+            "base" => Ok(Self::base()),
+            "ethereum" | "mainnet" => Ok(Self::ethereum()),
+            _ => bail!("Unknown chain profile {name}, expected one of: base, ethereum"),
+        }
+    }
+
+    // Get the router for a protocol if we support swapping through it on this chain
+    pub fn router(&self, pool_type: PoolType) -> Option<Router> {
+        self.routers.get(&pool_type).copied()
+    }
+
+    // Get the v2 swap fee for a protocol, scaled by 10000
+    pub fn v2_fee(&self, pool_type: PoolType) -> U256 {
+        U256::from(*self.v2_fees.get(&pool_type).unwrap_or(&DEFAULT_V2_FEE))
+    }
+
+    // Base mainnet
+    pub fn base() -> Self {
+        let routers = [
+            (PoolType::UniswapV2, address!("4752ba5dbc23f44d87826276bf6fd6b1c372ad24"), SwapType::V2Basic),
+            (PoolType::SushiSwapV2, address!("6BDED42c6DA8FBf0d2bA55B2fa120C5e0c8D7891"), SwapType::V2Basic),
+            (PoolType::PancakeSwapV2, address!("8cFe327CEc66d1C090Dd72bd0FF11d690C33a2Eb"), SwapType::V2Basic),
+            (PoolType::BaseSwapV2, address!("327Df1E6de05895d2ab08513aaDD9313Fe505d86"), SwapType::V2Basic),
+            (PoolType::SwapBasedV2, address!("aaa3b1F1bd7BCc97fD1917c18ADE665C5D31F066"), SwapType::V2Basic),
+            (PoolType::DackieSwapV2, address!("Ca4EAa32E7081b0c4Ba47e2bDF9B7163907Fe56f"), SwapType::V2Basic),
+            (PoolType::AlienBaseV2, address!("8c1A3cF8f83074169FE5D7aD50B978e1cD6b37c7"), SwapType::V2Basic),
+            (PoolType::UniswapV3, address!("2626664c2603336E57B271c5C0b26F421741e481"), SwapType::V3Basic),
+            (PoolType::AlienBaseV3, address!("B20C411FC84FBB27e78608C24d0056D974ea9411"), SwapType::V3Basic),
+            (PoolType::DackieSwapV3, address!("195FBc5B8Fbd5Ac739C1BA57D4Ef6D5a704F34f7"), SwapType::V3Basic),
+            (PoolType::PancakeSwapV3, address!("678Aa4bF4E210cf2166753e054d5b7c31cc7fa86"), SwapType::V3Basic),
+            (PoolType::SushiSwapV3, address!("FB7eF66a7e61224DD6FcD0D7d9C3be5C8B049b9f"), SwapType::V3Deadline),
+            (PoolType::SwapBasedV3, address!("756C6BbDd915202adac7beBB1c6C89aC0886503f"), SwapType::V3Deadline),
+            (PoolType::BaseSwapV3, address!("1B8eea9315bE495187D873DA7773a874545D9D48"), SwapType::V3Deadline),
+            (PoolType::Aerodrome, address!("cF77a3Ba9A5CA399B7c97c74d54e5b1Beb874E43"), SwapType::V2Aerodrome),
+            (PoolType::Slipstream, address!("BE6D8f0d05cC4be24d5167a3eF062215bE6D18a5"), SwapType::V3DeadlineTick),
+        ];
+
+        Self {
+            name: "base".to_string(),
+            chain: Chain::Base,
+            chain_id: 8453,
+            weth: address!("4200000000000000000000000000000000000006"),
+            base_fee_params: BaseFeeParams::optimism_canyon(),
+            submission_url: "https://mainnet-sequencer.base.org".to_string(),
+            pool_types: vec![
+                PoolType::UniswapV2,
+                PoolType::PancakeSwapV2,
+                PoolType::SushiSwapV2,
+                PoolType::UniswapV3,
+                PoolType::SushiSwapV3,
+                PoolType::BaseSwapV2,
+                PoolType::BaseSwapV3,
+                //PoolType::Aerodrome,
+                PoolType::Slipstream,
+                PoolType::AlienBaseV2,
+                PoolType::AlienBaseV3,
+            ],
+            routers: Self::router_map(&routers),
+            v2_fees: HashMap::from([
+                (PoolType::UniswapV2, 9970),
+                (PoolType::SushiSwapV2, 9970),
+                (PoolType::SwapBasedV2, 9970),
+                (PoolType::PancakeSwapV2, 9975),
+                (PoolType::BaseSwapV2, 9975),
+                (PoolType::DackieSwapV2, 9975),
+                (PoolType::AlienBaseV2, 9984),
+            ]),
+            v2_factories: vec![
+                (address!("8909Dc15e40173Ff4699343b6eB8132c65e18eC6"), 9970),
+                (address!("71524B4f93c58fcbF659783284E38825f0622859"), 9970),
+                (address!("02a84c1b3BBD7401a5f7fa98a384EBC70bB5749E"), 9975),
+                (address!("04C9f118d21e8B767D2e50C946f0cC9F6C367300"), 9970),
+                (address!("FDa619b6d20975be80A10332cD39b9a4b0FAa8BB"), 9975),
+                (address!("591f122D1df761E616c13d265006fcbf4c6d6551"), 9975),
+                (address!("3E84D913803b02A4a7f027165E8cA42C14C0FdE7"), 9984),
+            ],
+        }
+    }
+
+    // Ethereum mainnet
+    pub fn ethereum() -> Self {
+        let routers = [
+            (PoolType::UniswapV2, address!("7a250d5630B4cF539739dF2C5dAcb4c659F2488D"), SwapType::V2Basic),
+            (PoolType::SushiSwapV2, address!("d9e1cE17f2641f24aE83637ab66a2cca9C378B9F"), SwapType::V2Basic),
+            (PoolType::UniswapV3, address!("68b3465833fb72A70ecDF485E0e4C7bD8665Fc45"), SwapType::V3Basic),
+        ];
+
+        Self {
+            name: "ethereum".to_string(),
+            chain: Chain::Ethereum,
+            chain_id: 1,
+            weth: address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"),
+            base_fee_params: BaseFeeParams::ethereum(),
+            submission_url: "https://rpc.flashbots.net".to_string(),
+            pool_types: vec![PoolType::UniswapV2, PoolType::SushiSwapV2, PoolType::UniswapV3],
+            routers: Self::router_map(&routers),
+            v2_fees: HashMap::from([(PoolType::UniswapV2, 9970), (PoolType::SushiSwapV2, 9970)]),
+            v2_factories: vec![
+                (address!("5C69bEe5052E4A9Db2f0B1D4dC21b5E0F24a1B4e"), 9970),
+                (address!("C0AEe478e3658e2610c5F7A4A2E1777cE9e4f2Ac"), 9970),
+            ],
+        }
+    }
+
+    fn router_map(routers: &[(PoolType, Address, SwapType)]) -> HashMap<PoolType, Router> {
+        routers
+            .iter()
+            .map(|&(pool_type, address, swap_type)| (pool_type, Router { address, swap_type }))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod chain_tests {
+    use super::*;
+
+    // Profiles should be selectable by name and carry their own tables
+    #[test]
+    fn test_profile_selection() {
+        let base = ChainProfile::from_name("base").unwrap();
+        assert_eq!(base.chain_id, 8453);
+        assert_eq!(base.v2_fee(PoolType::AlienBaseV2), U256::from(9984));
+        assert!(base.router(PoolType::Slipstream).is_some());
+
+        let mainnet = ChainProfile::from_name("Mainnet").unwrap();
+        assert_eq!(mainnet.chain_id, 1);
+        assert!(mainnet.router(PoolType::Slipstream).is_none());
+
+        assert!(ChainProfile::from_name("solana").is_err());
+    }
+}
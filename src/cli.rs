@@ -0,0 +1,233 @@
+use alloy::primitives::{Address, U256};
+use anyhow::{anyhow, bail, Context, Result};
+use std::path::PathBuf;
+
+pub const USAGE: &str = "\
+usage: BaseBuster [config.toml | --config <path>] [command]
+
+commands:
+  run                   sync, filter, and search live blocks (default)
+  sync-pools            sync all pools and print counts by protocol
+  filter                print the filtered working set
+  cycles                print the generated swap paths as json
+  quote <pools...>      calculate and quote a path starting from a base token at head
+  trace-block <n>       print the tracked pools touched in block n
+  route <in> <out> <amount> [max hops]
+                        best route between two tokens at head, 3 hops by default
+  export <dir>          write the graph and rates as dot and json, and the path index
+  replay <journal dir>  replay a recorded journal offline";
+
+// What the binary was asked to do
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Command {
+    Run,
+    SyncPools,
+    Filter,
+    Cycles,
+    Quote(Vec<Address>),
+    TraceBlock(u64),
+    Route {
+        token_in: Address,
+        token_out: Address,
+        amount: U256,
+        max_hops: usize,
+    },
+    Export(PathBuf),
+    Replay(PathBuf),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Cli {
+    pub config_path: Option<PathBuf>,
+    pub command: Command,
+}
+
+impl Cli {
+    // Parse the arguments, not including the binary name
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self> {
+        let mut config_path = None;
+        let mut positional = Vec::new();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-c" | "--config" => {
+                    let path = args.next().ok_or_else(|| anyhow!("{arg} requires a path"))?;
+                    config_path = Some(PathBuf::from(path));
+                }
+                "-h" | "--help" => bail!("{USAGE}"),
+                _ => positional.push(arg),
+            }
+        }
+
+        // a leading config file is still accepted so `BaseBuster config.toml` runs as before
+        let mut positional = positional.into_iter().peekable();
+        if config_path.is_none() {
+            if let Some(path) = positional.next_if(|arg| arg.ends_with(".toml")) {
+                config_path = Some(PathBuf::from(path));
+            }
+        }
+
+        let command = match positional.next().as_deref() {
+            None | Some("run") => Command::Run,
+            Some("sync-pools") => Command::SyncPools,
+            Some("filter") => Command::Filter,
+            Some("cycles") => Command::Cycles,
+            Some("quote") => {
+                let pools = positional
+                    .by_ref()
+                    .map(|pool| pool.parse().with_context(|| format!("Invalid pool address {pool}")))
+                    .collect::<Result<Vec<Address>>>()?;
+                if pools.is_empty() {
+                    bail!("quote requires at least one pool\n\n{USAGE}");
+                }
+                Command::Quote(pools)
+            }
+            Some("trace-block") => {
+                let block = positional
+                    .next()
+                    .ok_or_else(|| anyhow!("trace-block requires a block number\n\n{USAGE}"))?;
+                Command::TraceBlock(
+                    block
+                        .parse()
+                        .with_context(|| format!("Invalid block number {block}"))?,
+                )
+            }
+            Some("route") => {
+                let mut next = |name: &str| {
+                    positional
+                        .next()
+                        .ok_or_else(|| anyhow!("route requires {name}\n\n{USAGE}"))
+                };
+                let token_in = next("a token in")?;
+                let token_out = next("a token out")?;
+                let amount = next("an amount")?;
+                let max_hops = match positional.next() {
+                    Some(hops) => hops
+                        .parse()
+                        .ok()
+                        .filter(|hops| (1..=5).contains(hops))
+                        .ok_or_else(|| anyhow!("Invalid max hops {hops}, must be between 1 and 5"))?,
+                    None => 3,
+                };
+                Command::Route {
+                    token_in: token_in
+                        .parse()
+                        .with_context(|| format!("Invalid token address {token_in}"))?,
+                    token_out: token_out
+                        .parse()
+                        .with_context(|| format!("Invalid token address {token_out}"))?,
+                    amount: amount
+                        .parse()
+                        .with_context(|| format!("Invalid amount {amount}"))?,
+                    max_hops,
+                }
+            }
+            Some("export") => {
+                let dir = positional
+                    .next()
+                    .ok_or_else(|| anyhow!("export requires an output directory\n\n{USAGE}"))?;
+                Command::Export(PathBuf::from(dir))
+            }
+            Some("replay") => {
+                let dir = positional
+                    .next()
+                    .ok_or_else(|| anyhow!("replay requires a journal directory\n\n{USAGE}"))?;
+                Command::Replay(PathBuf::from(dir))
+            }
+            Some(other) => bail!("Unknown command {other}\n\n{USAGE}"),
+        };
+
+        if let Some(extra) = positional.next() {
+            bail!("Unexpected argument {extra}\n\n{USAGE}");
+        }
+        Ok(Self {
+            config_path,
+            command,
+        })
+    }
+}
+
+#[cfg(test)]
+mod cli_tests {
+    use super::*;
+    use alloy::primitives::address;
+
+    fn parse(args: &[&str]) -> Result<Cli> {
+        Cli::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    // No command runs the bot, and a leading config file is still accepted
+    #[test]
+    fn test_default_run() {
+        assert_eq!(parse(&[]).unwrap().command, Command::Run);
+        let cli = parse(&["config.toml"]).unwrap();
+        assert_eq!(cli.config_path, Some(PathBuf::from("config.toml")));
+        assert_eq!(cli.command, Command::Run);
+    }
+
+    #[test]
+    fn test_commands() {
+        let cli = parse(&["--config", "bot.toml", "trace-block", "100"]).unwrap();
+        assert_eq!(cli.config_path, Some(PathBuf::from("bot.toml")));
+        assert_eq!(cli.command, Command::TraceBlock(100));
+
+        let cli = parse(&[
+            "config.toml",
+            "quote",
+            "0x4200000000000000000000000000000000000006",
+            "0xd8da6bf26964af9d7eed9e03e53415d37aa96045",
+        ])
+        .unwrap();
+        assert_eq!(
+            cli.command,
+            Command::Quote(vec![
+                address!("4200000000000000000000000000000000000006"),
+                address!("d8da6bf26964af9d7eed9e03e53415d37aa96045"),
+            ])
+        );
+        assert_eq!(
+            parse(&["replay", "journal"]).unwrap().command,
+            Command::Replay(PathBuf::from("journal"))
+        );
+        assert_eq!(
+            parse(&[
+                "route",
+                "0x4200000000000000000000000000000000000006",
+                "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913",
+                "1000000",
+            ])
+            .unwrap()
+            .command,
+            Command::Route {
+                token_in: address!("4200000000000000000000000000000000000006"),
+                token_out: address!("833589fCD6eDb6E08f4c7C32D4f71b54bdA02913"),
+                amount: U256::from(1_000_000),
+                max_hops: 3,
+            }
+        );
+        assert_eq!(
+            parse(&["export", "out"]).unwrap().command,
+            Command::Export(PathBuf::from("out"))
+        );
+    }
+
+    // Missing or malformed arguments are rejected
+    #[test]
+    fn test_invalid() {
+        assert!(parse(&["quote"]).is_err());
+        assert!(parse(&["quote", "not an address"]).is_err());
+        assert!(parse(&["trace-block"]).is_err());
+        assert!(parse(&["export"]).is_err());
+        assert!(parse(&["route", "0x4200000000000000000000000000000000000006"]).is_err());
+        assert!(parse(&[
+            "route",
+            "0x4200000000000000000000000000000000000006",
+            "0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913",
+            "1000",
+            "6",
+        ])
+        .is_err());
+        assert!(parse(&["cycles", "extra"]).is_err());
+        assert!(parse(&["unknown"]).is_err());
+    }
+}
@@ -0,0 +1,247 @@
+use alloy::primitives::{Address, U256};
+use alloy::providers::{Provider, ProviderBuilder};
+use alloy::rpc::types::BlockNumberOrTag;
+use anyhow::{anyhow, bail, Result};
+use log::info;
+use pool_sync::{Pool, PoolInfo, PoolSync};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fs;
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::calculation::Calculator;
+use crate::config::BotConfig;
+use crate::estimator::Estimator;
+use crate::export::{path_index_table, GraphExport};
+use crate::filter::filter_pools;
+use crate::graph::{pool_tokens, ArbGraph};
+use crate::market_state::MarketState;
+use crate::quoter::Quoter;
+use crate::router::Router;
+use crate::swap::{SwapPath, SwapStep};
+use crate::tracing::debug_trace_block;
+
+// Operational subcommands. Each one runs a single piece of the pipeline with the
+// same modules the bot uses and prints the result
+
+// Sync every pool for the chain, returning them with the block they are synced to
+pub async fn sync_pools(config: &BotConfig) -> Result<(Vec<Pool>, u64)> {
+    info!("Loading and syncing pools...");
+    let pool_sync = PoolSync::builder()
+        .add_pools(&config.chain.pool_types)
+        .chain(config.chain.chain)
+        .rate_limit(1000)
+        .build()?;
+    let (pools, last_synced_block) = pool_sync.sync_pools().await?;
+    Ok((pools, last_synced_block))
+}
+
+// Sync and filter the pools down to the working set
+pub async fn working_set(config: &BotConfig) -> Result<(Vec<Pool>, u64)> {
+    let (pools, last_synced_block) = sync_pools(config).await?;
+    let pools = filter_pools(pools, config.num_tokens, config).await;
+    Ok((pools, last_synced_block))
+}
+
+// sync-pools: pool counts by protocol
+pub async fn print_pool_counts(config: &BotConfig) -> Result<()> {
+    let (pools, last_synced_block) = sync_pools(config).await?;
+    let mut counts: BTreeMap<String, usize> = BTreeMap::new();
+    for pool in &pools {
+        *counts.entry(format!("{:?}", pool.pool_type())).or_default() += 1;
+    }
+    println!("Synced {} pools to block {}", pools.len(), last_synced_block);
+    for (pool_type, count) in counts {
+        println!("{pool_type:<24} {count}");
+    }
+    Ok(())
+}
+
+// filter: the working set, one pool per line
+pub async fn print_working_set(config: &BotConfig) -> Result<()> {
+    let (pools, _) = working_set(config).await?;
+    for pool in &pools {
+        print_pool(pool);
+    }
+    println!("{} pools in the working set", pools.len());
+    Ok(())
+}
+
+// cycles: every generated swap path as a line of json
+pub async fn print_cycles(config: &BotConfig) -> Result<()> {
+    let (pools, _) = working_set(config).await?;
+    let cycles = ArbGraph::generate_cycles(pools, config).await;
+    for cycle in &cycles {
+        println!("{}", serde_json::to_string(cycle)?);
+    }
+    info!("Generated {} cycles", cycles.len());
+    Ok(())
+}
+
+// quote: run the calculator and the quoter over a path at the head of the chain
+pub async fn quote(config: Arc<BotConfig>, pool_addrs: Vec<Address>) -> Result<()> {
+    let (pools, _) = sync_pools(&config).await?;
+    let mut pools_by_addr: HashMap<Address, Pool> =
+        pools.into_iter().map(|pool| (pool.address(), pool)).collect();
+    let mut path_pools = Vec::new();
+    for addr in &pool_addrs {
+        match pools_by_addr.remove(addr) {
+            Some(pool) => path_pools.push(pool),
+            None => bail!("Pool {addr} is not a synced pool"),
+        }
+    }
+    // the path can start in any of the bases
+    let path = config
+        .bases
+        .iter()
+        .find_map(|base| path_from_pools(&path_pools, base.token).ok())
+        .ok_or_else(|| anyhow!("Pools do not form a cycle through any base token"))?;
+
+    // load just the pools in the path at the head
+    let provider = ProviderBuilder::new().on_http(config.http_url.clone());
+    let head = provider.get_block_number().await?;
+    let market_state = MarketState::init_state(path_pools, head, provider, &config).await?;
+
+    let calculator = Calculator::new(market_state.clone(), config.clone());
+    println!("Block {head}, path hash {}", path.hash);
+    match calculator.debug_calculation(&path) {
+        Ok(calculated) => println!("Calculated: {:?}", calculated),
+        Err(e) => println!("Calculation failed: {e}"),
+    }
+    match Quoter::quote_path(path.to_quote_params(config.base_amount(&path.base_token())), market_state) {
+        Ok(quote) => println!("Quoted:     {:?}", quote),
+        Err(e) => println!("Quote failed: {e}"),
+    }
+    Ok(())
+}
+
+// trace-block: the tracked pools touched by a block
+pub async fn trace_block(config: &BotConfig, block_number: u64) -> Result<()> {
+    let (pools, _) = working_set(config).await?;
+    let tracked: HashMap<Address, Pool> =
+        pools.into_iter().map(|pool| (pool.address(), pool)).collect();
+
+    let provider = Arc::new(ProviderBuilder::new().on_http(config.http_url.clone()));
+    let updates = debug_trace_block(provider, BlockNumberOrTag::Number(block_number), true).await;
+    let touched: HashSet<Address> = updates
+        .iter()
+        .flat_map(|post| post.keys())
+        .filter(|address| tracked.contains_key(address))
+        .copied()
+        .collect();
+
+    for address in &touched {
+        print_pool(&tracked[address]);
+    }
+    println!("Block {block_number} touched {} tracked pools", touched.len());
+    Ok(())
+}
+
+// route: the best route between two tokens at the head of the chain as json
+pub async fn route(
+    config: Arc<BotConfig>,
+    token_in: Address,
+    token_out: Address,
+    amount: U256,
+    max_hops: usize,
+) -> Result<()> {
+    let (pools, _) = working_set(&config).await?;
+    let provider = ProviderBuilder::new().on_http(config.http_url.clone());
+    let head = provider.get_block_number().await?;
+    let market_state = MarketState::init_state(pools.clone(), head, provider, &config).await?;
+
+    // routing only needs the graph, so no start tokens and no cycles
+    let graph = ArbGraph::build(pools, Vec::new(), config.cycles.clone());
+    let calculator = Calculator::new(market_state, config.clone());
+    let route = Router::new(&graph, &calculator)
+        .best_route(token_in, token_out, amount, max_hops)
+        .ok_or_else(|| anyhow!("No route from {token_in} to {token_out} within {max_hops} hops"))?;
+    println!("Block {head}");
+    println!("{}", serde_json::to_string_pretty(&route)?);
+    Ok(())
+}
+
+// export: the graph with rates at head as dot and json, and the path index as a table
+pub async fn export(config: Arc<BotConfig>, dir: &Path) -> Result<()> {
+    let (pools, _) = working_set(&config).await?;
+    let provider = ProviderBuilder::new().on_http(config.http_url.clone());
+    let head = provider.get_block_number().await?;
+    let market_state = MarketState::init_state(pools.clone(), head, provider, &config).await?;
+    let mut estimator = Estimator::new(market_state.clone(), config.clone());
+    estimator.process_pools(pools.clone());
+    let graph = ArbGraph::new(pools, &config);
+
+    let export = GraphExport::new(
+        &graph,
+        |pool, token_in, token_out| {
+            estimator.rate(pool, token_in, token_out).zip(estimator.rate_hops(pool))
+        },
+        |token| estimator.token_value(token),
+        // the synced pools are older than head, read the state the rates were estimated on
+        |pool| {
+            let db = market_state.db.read().unwrap();
+            if pool.is_v2() {
+                let reserves = db.get_reserves(&pool.address()).ok();
+                let liquidity = reserves.map(|(reserve0, reserve1)| reserve0.saturating_mul(reserve1).root(2));
+                (reserves, liquidity)
+            } else if pool.is_v3() {
+                (None, db.liquidity(pool.address()).ok().map(U256::from))
+            } else {
+                (None, None)
+            }
+        },
+    );
+    fs::create_dir_all(dir)?;
+    fs::write(dir.join("graph.dot"), export.to_dot())?;
+    fs::write(dir.join("graph.json"), serde_json::to_vec_pretty(&export)?)?;
+    fs::write(dir.join("paths.tsv"), path_index_table(graph.cycles()))?;
+    println!(
+        "Exported {} tokens, {} pool directions and {} paths at block {head} to {}",
+        export.nodes.len(),
+        export.edges.len(),
+        graph.cycles().len(),
+        dir.display()
+    );
+    Ok(())
+}
+
+fn print_pool(pool: &Pool) {
+    println!(
+        "{} {:<24} {} {}",
+        pool.address(),
+        format!("{:?}", pool.pool_type()),
+        pool.token0_address(),
+        pool.token1_address()
+    );
+}
+
+// Walk the pools from the start token and back to form a swap path
+fn path_from_pools(pools: &[Pool], start: Address) -> Result<SwapPath> {
+    let mut token_in = start;
+    let mut steps = Vec::new();
+    for pool in pools {
+        // the pools alone do not say which token a swap through a larger pool comes out as
+        if matches!(pool, Pool::CurveTriCrypto(_)) || pool_tokens(pool).len() > 2 {
+            bail!("Quoting multi token pool {} is not supported", pool.address());
+        }
+        let token_out = if pool.token0_address() == token_in {
+            pool.token1_address()
+        } else if pool.token1_address() == token_in {
+            pool.token0_address()
+        } else {
+            bail!("Pool {} does not trade {}", pool.address(), token_in);
+        };
+        steps.push(SwapStep {
+            pool_address: pool.address(),
+            token_in,
+            token_out,
+            protocol: pool.pool_type(),
+            fee: pool.fee(),
+        });
+        token_in = token_out;
+    }
+    if token_in != start {
+        bail!("Path must end in {}, ends in {}", start, token_in);
+    }
+    Ok(SwapPath::new(steps))
+}
@@ -0,0 +1,686 @@
+use alloy::primitives::{Address, U256};
+use anyhow::{anyhow, bail, Context, Result};
+use reqwest::Url;
+use serde::Deserialize;
+use std::fs;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::chain::ChainProfile;
+
+// Validated configuration for a single bot instance. This is loaded once at startup
+// and handed to every component that needs it
+#[derive(Debug, Clone)]
+pub struct BotConfig {
+    // http endpoint of the full node, used for state fetching and tracing
+    pub http_url: Url,
+    // ipc path of the node, used to stream new blocks
+    pub ipc_path: String,
+    // path to the node database, used for filtering
+    pub db_path: String,
+    // hex encoded private key of the searcher
+    pub private_key: String,
+    // account the private key belongs to
+    pub account: Address,
+    // address of the deployed flash swap contract
+    pub swap_contract: Address,
+    // protocol tables and network parameters of the chain we run on
+    pub chain: ChainProfile,
+    // wrapped native token, used to build the working set and estimate rates
+    pub weth: Address,
+    // initial amount of weth we are trying to arb over
+    pub amount: U256,
+    // tokens our cycles start and end in, each with its own amount and threshold
+    pub bases: Vec<BaseToken>,
+    // if set, only simulate paths and never send transactions
+    pub simulate_only: bool,
+    // how the searcher finds candidate paths in a block
+    pub search_mode: SearchMode,
+    // split the legs of the best path across parallel pools for the same tokens
+    pub split_legs: bool,
+    // api key for the birdeye token list
+    pub birdeye_key: String,
+    // number of top volume tokens used to build the working set
+    pub num_tokens: usize,
+    // how often the working set is synced and filtered again while running, never if not set
+    pub refresh_interval: Option<Duration>,
+    // how workers are restarted and shut down
+    pub supervisor: SupervisorConfig,
+    // local address the health endpoint is served on
+    pub health_addr: SocketAddr,
+    // capacity of the channels between pipeline stages
+    pub channel_capacity: usize,
+    // where to journal events and state diffs, disabled if not set
+    pub journal: Option<JournalConfig>,
+    // how arbitrage cycles are enumerated from the pool graph
+    pub cycles: CycleConfig,
+}
+
+// Bounds on cycle enumeration
+#[derive(Debug, Clone)]
+pub struct CycleConfig {
+    // most pools a cycle can route through
+    pub max_hops: usize,
+    // pools with less liquidity than this are never routed through. For v2 pools this is
+    // sqrt(reserve0 * reserve1) so it is in the same units as v3 liquidity
+    pub min_liquidity: U256,
+    // most cycles any single pool can be part of, unlimited if not set
+    pub max_cycles_per_pool: Option<usize>,
+    // directory the generated cycles are saved to and reloaded from, disabled if not set
+    pub cache_dir: Option<PathBuf>,
+}
+
+// Where the searcher gets its candidate paths from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SearchMode {
+    // the enumerated cycles through the touched pools
+    Paths,
+    // negative cycles in the rate graph, found with spfa from the touched pools
+    NegativeCycle,
+    // both of the above
+    Both,
+}
+
+impl FromStr for SearchMode {
+    type Err = String;
+
+    fn from_str(mode: &str) -> Result<Self, Self::Err> {
+        match mode {
+            "paths" => Ok(Self::Paths),
+            "negative_cycle" => Ok(Self::NegativeCycle),
+            "both" => Ok(Self::Both),
+            _ => Err(format!(
+                "unknown search mode {mode}, expected one of: paths, negative_cycle, both"
+            )),
+        }
+    }
+}
+
+// A token cycles start and end in. The flash loan is taken in this token so its profit
+// is converted to eth for gas bidding and metrics
+#[derive(Debug, Clone)]
+pub struct BaseToken {
+    pub token: Address,
+    pub decimals: u8,
+    // initial amount we are trying to arb over
+    pub amount: U256,
+    // profit over the flash loan repayment a path must make, in token units
+    pub min_profit: U256,
+    // wei one whole token is worth
+    pub eth_value: U256,
+    // storage slot of the erc20 balance mapping, used to fund simulations
+    pub balance_slot: u64,
+}
+
+impl BaseToken {
+    // Output a path has to return to repay the flash loan and clear the profit threshold
+    pub fn min_output(&self) -> U256 {
+        let flash_loan_fee = (self.amount * U256::from(9)) / U256::from(10000);
+        self.amount + flash_loan_fee + self.min_profit
+    }
+
+    // Value of an amount of the token in wei
+    pub fn to_eth(&self, amount: U256) -> U256 {
+        amount.saturating_mul(self.eth_value) / U256::from(10).pow(U256::from(self.decimals))
+    }
+}
+
+// On disk event journal
+#[derive(Debug, Clone)]
+pub struct JournalConfig {
+    // directory journal files are written to
+    pub dir: PathBuf,
+    // size a file is allowed to grow to before we rotate to a new one
+    pub max_file_bytes: u64,
+    // number of files to keep, the oldest are removed first
+    pub max_files: usize,
+}
+
+// Restart and shutdown policy for the worker supervisor
+#[derive(Debug, Clone)]
+pub struct SupervisorConfig {
+    // how many times a restartable worker is restarted before the bot shuts down
+    pub max_restarts: u32,
+    // how long to wait before restarting a worker
+    pub restart_backoff: Duration,
+    // how long workers get to drain on shutdown before they are aborted
+    pub drain_timeout: Duration,
+}
+
+impl BotConfig {
+    // Load the config from a toml file, apply any environment overrides and validate it.
+    // If no path is given, the config is built purely from the environment
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let mut raw = match path {
+            Some(path) => {
+                let contents = fs::read_to_string(path)
+                    .with_context(|| format!("Failed to read config file {}", path.display()))?;
+                RawConfig::from_str(&contents)
+                    .with_context(|| format!("Failed to parse config file {}", path.display()))?
+            }
+            None => RawConfig::default(),
+        };
+        raw.apply_env_overrides()?;
+        raw.validate()
+    }
+
+    // Build the config only from environment variables
+    pub fn from_env() -> Result<Self> {
+        Self::load(None)
+    }
+
+    // The base a path starting in this token belongs to
+    pub fn base(&self, token: &Address) -> Option<&BaseToken> {
+        self.bases.iter().find(|base| base.token == *token)
+    }
+
+    // Input amount for a path starting in this token, the weth amount if it is not a base
+    pub fn base_amount(&self, token: &Address) -> U256 {
+        self.base(token).map_or(self.amount, |base| base.amount)
+    }
+}
+
+// Raw representation of the config file. Everything is optional so that values
+// can be filled in from the environment before validation
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawConfig {
+    chain: RawChainConfig,
+    rpc: RawRpcConfig,
+    wallet: RawWalletConfig,
+    strategy: RawStrategyConfig,
+    filter: RawFilterConfig,
+    supervisor: RawSupervisorConfig,
+    health: RawHealthConfig,
+    pipeline: RawPipelineConfig,
+    journal: RawJournalConfig,
+    cycles: RawCycleConfig,
+    bases: Vec<RawBaseConfig>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawChainConfig {
+    profile: Option<String>,
+    chain_id: Option<u64>,
+    submission_url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawRpcConfig {
+    http_url: Option<String>,
+    ipc_path: Option<String>,
+    db_path: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawWalletConfig {
+    private_key: Option<String>,
+    account: Option<String>,
+    swap_contract: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawStrategyConfig {
+    weth: Option<String>,
+    amount: Option<String>,
+    simulate_only: Option<bool>,
+    search_mode: Option<String>,
+    split_legs: Option<bool>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawFilterConfig {
+    birdeye_key: Option<String>,
+    num_tokens: Option<usize>,
+    refresh_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawSupervisorConfig {
+    max_restarts: Option<u32>,
+    restart_backoff_ms: Option<u64>,
+    drain_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawHealthConfig {
+    listen_addr: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawPipelineConfig {
+    channel_capacity: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawJournalConfig {
+    dir: Option<String>,
+    max_file_mb: Option<u64>,
+    max_files: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawCycleConfig {
+    max_hops: Option<usize>,
+    min_liquidity: Option<String>,
+    max_cycles_per_pool: Option<usize>,
+    cache_dir: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct RawBaseConfig {
+    token: Option<String>,
+    decimals: Option<u8>,
+    amount: Option<String>,
+    min_profit: Option<String>,
+    eth_value: Option<String>,
+    balance_slot: Option<u64>,
+}
+
+impl FromStr for RawConfig {
+    type Err = toml::de::Error;
+
+    fn from_str(contents: &str) -> Result<Self, Self::Err> {
+        toml::from_str(contents)
+    }
+}
+
+impl RawConfig {
+    // Environment variables take precedence over values in the file
+    fn apply_env_overrides(&mut self) -> Result<()> {
+        env_string("CHAIN", &mut self.chain.profile);
+        env_string("SUBMISSION_URL", &mut self.chain.submission_url);
+        env_string("FULL", &mut self.rpc.http_url);
+        env_string("IPC", &mut self.rpc.ipc_path);
+        env_string("DB_PATH", &mut self.rpc.db_path);
+        env_string("PRIVATE_KEY", &mut self.wallet.private_key);
+        env_string("ACCOUNT", &mut self.wallet.account);
+        env_string("SWAP_CONTRACT", &mut self.wallet.swap_contract);
+        env_string("WETH", &mut self.strategy.weth);
+        env_string("AMOUNT", &mut self.strategy.amount);
+        env_string("BIRDEYE_KEY", &mut self.filter.birdeye_key);
+        env_string("HEALTH_ADDR", &mut self.health.listen_addr);
+        env_string("JOURNAL_DIR", &mut self.journal.dir);
+
+        if let Ok(sim) = std::env::var("SIM") {
+            let sim = sim
+                .parse()
+                .map_err(|_| anyhow!("Invalid value for SIM: expected true or false, got {sim}"))?;
+            self.strategy.simulate_only = Some(sim);
+        }
+        Ok(())
+    }
+
+    // Make sure all required values are present and well formed
+    fn validate(self) -> Result<BotConfig> {
+        let mut missing: Vec<&str> = Vec::new();
+        let http_url = required(self.rpc.http_url, "rpc.http_url (FULL)", &mut missing);
+        let ipc_path = required(self.rpc.ipc_path, "rpc.ipc_path (IPC)", &mut missing);
+        let db_path = required(self.rpc.db_path, "rpc.db_path (DB_PATH)", &mut missing);
+        let private_key =
+            required(self.wallet.private_key, "wallet.private_key (PRIVATE_KEY)", &mut missing);
+        let account = required(self.wallet.account, "wallet.account (ACCOUNT)", &mut missing);
+        let swap_contract = required(
+            self.wallet.swap_contract,
+            "wallet.swap_contract (SWAP_CONTRACT)",
+            &mut missing,
+        );
+        let birdeye_key =
+            required(self.filter.birdeye_key, "filter.birdeye_key (BIRDEYE_KEY)", &mut missing);
+        if !missing.is_empty() {
+            bail!("Missing required config values: {}", missing.join(", "));
+        }
+
+        // select the chain profile, defaulting to base, and apply any overrides
+        let mut chain = ChainProfile::from_name(self.chain.profile.as_deref().unwrap_or("base"))?;
+        if let Some(chain_id) = self.chain.chain_id {
+            chain.chain_id = chain_id;
+        }
+        if let Some(submission_url) = self.chain.submission_url {
+            parse_value::<Url>(&submission_url, "chain.submission_url")?;
+            chain.submission_url = submission_url;
+        }
+        if let Some(weth) = self.strategy.weth {
+            chain.weth = parse_value(&weth, "strategy.weth")?;
+        }
+
+        // the private key must decode to a 32 byte secret
+        let key_hex = alloy::hex::decode(&private_key)
+            .map_err(|_| anyhow!("Invalid wallet.private_key: not valid hex"))?;
+        if key_hex.len() != 32 {
+            bail!("Invalid wallet.private_key: expected 32 bytes, got {}", key_hex.len());
+        }
+
+        let amount = match self.strategy.amount {
+            Some(amount) => parse_value::<U256>(&amount, "strategy.amount")?,
+            None => U256::from(1e15),
+        };
+        if amount.is_zero() {
+            bail!("Invalid strategy.amount: must be greater than zero");
+        }
+
+        // without any configured bases, cycles start in weth as they always have
+        let bases = if self.bases.is_empty() {
+            vec![BaseToken {
+                token: chain.weth,
+                decimals: 18,
+                amount,
+                min_profit: amount / U256::from(100),
+                eth_value: U256::from(1e18),
+                balance_slot: 3,
+            }]
+        } else {
+            let mut bases: Vec<BaseToken> = Vec::new();
+            for (i, raw) in self.bases.into_iter().enumerate() {
+                let base = raw.validate(i, chain.weth)?;
+                if bases.iter().any(|b| b.token == base.token) {
+                    bail!("Invalid bases[{i}].token: {} is listed twice", base.token);
+                }
+                bases.push(base);
+            }
+            bases
+        };
+
+        let search_mode = match self.strategy.search_mode {
+            Some(mode) => parse_value(&mode, "strategy.search_mode")?,
+            None => SearchMode::Paths,
+        };
+
+        let num_tokens = self.filter.num_tokens.unwrap_or(4000);
+        if num_tokens == 0 {
+            bail!("Invalid filter.num_tokens: must be greater than zero");
+        }
+        if self.filter.refresh_secs == Some(0) {
+            bail!("Invalid filter.refresh_secs: must be greater than zero");
+        }
+
+        let supervisor = SupervisorConfig {
+            max_restarts: self.supervisor.max_restarts.unwrap_or(5),
+            restart_backoff: Duration::from_millis(self.supervisor.restart_backoff_ms.unwrap_or(1000)),
+            drain_timeout: Duration::from_secs(self.supervisor.drain_timeout_secs.unwrap_or(30)),
+        };
+
+        let health_addr = parse_value(
+            self.health.listen_addr.as_deref().unwrap_or("127.0.0.1:9100"),
+            "health.listen_addr",
+        )?;
+
+        let channel_capacity = self.pipeline.channel_capacity.unwrap_or(64);
+        if channel_capacity == 0 {
+            bail!("Invalid pipeline.channel_capacity: must be greater than zero");
+        }
+
+        let journal = match self.journal.dir {
+            Some(dir) if !dir.trim().is_empty() => {
+                let max_files = self.journal.max_files.unwrap_or(48);
+                if max_files == 0 {
+                    bail!("Invalid journal.max_files: must be greater than zero");
+                }
+                Some(JournalConfig {
+                    dir: PathBuf::from(dir),
+                    max_file_bytes: self.journal.max_file_mb.unwrap_or(64) * 1024 * 1024,
+                    max_files,
+                })
+            }
+            _ => None,
+        };
+
+        // two hops keeps the original behaviour, past five the cycle count explodes
+        let max_hops = self.cycles.max_hops.unwrap_or(2);
+        if !(2..=5).contains(&max_hops) {
+            bail!("Invalid cycles.max_hops: must be between 2 and 5, got {max_hops}");
+        }
+        let min_liquidity = match self.cycles.min_liquidity {
+            Some(min_liquidity) => parse_value(&min_liquidity, "cycles.min_liquidity")?,
+            None => U256::ZERO,
+        };
+        if self.cycles.max_cycles_per_pool == Some(0) {
+            bail!("Invalid cycles.max_cycles_per_pool: must be greater than zero");
+        }
+        let cycles = CycleConfig {
+            max_hops,
+            min_liquidity,
+            max_cycles_per_pool: self.cycles.max_cycles_per_pool,
+            cache_dir: self
+                .cycles
+                .cache_dir
+                .filter(|dir| !dir.trim().is_empty())
+                .map(PathBuf::from),
+        };
+
+        Ok(BotConfig {
+            http_url: parse_value(&http_url, "rpc.http_url")?,
+            ipc_path,
+            db_path,
+            private_key,
+            account: parse_value(&account, "wallet.account")?,
+            swap_contract: parse_value(&swap_contract, "wallet.swap_contract")?,
+            weth: chain.weth,
+            chain,
+            amount,
+            bases,
+            simulate_only: self.strategy.simulate_only.unwrap_or(false),
+            search_mode,
+            split_legs: self.strategy.split_legs.unwrap_or(false),
+            birdeye_key,
+            num_tokens,
+            refresh_interval: self.filter.refresh_secs.map(Duration::from_secs),
+            supervisor,
+            health_addr,
+            channel_capacity,
+            journal,
+            cycles,
+        })
+    }
+}
+
+impl RawBaseConfig {
+    fn validate(self, i: usize, weth: Address) -> Result<BaseToken> {
+        let token: Address = match self.token {
+            Some(token) => parse_value(&token, &format!("bases[{i}].token"))?,
+            None => bail!("Missing required config value bases[{i}].token"),
+        };
+        let amount: U256 = match self.amount {
+            Some(amount) => parse_value(&amount, &format!("bases[{i}].amount"))?,
+            None => bail!("Missing required config value bases[{i}].amount"),
+        };
+        if amount.is_zero() {
+            bail!("Invalid bases[{i}].amount: must be greater than zero");
+        }
+        let min_profit = match self.min_profit {
+            Some(min_profit) => parse_value(&min_profit, &format!("bases[{i}].min_profit"))?,
+            None => amount / U256::from(100),
+        };
+
+        // weth is already worth eth, anything else has to say what it is worth
+        let eth_value = match self.eth_value {
+            Some(eth_value) => parse_value(&eth_value, &format!("bases[{i}].eth_value"))?,
+            None if token == weth => U256::from(1e18),
+            None => bail!("Missing required config value bases[{i}].eth_value"),
+        };
+
+        Ok(BaseToken {
+            token,
+            decimals: self.decimals.unwrap_or(18),
+            amount,
+            min_profit,
+            eth_value,
+            balance_slot: self.balance_slot.unwrap_or(3),
+        })
+    }
+}
+
+// Overwrite the value if the environment variable is set
+fn env_string(var: &str, value: &mut Option<String>) {
+    if let Ok(env_value) = std::env::var(var) {
+        *value = Some(env_value);
+    }
+}
+
+// Take a required value, recording its key if it is missing
+fn required<'a>(value: Option<String>, key: &'a str, missing: &mut Vec<&'a str>) -> String {
+    match value {
+        Some(value) if !value.trim().is_empty() => value,
+        _ => {
+            missing.push(key);
+            String::new()
+        }
+    }
+}
+
+// Parse a value, naming the offending key on failure
+fn parse_value<V: FromStr>(value: &str, key: &str) -> Result<V>
+where
+    V::Err: std::fmt::Display,
+{
+    value
+        .trim()
+        .parse()
+        .map_err(|e| anyhow!("Invalid value for {key}: {e}"))
+}
+
+#[cfg(test)]
+mod config_tests {
+    use super::*;
+    use alloy::primitives::address;
+
+    const FULL_CONFIG: &str = r#"
+        [rpc]
+        http_url = "http://localhost:8545"
+        ipc_path = "/tmp/node.ipc"
+        db_path = "/data/node"
+
+        [wallet]
+        private_key = "ac0974bec39a17e36ba4a6b4d238ff944bacb478cbed5efcae784d7bf4f2ff80"
+        account = "0xf39Fd6e51aad88F6F4ce6aB8827279cffFb92266"
+        swap_contract = "0x0000000000000000000000000000000000000001"
+
+        [strategy]
+        weth = "0x4200000000000000000000000000000000000006"
+        amount = "2000000000000000"
+        simulate_only = true
+
+        [filter]
+        birdeye_key = "key"
+    "#;
+
+    // A complete config should validate into typed values
+    #[test]
+    fn test_valid_config() {
+        let config = RawConfig::from_str(FULL_CONFIG).unwrap().validate().unwrap();
+        assert_eq!(config.weth, address!("4200000000000000000000000000000000000006"));
+        assert_eq!(config.amount, U256::from(2e15));
+        assert!(config.simulate_only);
+        assert_eq!(config.search_mode, SearchMode::Paths);
+        assert!(!config.split_legs);
+        assert_eq!(config.num_tokens, 4000);
+        assert!(config.refresh_interval.is_none());
+        assert_eq!(config.supervisor.max_restarts, 5);
+        assert_eq!(config.supervisor.drain_timeout, Duration::from_secs(30));
+        assert_eq!(config.health_addr, "127.0.0.1:9100".parse().unwrap());
+        assert!(config.journal.is_none());
+        assert_eq!(config.cycles.max_hops, 2);
+        assert_eq!(config.bases.len(), 1);
+        assert_eq!(config.bases[0].token, config.weth);
+        assert_eq!(config.bases[0].amount, config.amount);
+        assert!(config.cycles.max_cycles_per_pool.is_none());
+        assert!(config.cycles.cache_dir.is_none());
+    }
+
+    // All missing values should be reported at once
+    #[test]
+    fn test_missing_values() {
+        let err = RawConfig::default().validate().unwrap_err().to_string();
+        assert!(err.contains("rpc.http_url (FULL)"));
+        assert!(err.contains("wallet.private_key (PRIVATE_KEY)"));
+        assert!(err.contains("filter.birdeye_key (BIRDEYE_KEY)"));
+    }
+
+    // The chain profile decides the defaults, the file can override them
+    #[test]
+    fn test_chain_profile() {
+        let config = RawConfig::from_str(FULL_CONFIG).unwrap().validate().unwrap();
+        assert_eq!(config.chain.chain_id, 8453);
+
+        let mainnet = FULL_CONFIG
+            .replace("weth = \"0x4200000000000000000000000000000000000006\"", "")
+            .replace("[rpc]", "[chain]\nprofile = \"ethereum\"\nsubmission_url = \"http://localhost:9000\"\n\n[rpc]");
+        let config = RawConfig::from_str(&mainnet).unwrap().validate().unwrap();
+        assert_eq!(config.chain.chain_id, 1);
+        assert_eq!(config.weth, address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"));
+        assert_eq!(config.chain.submission_url, "http://localhost:9000");
+    }
+
+    // Each base has its own amount and threshold, profit is converted to eth
+    #[test]
+    fn test_bases() {
+        let config = format!(
+            "{FULL_CONFIG}
+            [[bases]]
+            token = \"0x4200000000000000000000000000000000000006\"
+            amount = \"1000000000000000\"
+
+            [[bases]]
+            token = \"0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913\"
+            decimals = 6
+            amount = \"5000000\"
+            min_profit = \"10000\"
+            eth_value = \"400000000000000\"
+            balance_slot = 9
+            "
+        );
+        let config = RawConfig::from_str(&config).unwrap().validate().unwrap();
+        assert_eq!(config.bases.len(), 2);
+        assert_eq!(config.bases[0].eth_value, U256::from(1e18));
+        assert_eq!(config.bases[0].min_profit, U256::from(1e13));
+
+        let usdc = config.base(&address!("833589fCD6eDb6E08f4c7C32D4f71b54bdA02913")).unwrap();
+        assert_eq!(usdc.min_output(), U256::from(5_000_000 + 4_500 + 10_000));
+        // 2.5 usdc at 0.0004 eth each
+        assert_eq!(usdc.to_eth(U256::from(2_500_000)), U256::from(1e15));
+
+        // anything other than weth needs an eth value
+        let missing = format!(
+            "{FULL_CONFIG}
+            [[bases]]
+            token = \"0x833589fCD6eDb6E08f4c7C32D4f71b54bdA02913\"
+            amount = \"5000000\"
+            "
+        );
+        let err = RawConfig::from_str(&missing).unwrap().validate().unwrap_err().to_string();
+        assert!(err.contains("bases[0].eth_value"));
+    }
+
+    // Malformed values should name the key they came from
+    #[test]
+    fn test_invalid_values() {
+        let config = FULL_CONFIG.replace("0x4200000000000000000000000000000000000006", "not_an_address");
+        let err = RawConfig::from_str(&config).unwrap().validate().unwrap_err().to_string();
+        assert!(err.contains("strategy.weth"));
+
+        let config = FULL_CONFIG.replace("amount = \"2000000000000000\"", "amount = \"0\"");
+        let err = RawConfig::from_str(&config).unwrap().validate().unwrap_err().to_string();
+        assert!(err.contains("strategy.amount"));
+
+        let config = FULL_CONFIG.replace("simulate_only = true", "search_mode = \"dijkstra\"");
+        let err = RawConfig::from_str(&config).unwrap().validate().unwrap_err().to_string();
+        assert!(err.contains("strategy.search_mode"));
+
+        let config = format!("{FULL_CONFIG}\n[cycles]\nmax_hops = 6");
+        let err = RawConfig::from_str(&config).unwrap().validate().unwrap_err().to_string();
+        assert!(err.contains("cycles.max_hops"));
+    }
+}
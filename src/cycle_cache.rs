@@ -0,0 +1,168 @@
+use alloy::primitives::{keccak256, Address, B256};
+use anyhow::{Context, Result};
+use log::warn;
+use pool_sync::{Pool, PoolInfo};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::CycleConfig;
+use crate::graph::pool_tokens;
+use crate::swap::{protocol_id, SwapPath, SwapStep};
+
+// Bumped whenever the cached format or how cycles are generated changes
+const CACHE_VERSION: u8 = 2;
+const CACHE_FILE: &str = "cycles.json";
+
+// The generated cycles saved to disk. The file holds a key hashed from everything cycle
+// generation depends on, so a changed working set or hop config regenerates instead
+pub struct CycleCache {
+    path: PathBuf,
+    key: B256,
+}
+
+#[derive(Serialize, Deserialize)]
+struct CachedCycles {
+    key: B256,
+    cycles: Vec<Vec<SwapStep>>,
+}
+
+impl CycleCache {
+    pub fn new(dir: &Path, pools: &[Pool], start_tokens: &[Address], config: &CycleConfig) -> Self {
+        Self {
+            path: dir.join(CACHE_FILE),
+            key: cache_key(pools, start_tokens, config),
+        }
+    }
+
+    pub fn key(&self) -> B256 {
+        self.key
+    }
+
+    // The cached cycles if they were generated from the same inputs
+    pub fn load(&self) -> Option<Vec<SwapPath>> {
+        let contents = fs::read(&self.path).ok()?;
+        let cached: CachedCycles = match serde_json::from_slice(&contents) {
+            Ok(cached) => cached,
+            Err(e) => {
+                warn!("Ignoring unreadable cycle cache {}: {}", self.path.display(), e);
+                return None;
+            }
+        };
+        if cached.key != self.key {
+            return None;
+        }
+        Some(cached.cycles.into_iter().map(SwapPath::new).collect())
+    }
+
+    // Replace the cache with these cycles. Written to a temporary file first so a crash
+    // never leaves a partial cache behind
+    pub fn save(&self, cycles: &[SwapPath]) -> Result<()> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create {}", dir.display()))?;
+        }
+        let cached = CachedCycles {
+            key: self.key,
+            cycles: cycles.iter().map(|path| path.steps.clone()).collect(),
+        };
+        let tmp = self.path.with_extension("json.tmp");
+        fs::write(&tmp, serde_json::to_vec(&cached)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        fs::rename(&tmp, &self.path)
+            .with_context(|| format!("Failed to write {}", self.path.display()))?;
+        Ok(())
+    }
+}
+
+// Keccak over the pools sorted by address with the tokens they connect, the start tokens
+// and the hop config. Reserves are left out, they change every block, so a cache built with
+// min_liquidity or a per pool cap keeps the pruning from when it was generated
+pub fn cache_key(pools: &[Pool], start_tokens: &[Address], config: &CycleConfig) -> B256 {
+    let mut pools: Vec<&Pool> = pools.iter().collect();
+    pools.sort_by_key(|pool| pool.address());
+
+    let mut packed = vec![CACHE_VERSION];
+    for pool in pools {
+        packed.extend_from_slice(pool.address().as_slice());
+        packed.push(protocol_id(pool.pool_type()));
+        packed.extend_from_slice(&pool.fee().to_be_bytes());
+        let tokens = pool_tokens(pool);
+        packed.push(tokens.len() as u8);
+        for token in tokens {
+            packed.extend_from_slice(token.as_slice());
+        }
+    }
+    packed.push(start_tokens.len() as u8);
+    for token in start_tokens {
+        packed.extend_from_slice(token.as_slice());
+    }
+    packed.extend_from_slice(&(config.max_hops as u64).to_be_bytes());
+    packed.extend_from_slice(&config.min_liquidity.to_be_bytes::<32>());
+    let cap = config.max_cycles_per_pool.unwrap_or_default();
+    packed.extend_from_slice(&(cap as u64).to_be_bytes());
+    keccak256(&packed)
+}
+
+#[cfg(test)]
+mod cycle_cache_tests {
+    use super::*;
+    use alloy::primitives::U256;
+    use pool_sync::UniswapV2Pool;
+    use std::time::{SystemTime, UNIX_EPOCH};
+
+    use crate::graph::ArbGraph;
+    use crate::tests::helpers::fixtures::pools::{weth_usdc, USDC, WETH};
+
+    fn pool(id: u8, protocol: fn(UniswapV2Pool) -> Pool) -> Pool {
+        weth_usdc(Address::with_last_byte(id), protocol, (1_000_000, 1_000_000))
+    }
+
+    fn pools() -> Vec<Pool> {
+        vec![pool(1, Pool::UniswapV2), pool(2, Pool::SushiSwapV2)]
+    }
+
+    fn config(max_hops: usize, cache_dir: Option<PathBuf>) -> CycleConfig {
+        CycleConfig {
+            max_hops,
+            min_liquidity: U256::ZERO,
+            max_cycles_per_pool: None,
+            cache_dir,
+        }
+    }
+
+    fn temp_dir() -> PathBuf {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap().as_nanos();
+        std::env::temp_dir().join(format!("cycle_cache_{nanos}"))
+    }
+
+    // The key ignores pool order and the cache dir, but not the pools or hop config
+    #[test]
+    fn test_cache_key() {
+        let key = cache_key(&pools(), &[WETH], &config(2, None));
+        let mut reversed = pools();
+        reversed.reverse();
+        assert_eq!(key, cache_key(&reversed, &[WETH], &config(2, Some(temp_dir()))));
+
+        assert_ne!(key, cache_key(&pools()[..1], &[WETH], &config(2, None)));
+        assert_ne!(key, cache_key(&pools(), &[WETH], &config(3, None)));
+        assert_ne!(key, cache_key(&pools(), &[WETH, USDC], &config(2, None)));
+    }
+
+    // Saved cycles reload with the same hashes, and only for the same key
+    #[test]
+    fn test_save_load() {
+        let dir = temp_dir();
+        let cycles = ArbGraph::build(pools(), vec![WETH], config(2, None)).cycles().to_vec();
+        assert_eq!(cycles.len(), 2);
+
+        let cache = CycleCache::new(&dir, &pools(), &[WETH], &config(2, None));
+        assert!(cache.load().is_none());
+        cache.save(&cycles).unwrap();
+        assert_eq!(cache.load().unwrap(), cycles);
+
+        let changed = CycleCache::new(&dir, &pools(), &[WETH], &config(3, None));
+        assert!(changed.load().is_none());
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
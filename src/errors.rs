@@ -0,0 +1,62 @@
+use alloy::primitives::{Address, Bytes, B256, U256};
+use alloy::transports::TransportError;
+use pool_sync::PoolType;
+use thiserror::Error;
+
+// Errors reading or writing our view of chain state
+#[derive(Debug, Error)]
+pub enum StateError {
+    #[error("provider request failed: {0}")]
+    Transport(#[from] TransportError),
+    #[error("state provider failed: {0}")]
+    Provider(String),
+    #[error("pool {0} is not tracked")]
+    UntrackedPool(Address),
+    #[error("account {0} could not be fetched")]
+    MissingAccount(Address),
+    #[error("slot {slot} of {address} is not loaded")]
+    MissingSlot { address: Address, slot: U256 },
+    #[error("code {0} is not loaded")]
+    MissingCode(B256),
+    #[error("pool {0} has unexpected state: {1}")]
+    InvalidPool(Address, String),
+    #[error("view call to {0} reverted")]
+    CallReverted(Address),
+}
+
+// Errors computing a swap output offchain
+#[derive(Debug, Error)]
+pub enum CalculationError {
+    #[error(transparent)]
+    State(#[from] StateError),
+    #[error("{0:?} pools are not supported")]
+    UnsupportedProtocol(PoolType),
+    #[error("math error in pool {0}: {1}")]
+    Math(Address, String),
+}
+
+// Errors simulating a path against the quoter contract
+#[derive(Debug, Error)]
+pub enum SimulationError {
+    #[error("evm error: {0}")]
+    Evm(String),
+    #[error("simulation reverted: {0}")]
+    Revert(Bytes),
+    #[error("simulation halted: {0}")]
+    Halt(String),
+    #[error("failed to decode quoter output")]
+    Decode,
+}
+
+// Errors submitting a transaction to the sequencer
+#[derive(Debug, Error)]
+pub enum SubmissionError {
+    #[error("failed to sign transaction: {0}")]
+    Signing(String),
+    #[error("request failed: {0}")]
+    Request(String),
+    #[error("invalid response: {0}")]
+    InvalidResponse(String),
+    #[error("transaction rejected: {0}")]
+    Rejected(String),
+}
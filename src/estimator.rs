@@ -4,20 +4,28 @@ use alloy::providers::Provider;
 use alloy::transports::Transport;
 use lazy_static::lazy_static;
 use pool_sync::{Pool, PoolInfo};
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::Ordering;
 use std::sync::Arc;
 use log::debug;
 
 use crate::calculation::Calculator;
+use crate::config::{BaseToken, BotConfig};
+use crate::graph::pool_tokens;
 use crate::market_state::MarketState;
-use crate::swap::SwapPath;
-use crate::AMOUNT;
+use crate::swap::{SwapPath, SPLIT_PRECISION};
 
 // Calculation constants
 const RATE_SCALE: u32 = 18; // 18 decimals for rate precision
 lazy_static! {
     pub static ref RATE_SCALE_VALUE: U256 = U256::from(1e18);
 }
+// Sizes every pool direction is quoted at, in basis points of the amount of the input token
+// worth our input. The rates between them trace out the price impact
+const CURVE_SIZES: [u64; 4] = [2_500, 10_000, 30_000, 100_000];
+const REFERENCE_SIZE: u64 = 10_000;
+// Larger inputs tried when sizing a trade, in fifths of the base amount like the quoter
+const INPUT_STEPS: u64 = 50;
 
 // Handles initial estimation of path profitability before moving onto
 // precise calculations and simulation
@@ -27,18 +35,26 @@ where
     N: Network,
     P: Provider<T, N>,
 {
-    // Mapping from pool address => token => rate
-    rates: HashMap<Address, HashMap<Address, U256>>,
-    // Tracks if a pool is based in weth
-    weth_based: HashMap<Address, bool>,
+    // Id of every pool direction by (pool, token in, token out), indexing the flat arrays below
+    directions: HashMap<(Address, Address, Address), usize>,
+    // ln of the rate at the reference size per direction, -inf until it is rated
+    log_rates: Vec<f64>,
+    // ln rates by input size per direction
+    curves: Vec<RateCurve>,
+    // Ids of the directions of removed pools, handed out again before the arrays grow
+    free_ids: Vec<usize>,
+    // Pools between each rated pool and a base
+    rate_hops: HashMap<Address, usize>,
     // Reference to the market_state
     market_state: Arc<MarketState<T, N, P>>,
     // Calculator to calculate the outputs of swaps
     calculator: Calculator<T, N, P>,
-    // Maps from a quote token to its aggregated weth rate
-    aggregated_weth_rate: HashMap<Address, U256>,
+    // Amount of every token worth our input and the pools it is priced from
+    prices: TokenPrices,
     // Decimals in token
     token_decimals: HashMap<Address, u32>,
+    // Bot configuration, holds weth and our input amount
+    config: Arc<BotConfig>,
 }
 
 impl<T, N, P> Estimator<T, N, P>
@@ -48,14 +64,18 @@ where
     P: Provider<T, N>,
 {
     // Construct a new estimator
-    pub fn new(market_state: Arc<MarketState<T, N, P>>) -> Self {
+    pub fn new(market_state: Arc<MarketState<T, N, P>>, config: Arc<BotConfig>) -> Self {
         Self {
-            rates: HashMap::new(),
-            weth_based: HashMap::new(),
+            directions: HashMap::new(),
+            log_rates: Vec::new(),
+            curves: Vec::new(),
+            free_ids: Vec::new(),
+            rate_hops: HashMap::new(),
             market_state: market_state.clone(),
-            calculator: Calculator::new(market_state.clone()),
-            aggregated_weth_rate: HashMap::new(),
+            calculator: Calculator::new(market_state.clone(), config.clone()),
+            prices: TokenPrices::default(),
             token_decimals: HashMap::new(),
+            config,
         }
     }
 
@@ -69,52 +89,141 @@ where
         self.process_pools(pools);
     }
 
-    // Given a path, estimate the output
-    pub fn estimate_output_amount(&self, swap_path: &SwapPath) -> U256 {
-        let mut current_amount = *AMOUNT;
-
-        // Follow the path and apply rates sequentially
-        for step in &swap_path.steps {
-            if let Some(pool_rates) = self.rates.get(&step.pool_address) {
-                if let Some(&rate) = pool_rates.get(&step.token_in) {
-                    // Calculate: amount * rate / RATE_SCALE_VALUE
-                    current_amount = current_amount
-                        .checked_mul(rate)
-                        .and_then(|v| v.checked_div(*RATE_SCALE_VALUE))
-                        .unwrap_or(U256::ZERO);
-                } else {
-                    return U256::ZERO;
-                }
-            } else {
-                return U256::ZERO;
+    // Forget the rates of pools that left the working set and reprice the tokens they priced.
+    // The ids of their directions are reused, so the paths through the pools have to be
+    // dropped before
+    pub fn remove_pools(&mut self, pool_addrs: &HashSet<Address>) {
+        self.directions.retain(|(pool, _, _), &mut id| {
+            if !pool_addrs.contains(pool) {
+                return true;
             }
+            self.log_rates[id] = f64::NEG_INFINITY;
+            self.curves[id] = RateCurve::default();
+            self.free_ids.push(id);
+            false
+        });
+        for pool in pool_addrs {
+            self.rate_hops.remove(pool);
         }
-        current_amount
-    }
-
-    // Given a swappath, estimate if it is profitable based on calculated rates
-    pub fn is_profitable(&self, swap_path: &SwapPath, min_profit_ratio: U256) -> bool {
-        let mut cumulative_rate = *RATE_SCALE_VALUE; // Start with 1.0 in our scaled format
-
-        // Calculate the cumulative rate along the path
-        for pool in &swap_path.steps {
-            if let Some(pool_rates) = self.rates.get(&pool.pool_address) {
-                if let Some(&rate) = pool_rates.get(&pool.token_in) {
-                    cumulative_rate = cumulative_rate
-                        .checked_mul(rate)
-                        .and_then(|v| v.checked_div(*RATE_SCALE_VALUE))
-                        .unwrap_or(U256::ZERO);
-                } else {
-                    return false;
-                }
-            } else {
-                return false;
+        let block = self.market_state.last_block.load(Ordering::Relaxed);
+        self.prices.remove_sources(pool_addrs, block);
+    }
+
+    // Amount of a token worth our input and the block it was priced at
+    pub fn token_value(&self, token: &Address) -> Option<(U256, u64)> {
+        let value = self.prices.values.get(token)?;
+        Some((value.amount, value.block))
+    }
+
+    // The rate of a pool direction as a float at the reference size, 1.0 being an even trade
+    pub fn rate(&self, pool: &Address, token_in: &Address, token_out: &Address) -> Option<f64> {
+        let log_rate = self.log_rates[*self.directions.get(&(*pool, *token_in, *token_out))?];
+        log_rate.is_finite().then(|| log_rate.exp())
+    }
+
+    // The direction ids of every step of a path, for scoring it with log_score. Directions
+    // that were never rated get an id now and score -inf until they are
+    pub fn direction_ids(&mut self, swap_path: &SwapPath) -> Vec<usize> {
+        swap_path
+            .steps
+            .iter()
+            .map(|step| self.direction_id(step.pool_address, step.token_in, step.token_out))
+            .collect()
+    }
+
+    // Sum of the ln rates at the reference size of the directions of a path. The output of
+    // the reference input is exp(score) times the input, so a path is screened against a
+    // threshold with one add per step and no big number arithmetic
+    pub fn log_score(&self, directions: &[usize]) -> f64 {
+        sum_log_rates(&self.log_rates, directions)
+    }
+
+    fn direction_id(&mut self, pool: Address, token_in: Address, token_out: Address) -> usize {
+        if let Some(&id) = self.directions.get(&(pool, token_in, token_out)) {
+            return id;
+        }
+        let id = self.free_ids.pop().unwrap_or_else(|| {
+            self.log_rates.push(f64::NEG_INFINITY);
+            self.curves.push(RateCurve::default());
+            self.log_rates.len() - 1
+        });
+        self.directions.insert((pool, token_in, token_out), id);
+        id
+    }
+
+    // Given a path, estimate the output for an input amount. Every step uses the rate its
+    // pool has at the amount reaching it, so larger inputs see more price impact
+    pub fn estimate_output_amount(&self, swap_path: &SwapPath, amount_in: U256) -> U256 {
+        let base_decimals = *self.token_decimals.get(&swap_path.base_token()).unwrap_or(&18);
+        // amounts are carried at rate precision so they can be compared against the curves
+        let mut current_amount = f64::from(self.scale_to_rate(amount_in, base_decimals));
+
+        // Follow the path and apply rates sequentially, a split leg sums its pools
+        for leg in swap_path.legs() {
+            let mut leg_amount = 0.0;
+            for (step, share) in leg {
+                let part = current_amount * share as f64 / SPLIT_PRECISION as f64;
+                let Some(log_rate) = self
+                    .directions
+                    .get(&(step.pool_address, step.token_in, step.token_out))
+                    .and_then(|&id| self.curves[id].log_rate_at(part))
+                else {
+                    return U256::ZERO;
+                };
+                leg_amount += part * log_rate.exp();
             }
+            current_amount = leg_amount;
+        }
+        if !current_amount.is_finite() || current_amount < 1.0 {
+            return U256::ZERO;
         }
+        self.scale_from_rate(U256::from(current_amount.floor()), base_decimals)
+    }
+
+    // Given a swappath and input amount, estimate if the output beats the input by more than
+    // min_profit_ratio
+    pub fn is_profitable(
+        &self,
+        swap_path: &SwapPath,
+        amount_in: U256,
+        min_profit_ratio: U256,
+    ) -> bool {
+        if amount_in.is_zero() {
+            return false;
+        }
+        let output = self.estimate_output_amount(swap_path, amount_in);
+        let cumulative_rate = output
+            .checked_mul(*RATE_SCALE_VALUE)
+            .and_then(|v| v.checked_div(amount_in))
+            .unwrap_or(U256::ZERO);
+
         // Check if rate exceeds 1.0 + min_profit_ratio
         cumulative_rate > (*RATE_SCALE_VALUE + min_profit_ratio)
     }
 
+    // The input with the most estimated profit after the flash loan fee and its output,
+    // stepping up from the base amount the same way the quoter optimizes. Profit only
+    // falls once price impact outweighs the edge, so the first drop ends the search
+    pub fn best_input(&self, swap_path: &SwapPath, base: &BaseToken) -> (U256, U256) {
+        let profit = |input: U256, output: U256| {
+            let flash_loan_fee = input * U256::from(9) / U256::from(10000);
+            output.saturating_sub(input + flash_loan_fee)
+        };
+        let mut best_input = base.amount;
+        let mut best_output = self.estimate_output_amount(swap_path, base.amount);
+        let step = base.amount / U256::from(5);
+        for i in 1..=INPUT_STEPS {
+            let input = base.amount + step * U256::from(i);
+            let output = self.estimate_output_amount(swap_path, input);
+            if profit(input, output) <= profit(best_input, best_output) {
+                break;
+            }
+            best_input = input;
+            best_output = output;
+        }
+        (best_input, best_output)
+    }
+
     // Scale a number to our rate precision
     fn scale_to_rate(&self, amount: U256, token_decimals: u32) -> U256 {
         if token_decimals <= RATE_SCALE {
@@ -124,6 +233,30 @@ where
         }
     }
 
+    // Scale a number at rate precision back to the token's decimals
+    fn scale_from_rate(&self, amount: U256, token_decimals: u32) -> U256 {
+        if token_decimals <= RATE_SCALE {
+            amount / U256::from(10u64.pow(RATE_SCALE - token_decimals))
+        } else {
+            amount * U256::from(10u64.pow(token_decimals - RATE_SCALE))
+        }
+    }
+
+    // ln of the exchange rate, none if the output is zero
+    fn log_rate(
+        &self,
+        input: U256,
+        output: U256,
+        input_decimals: u32,
+        output_decimals: u32,
+    ) -> Option<f64> {
+        let rate = self.calculate_rate(input, output, input_decimals, output_decimals);
+        if rate.is_zero() {
+            return None;
+        }
+        Some((f64::from(rate) / f64::from(*RATE_SCALE_VALUE)).ln())
+    }
+
     // Calculate the exchange rate with proper scaling
     fn calculate_rate(
         &self,
@@ -142,240 +275,473 @@ where
             .unwrap_or(U256::ZERO)
     }
 
-    // Given an initial set of filtered pools, estimate the exchange rates
+    // Given a set of pools, estimate the exchange rates in both directions. Rates spread
+    // breadth first out from the bases, so a pool is rated as long as some chain of pools
+    // connects it to one
     pub fn process_pools(&mut self, pools: Vec<Pool>) {
-        let weth: Address = std::env::var("WETH").unwrap().parse().unwrap();
-        let mut alt_tokens: HashSet<Address> = HashSet::new();
-        let mut weth_alt_cnt: HashMap<Address, u32> = HashMap::new();
-
-        // amount is our arb input, this is to generalize the exchange rates to
-        // whatever we are trying to initially arb with
-        let eth_input = *AMOUNT;
-
-        // calcualte the rate for all pools with weth as a base/quote, we are very confident in these quotes
-        for pool in pools
+        let db = self.market_state.db.read().unwrap();
+        for pool in &pools {
+            self.token_decimals
+                .insert(pool.token0_address(), pool.token0_decimals().into());
+            self.token_decimals
+                .insert(pool.token1_address(), pool.token1_decimals().into());
+            // tokens past the first two of a balancer pool are only known from its state
+            if let Ok(state) = db.balancer_state(&pool.address()) {
+                for (token, decimals) in state.tokens.iter().zip(&state.decimals) {
+                    self.token_decimals.insert(*token, (*decimals).into());
+                }
+            }
+        }
+        drop(db);
+        let block = self.market_state.last_block.load(Ordering::Relaxed);
+        let bases = self
+            .config
+            .bases
             .iter()
-            .filter(|p| p.token0_address() == weth || p.token1_address() == weth)
-        {
-            debug!("Processing pool {}", pool.address());
-            self.weth_based.insert(pool.address(), true);
-            self.process_eth_pool(pool, weth, *AMOUNT, &mut alt_tokens, &mut weth_alt_cnt);
+            .map(|base| (base.token, base.amount))
+            .chain([(self.config.weth, self.config.amount)]);
+        for (token, amount) in bases {
+            self.prices.values.entry(token).or_insert(TokenValue {
+                amount,
+                hops: 0,
+                block,
+            });
+        }
+
+        let mut prices = std::mem::take(&mut self.prices);
+        let quotes = propagate_rates(
+            &pools,
+            &mut prices,
+            block,
+            |pool, token_in, token_out, input| self.round_trip(pool, token_in, token_out, input),
+            |pool, token| self.pool_depth(pool, token),
+        );
+        self.prices = prices;
+        let by_address: HashMap<Address, &Pool> =
+            pools.iter().map(|pool| (pool.address(), pool)).collect();
+        for pool in by_address.keys() {
+            self.rate_hops.remove(pool);
+        }
+        for quote in &quotes {
+            self.store_rates(by_address[&quote.pool], quote);
         }
+        let rated: HashSet<Address> = quotes.iter().map(|quote| quote.pool).collect();
+        debug!(
+            "Rated {} of {} pools, {} are not connected to a base or could not be quoted",
+            rated.len(),
+            pools.len(),
+            pools.len() - rated.len()
+        );
+    }
 
-        // update the alt rates
-        for token in alt_tokens {
-            if let Some(&count) = weth_alt_cnt.get(&token) {
-                if let Some(rate) = self.aggregated_weth_rate.get_mut(&token) {
-                    *rate = rate.checked_div(U256::from(count)).unwrap_or(U256::ZERO);
+    // Number of pools between the pool and a base when it was rated, none if it was never
+    // rated. Rates of far away pools build on more estimates and are less reliable
+    pub fn rate_hops(&self, pool: &Address) -> Option<usize> {
+        self.rate_hops.get(pool).copied()
+    }
+
+    // Quote the pool at every curve size from the token it was rated from and store the
+    // rates in both directions. The way back is quoted with the output of the way there, so
+    // each direction gets a point per size
+    fn store_rates(&mut self, pool: &Pool, quote: &PoolQuote) {
+        let in_decimals = *self.token_decimals.get(&quote.token_in).unwrap_or(&18);
+        let out_decimals = *self.token_decimals.get(&quote.token_out).unwrap_or(&18);
+        let forward_id = self.direction_id(quote.pool, quote.token_in, quote.token_out);
+        let back_id = self.direction_id(quote.pool, quote.token_out, quote.token_in);
+        let mut forward = RateCurve::default();
+        let mut back = RateCurve::default();
+        self.log_rates[forward_id] = f64::NEG_INFINITY;
+        self.log_rates[back_id] = f64::NEG_INFINITY;
+        for size in CURVE_SIZES {
+            let (input, output, back_output) = if size == REFERENCE_SIZE {
+                (quote.input, quote.output, quote.back)
+            } else {
+                let input = quote.input * U256::from(size) / U256::from(REFERENCE_SIZE);
+                let Some((output, back_output)) =
+                    self.round_trip(pool, quote.token_in, quote.token_out, input)
+                else {
+                    continue;
+                };
+                (input, output, back_output)
+            };
+            if input.is_zero() || output.is_zero() {
+                continue;
+            }
+            if let Some(log_rate) = self.log_rate(input, output, in_decimals, out_decimals) {
+                forward.insert(f64::from(self.scale_to_rate(input, in_decimals)), log_rate);
+                if size == REFERENCE_SIZE {
+                    self.log_rates[forward_id] = log_rate;
+                }
+            }
+            if let Some(log_rate) = self.log_rate(output, back_output, out_decimals, in_decimals) {
+                back.insert(f64::from(self.scale_to_rate(output, out_decimals)), log_rate);
+                if size == REFERENCE_SIZE {
+                    self.log_rates[back_id] = log_rate;
                 }
             }
         }
-        // calculate the ratio for all pools that weth is neither a base/quote, this will use
-        // an averaged input from the corresponding weth pair
-        for pool in pools
-            .iter()
-            .filter(|p| p.token0_address() != weth && p.token1_address() != weth)
-        {
-            debug!("Processing pool {}", pool.address());
-            self.process_nonweth_pool(pool, eth_input);
+
+        self.curves[forward_id] = forward;
+        self.curves[back_id] = back;
+        // a multi token pool is as close to a base as its closest pair
+        let hops = self.rate_hops.entry(quote.pool).or_insert(quote.hops);
+        *hops = (*hops).min(quote.hops);
+    }
+
+    // Current amount of token in a pool, the reserve for v2 and the virtual reserve at the
+    // current price for v3. Weights of sources of the same token are then all in that token,
+    // where sqrt(reserve0 * reserve1) or liquidity would be in units of each pair. Pools we
+    // can not measure, like balancer pools with their uneven weights, get no weight
+    fn pool_depth(&self, pool: &Pool, token: Address) -> U256 {
+        let db = self.market_state.db.read().unwrap();
+        let is_token0 = token == pool.token0_address();
+        if pool.is_v2() {
+            db.get_reserves(&pool.address())
+                .map(|(reserve0, reserve1)| if is_token0 { reserve0 } else { reserve1 })
+                .unwrap_or_default()
+        } else if pool.is_v3() {
+            let state = (db.liquidity(pool.address()), db.slot0(pool.address()));
+            let (Ok(liquidity), Ok(slot0)) = state else {
+                return U256::ZERO;
+            };
+            let liquidity = U256::from(liquidity);
+            let sqrt_price = U256::from(slot0.sqrtPriceX96);
+            if sqrt_price.is_zero() {
+                U256::ZERO
+            } else if is_token0 {
+                (liquidity << 96) / sqrt_price
+            } else {
+                liquidity.saturating_mul(sqrt_price) >> 96
+            }
+        } else {
+            U256::ZERO
         }
-        // calculate every pool that is
     }
 
-    // Calculate the rate for an weth based pool
-    fn process_eth_pool(
-        &mut self,
+    // Swap through the pool and back, a pool we cannot calculate is skipped
+    fn round_trip(
+        &self,
         pool: &Pool,
-        weth: Address,
+        token_in: Address,
+        token_out: Address,
         input: U256,
-        alt_tokens: &mut HashSet<Address>,
-        weth_alt_cnt: &mut HashMap<Address, u32>,
-    ) {
-        let pool_address = pool.address();
-        let token0 = pool.token0_address();
-        let token1 = pool.token1_address();
-
-        // insert the decimals
-        self.token_decimals
-            .insert(token0, pool.token0_decimals().into());
-        self.token_decimals
-            .insert(token1, pool.token1_decimals().into());
-
-        // Get which token is weth and which is the quote token
-        let (weth, alt) = if token0 == weth {
-            (token0, token1)
+    ) -> Option<(U256, U256)> {
+        let outputs = self
+            .calculator
+            .compute_pool_output(pool.address(), token_in, token_out, pool.pool_type(), pool.fee(), input)
+            .and_then(|output| {
+                let back = self.calculator.compute_pool_output(
+                    pool.address(),
+                    token_out,
+                    token_in,
+                    pool.pool_type(),
+                    pool.fee(),
+                    output,
+                )?;
+                Ok((output, back))
+            });
+        match outputs {
+            Ok(outputs) => Some(outputs),
+            Err(e) => {
+                debug!("Skipping rates for pool {}: {}", pool.address(), e);
+                None
+            }
+        }
+    }
+}
+
+fn sum_log_rates(log_rates: &[f64], directions: &[usize]) -> f64 {
+    directions
+        .iter()
+        .map(|&id| log_rates.get(id).copied().unwrap_or(f64::NEG_INFINITY))
+        .sum()
+}
+
+// ln rates of a pool direction at increasing input sizes, with amounts at rate precision.
+// Between two sizes the ln rate is interpolated and past the largest it keeps falling at the
+// same slope, so thin pools drop off quickly
+#[derive(Debug, Clone, Default, PartialEq)]
+struct RateCurve {
+    // (input amount, ln rate) by increasing input
+    points: Vec<(f64, f64)>,
+}
+
+impl RateCurve {
+    fn insert(&mut self, amount: f64, log_rate: f64) {
+        match self.points.binary_search_by(|(point, _)| point.total_cmp(&amount)) {
+            Ok(index) => self.points[index].1 = log_rate,
+            Err(index) => self.points.insert(index, (amount, log_rate)),
+        }
+    }
+
+    // The ln rate for an input, below the smallest size it is the rate at that size
+    fn log_rate_at(&self, amount: f64) -> Option<f64> {
+        let &(first_amount, first_rate) = self.points.first()?;
+        if amount <= first_amount || self.points.len() == 1 {
+            return Some(first_rate);
+        }
+        let end = self
+            .points
+            .iter()
+            .position(|(point, _)| *point >= amount)
+            .unwrap_or(self.points.len() - 1);
+        let (start_amount, start_rate) = self.points[end - 1];
+        let (end_amount, end_rate) = self.points[end];
+        let offset = amount - start_amount;
+        let width = end_amount - start_amount;
+        if end_rate <= start_rate {
+            Some(start_rate + (end_rate - start_rate) * offset / width)
         } else {
-            (token1, token0)
+            // a rate rising with size is rounding noise, it is not extrapolated
+            Some(start_rate + (end_rate - start_rate) * offset.min(width) / width)
+        }
+    }
+}
+
+// Amount of a token worth our input, used as the size pools trading it are quoted with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct TokenValue {
+    amount: U256,
+    // pools between the token and a base
+    hops: usize,
+    // block the value was last computed at
+    block: u64,
+}
+
+// The value of every priced token along with the pools leading to it from a token closer to
+// a base. Each source holds the amount of the token the pool gave for the value of the token
+// before it, weighted by how much of the token the pool holds
+#[derive(Debug, Default)]
+struct TokenPrices {
+    values: HashMap<Address, TokenValue>,
+    sources: HashMap<Address, HashMap<Address, (U256, U256)>>,
+}
+
+impl TokenPrices {
+    // Recompute a token's value from all of its current sources
+    fn reprice(&mut self, token: Address, hops: usize, block: u64) {
+        let Some(amount) = self
+            .sources
+            .get(&token)
+            .and_then(|sources| weighted_median(sources.values().copied()))
+        else {
+            return;
         };
-        alt_tokens.insert(alt);
+        self.values.insert(token, TokenValue { amount, hops, block });
+    }
 
+    // Drop the pools as sources. A token left without any is no longer priced, so its pools
+    // are not quoted with a stale value until a pool leads to it again
+    fn remove_sources(&mut self, pools: &HashSet<Address>, block: u64) {
+        let mut repriced = Vec::new();
+        for (token, sources) in self.sources.iter_mut() {
+            let before = sources.len();
+            sources.retain(|pool, _| !pools.contains(pool));
+            if sources.len() != before {
+                repriced.push(*token);
+            }
+        }
+        for token in repriced {
+            if self.sources.get(&token).is_some_and(|sources| sources.is_empty()) {
+                self.sources.remove(&token);
+                self.values.remove(&token);
+                continue;
+            }
+            if let Some(hops) = self.values.get(&token).map(|value| value.hops) {
+                self.reprice(token, hops, block);
+            }
+        }
+    }
+}
 
-        // get the output quote and then determine the rates
-        let alt_output = self.calculator.compute_pool_output(
-            pool_address,
-            weth,
-            pool.pool_type(),
-            pool.fee(),
-            input,
-        );
+// The amount at which half of the total liquidity is on either side, so thin pools can not
+// drag a deep pool's price. Without any liquidity measured every source weighs the same
+fn weighted_median(sources: impl Iterator<Item = (U256, U256)>) -> Option<U256> {
+    let mut sources: Vec<(U256, U256)> = sources.collect();
+    sources.sort();
+    let total: U256 = sources.iter().map(|(_, weight)| *weight).sum();
+    let weight = |source: &(U256, U256)| if total.is_zero() { U256::from(1) } else { source.1 };
+    let total = if total.is_zero() { U256::from(sources.len()) } else { total };
+
+    let mut cumulative = U256::ZERO;
+    for source in &sources {
+        cumulative += weight(source);
+        if cumulative * U256::from(2) >= total {
+            return Some(source.0);
+        }
+    }
+    None
+}
 
-        // Get decimals for both tokens
-        let weth_decimals = self.token_decimals.get(&weth).unwrap_or(&18);
-        let alt_decimals = self.token_decimals.get(&alt).unwrap_or(&18);
+// A pool swapped from token_in and back again with the input it was quoted with
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct PoolQuote {
+    pool: Address,
+    token_in: Address,
+    token_out: Address,
+    input: U256,
+    output: U256,
+    back: U256,
+    hops: usize,
+}
 
+// Quote every pair of tokens of a pool from whichever of the two is closest to a base, a layer
+// of tokens at a time. A pool leading away from the bases becomes a source of the token
+// further out, and once a layer is quoted the tokens it reached are repriced from all of
+// their sources, new and old, before their own pools are quoted with the new value. Sources
+// of pools that were not touched keep their last quote
+fn propagate_rates<F, L>(
+    pools: &[Pool],
+    prices: &mut TokenPrices,
+    block: u64,
+    round_trip: F,
+    liquidity: L,
+) -> Vec<PoolQuote>
+where
+    F: Fn(&Pool, Address, Address, U256) -> Option<(U256, U256)>,
+    L: Fn(&Pool, Address) -> U256,
+{
+    let mut by_token: HashMap<Address, Vec<&Pool>> = HashMap::new();
+    for pool in pools {
+        for token in pool_tokens(pool) {
+            by_token.entry(token).or_default().push(pool);
+        }
+    }
 
-        let other_output = self.calculator.compute_pool_output(
-            pool_address,
-            alt,
-            pool.pool_type(),
-            pool.fee(),
-            alt_output
-        );
+    // the valued tokens by distance, closest first
+    let mut layers: BTreeMap<usize, Vec<Address>> = BTreeMap::new();
+    for token in by_token.keys() {
+        if let Some(value) = prices.values.get(token) {
+            layers.entry(value.hops).or_default().push(*token);
+        }
+    }
 
-        // Calculate rates with proper scaling
-        let zero_one_rate =
-            self.calculate_rate(input, alt_output, *weth_decimals, *alt_decimals);
-        let one_zero_rate =
-            self.calculate_rate(alt_output, other_output, *alt_decimals, *weth_decimals);
-
-        // Store rates
-        self.rates
-            .entry(pool_address)
-            .or_default()
-            .insert(token0, zero_one_rate);
-        self.rates
-            .entry(pool_address)
-            .or_default()
-            .insert(token1, one_zero_rate);
-
-
-        // Update aggregated rate
-        if weth == token0 {
-            *self.aggregated_weth_rate.entry(alt).or_insert(U256::ZERO) += zero_one_rate;
-        } else {
-            *self.aggregated_weth_rate.entry(alt).or_insert(U256::ZERO) += one_zero_rate;
+    // (pool, token, token) of every quoted pair, lower token first
+    let mut quoted: HashSet<(Address, Address, Address)> = HashSet::new();
+    let mut quotes = Vec::new();
+    while let Some((hops, mut layer)) = layers.pop_first() {
+        layer.sort();
+        layer.dedup();
+        let mut reached: HashSet<Address> = HashSet::new();
+        for token in layer {
+            // moved closer to a base since it was queued
+            let Some(value) = prices.values.get(&token).filter(|value| value.hops == hops) else {
+                continue;
+            };
+            let input = value.amount;
+            let pairs = by_token.get(&token).into_iter().flatten().flat_map(|pool| {
+                pool_tokens(pool)
+                    .into_iter()
+                    .filter(move |other| *other != token)
+                    .map(move |other| (*pool, other))
+            });
+            for (pool, other) in pairs {
+                let pair = (pool.address(), token.min(other), token.max(other));
+                if quoted.contains(&pair) {
+                    continue;
+                }
+                let Some((output, back)) = round_trip(pool, token, other, input) else {
+                    continue;
+                };
+                quoted.insert(pair);
+
+                let other_hops = prices.values.get(&other).map(|value| value.hops);
+                if other_hops.map_or(true, |other_hops| other_hops > hops) && !output.is_zero() {
+                    // a shorter way to the token, the sources from further out no longer count
+                    if other_hops.is_some_and(|other_hops| other_hops > hops + 1) {
+                        prices.sources.remove(&other);
+                    }
+                    prices
+                        .sources
+                        .entry(other)
+                        .or_default()
+                        .insert(pool.address(), (output, liquidity(pool, other)));
+                    reached.insert(other);
+                }
+                quotes.push(PoolQuote {
+                    pool: pool.address(),
+                    token_in: token,
+                    token_out: other,
+                    input,
+                    output,
+                    back,
+                    hops,
+                });
+            }
         }
-        *weth_alt_cnt.entry(alt).or_insert(0) += 1;
-    }
-
-    fn process_nonweth_pool(&mut self, pool: &Pool, _input: U256) {
-        let pool_address = pool.address();
-        let token0 = pool.token0_address();
-        let token1 = pool.token1_address();
-
-        if let Some(&_input_rate) = self.aggregated_weth_rate.get(&token0) {
-            let token0_decimals = self.token_decimals.get(&token0).unwrap_or(&18);
-            //let scaled_input = U256::from(10u128).pow(U256::from(*token0_decimals));
-
-            let output = self.calculator.compute_pool_output(
-                pool_address,
-                token0,
-                pool.pool_type(),
-                pool.fee(),
-                _input_rate,
-            );
-
-            let token1_decimals = self.token_decimals.get(&token1).unwrap_or(&18);
-
-            let other_output = self.calculator.compute_pool_output(
-                pool_address,
-                token1,
-                pool.pool_type(),
-                pool.fee(),
-                output,
-            );
-
-            let zero_one_rate =
-                self.calculate_rate(_input_rate, output, *token0_decimals, *token1_decimals);
-            let one_zero_rate =
-                self.calculate_rate(output, other_output, *token1_decimals, *token0_decimals);
-
-            self.rates
-                .entry(pool_address)
-                .or_default()
-                .insert(token0, zero_one_rate);
-            self.rates
-                .entry(pool_address)
-                .or_default()
-                .insert(token1, one_zero_rate);
+
+        for token in reached {
+            let queued = prices.values.get(&token).is_some_and(|value| value.hops == hops + 1);
+            prices.reprice(token, hops + 1, block);
+            if !queued {
+                layers.entry(hops + 1).or_default().push(token);
+            }
         }
     }
+    quotes
 }
 
 #[cfg(test)]
 mod estimator_tests {
     use super::*;
+    use crate::health::Health;
     use crate::swap::SwapStep;
+    use crate::tests::helpers::fixtures::pools::{pool, weth_usdc};
     use alloy::network::Ethereum;
     use alloy::primitives::address;
     use alloy::providers::{Provider, ProviderBuilder, RootProvider};
     use alloy::transports::http::{Client, Http};
     use pool_sync::PoolType;
-    use pool_sync::UniswapV2Pool;
-    use std::sync::mpsc;
+    use tokio::sync::mpsc;
     use tokio::sync::broadcast;
     use std::sync::Arc;
-    use std::sync::atomic::AtomicBool;
-    use std::sync::atomic::Ordering;
 
-    // Create mock uniswapv2 weth/usdc pool
+    // Uniswapv2 and sushiswapv2 weth/usdc pools with mock reserves
     fn uni_v2_weth_usdc() -> Pool {
-        let pool = UniswapV2Pool {
-            address: address!("88A43bbDF9D098eEC7bCEda4e2494615dfD9bB9C"),
-            token0: address!("4200000000000000000000000000000000000006"),
-            token1: address!("833589fCD6eDb6E08f4c7C32D4f71b54bdA02913"),
-            token0_name: "WETH".to_string(),
-            token1_name: "USDC".to_string(),
-            token0_decimals: 18,
-            token1_decimals: 6,
-            token0_reserves: U256::from(325032740126871996707_u128),
-            token1_reserves: U256::from(1014189875851_u128),
-            stable: None,
-            fee: None,
-        };
-        Pool::UniswapV2(pool)
+        weth_usdc(
+            address!("88A43bbDF9D098eEC7bCEda4e2494615dfD9bB9C"),
+            Pool::UniswapV2,
+            (325032740126871996707, 1014189875851),
+        )
     }
 
-    // Create mock sushiswapv2 weth/usdc pool
     fn sushi_v2_weth_usdc() -> Pool {
-        let pool = UniswapV2Pool {
-            address: address!("2F8818D1B0f3e3E295440c1C0cDDf40aAA21fA87"),
-            token0: address!("4200000000000000000000000000000000000006"),
-            token1: address!("833589fCD6eDb6E08f4c7C32D4f71b54bdA02913"),
-            token0_name: "WETH".to_string(),
-            token1_name: "USDC".to_string(),
-            token0_decimals: 18,
-            token1_decimals: 6,
-            token0_reserves: U256::from(324239280299976672116_u128),
-            token1_reserves: U256::from(1016689282374_u128),
-            stable: None,
-            fee: None,
-        };
-        Pool::SushiSwapV2(pool)
+        weth_usdc(
+            address!("2F8818D1B0f3e3E295440c1C0cDDf40aAA21fA87"),
+            Pool::SushiSwapV2,
+            (324239280299976672116, 1016689282374),
+        )
     }
 
     // Mock the estimator for calculation
     async fn mock_estimator() -> Estimator<Http<Client>, Ethereum, RootProvider<Http<Client>>> {
         dotenv::dotenv().ok();
-        let endpoint = std::env::var("FULL").unwrap().parse().unwrap();
+        let config = Arc::new(BotConfig::from_env().unwrap());
 
         let uni_pool = uni_v2_weth_usdc();
         let sushi_pool = sushi_v2_weth_usdc();
         let pools = vec![uni_pool, sushi_pool];
 
         let (_, block_rx) = broadcast::channel(10);
-        let (address_tx, _) = mpsc::channel();
+        let (address_tx, _) = mpsc::channel(config.channel_capacity);
 
-        let provider = ProviderBuilder::new().on_http(endpoint);
+        let provider = ProviderBuilder::new().on_http(config.http_url.clone());
         let block = provider.get_block_number().await.unwrap();
 
-        let is_caught_up = Arc::new(AtomicBool::new(false));
-        let market_state =
-            MarketState::init_state_and_start_stream(pools, block_rx, address_tx, block, provider, is_caught_up.clone())
-                .await
-                .unwrap();
-        while is_caught_up.load(Ordering::Relaxed) == false {}
-        Estimator::new(market_state)
+        let health = Arc::new(Health::new());
+        let market_state = MarketState::init_state_and_start_stream(
+            pools,
+            block_rx,
+            address_tx,
+            block,
+            provider,
+            health.clone(),
+            config.clone(),
+        )
+        .await
+        .unwrap();
+        health.wait_until_caught_up().await;
+        Estimator::new(market_state, config)
     }
 
     // Test that we can properly scale values to a desired precision
@@ -436,6 +802,7 @@ mod estimator_tests {
                 },
             ],
             hash: 0,
+            splits: Vec::new(),
         };
         let profitable = SwapPath {
             steps: vec![
@@ -455,12 +822,153 @@ mod estimator_tests {
                 },
             ],
             hash: 0,
+            splits: Vec::new(),
         };
 
-        let no_profit = estimator.is_profitable(&not_profitable, U256::ZERO);
-        let profit = estimator.is_profitable(&profitable, U256::ZERO);
+        let amount = estimator.config.amount;
+        let no_profit = estimator.is_profitable(&not_profitable, amount, U256::ZERO);
+        let profit = estimator.is_profitable(&profitable, amount, U256::ZERO);
         assert!(!no_profit);
         assert!(profit);
     }
 
+    // The directions of a removed pool are unrated and their ids handed out again, so the
+    // rate arrays do not grow as pools come and go
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_reuse_direction_ids() {
+        let mut estimator = mock_estimator().await;
+        let uni_pool = uni_v2_weth_usdc();
+        estimator.process_pools(vec![uni_pool.clone(), sushi_v2_weth_usdc()]);
+        let weth = uni_pool.token0_address();
+        let usdc = uni_pool.token1_address();
+        assert!(estimator.rate(&uni_pool.address(), &weth, &usdc).is_some());
+        let rated = estimator.log_rates.len();
+
+        estimator.remove_pools(&HashSet::from([uni_pool.address()]));
+        assert_eq!(estimator.rate(&uni_pool.address(), &weth, &usdc), None);
+        assert_eq!(estimator.free_ids.len(), 2);
+
+        estimator.process_pools(vec![uni_pool.clone()]);
+        assert!(estimator.rate(&uni_pool.address(), &weth, &usdc).is_some());
+        assert!(estimator.free_ids.is_empty());
+        assert_eq!(estimator.log_rates.len(), rated);
+    }
+
+    // Pools two hops out and pools with weth as token1 are quoted, disconnected ones are not.
+    // Token values follow the deepest pool and are recomputed, not accumulated, on updates
+    #[test]
+    fn test_propagate_rates() {
+        let weth = address!("4200000000000000000000000000000000000006");
+        let usdc = address!("833589fCD6eDb6E08f4c7C32D4f71b54bdA02913");
+        let dai = address!("50c5725949A6F0c72E6C4a641F24049A917DB0Cb");
+        let degen = address!("4ed4E862860beD51a9570b96d89aF5E1B0Efefed");
+        let pools = vec![
+            pool(1, usdc, degen),
+            pool(2, weth, usdc),
+            pool(3, dai, weth),
+            pool(4, Address::with_last_byte(5), Address::with_last_byte(6)),
+            pool(6, weth, usdc),
+        ];
+        let mut prices = TokenPrices::default();
+        prices.values.insert(
+            weth,
+            TokenValue {
+                amount: U256::from(1000),
+                hops: 0,
+                block: 1,
+            },
+        );
+
+        // every pool doubles the input except the thin weth/usdc pool, which triples it
+        let thin = Address::with_last_byte(6);
+        let quotes = propagate_rates(
+            &pools,
+            &mut prices,
+            1,
+            |pool, _, _, input| {
+                let multiple = if pool.address() == thin { 3 } else { 2 };
+                Some((input * U256::from(multiple), input))
+            },
+            |pool, _| U256::from(if pool.address() == thin { 1 } else { 100 }),
+        );
+        assert_eq!(quotes.len(), 4);
+        let quote = |id: u8| {
+            quotes
+                .iter()
+                .find(|quote| quote.pool == Address::with_last_byte(id))
+                .unwrap()
+        };
+        assert_eq!(quote(3).token_in, weth);
+        assert_eq!(quote(3).hops, 0);
+        assert_eq!(quote(1).token_in, usdc);
+        assert_eq!(quote(1).input, U256::from(2000));
+        assert_eq!(quote(1).hops, 1);
+        assert_eq!(prices.values[&usdc].amount, U256::from(2000));
+        assert_eq!(prices.values[&degen].amount, U256::from(4000));
+        assert_eq!(prices.values[&degen].hops, 2);
+
+        // once the second pool is the deeper one, usdc is priced from it
+        let quotes = propagate_rates(
+            &pools[4..],
+            &mut prices,
+            2,
+            |_, _, _, input| Some((input * U256::from(4), input)),
+            |_, _| U256::from(1000),
+        );
+        assert_eq!(quotes.len(), 1);
+        assert_eq!(prices.values[&usdc].amount, U256::from(4000));
+        assert_eq!(prices.values[&usdc].block, 2);
+
+        // and back to the first pool when it is removed
+        prices.remove_sources(&HashSet::from([thin]), 3);
+        assert_eq!(prices.values[&usdc].amount, U256::from(2000));
+        assert_eq!(prices.values[&usdc].block, 3);
+
+        // degen loses its only source and is no longer priced
+        prices.remove_sources(&HashSet::from([Address::with_last_byte(1)]), 4);
+        assert!(!prices.values.contains_key(&degen));
+        assert!(!prices.sources.contains_key(&degen));
+        assert_eq!(prices.values[&usdc].amount, U256::from(2000));
+    }
+
+    // Without any liquidity the sources weigh the same
+    #[test]
+    fn test_weighted_median() {
+        let sources = [
+            (U256::from(9), U256::ZERO),
+            (U256::from(1), U256::ZERO),
+            (U256::from(5), U256::ZERO),
+        ];
+        assert_eq!(weighted_median(sources.into_iter()), Some(U256::from(5)));
+        let sources = [(U256::from(9), U256::from(10)), (U256::from(1), U256::from(1))];
+        assert_eq!(weighted_median(sources.into_iter()), Some(U256::from(9)));
+        assert_eq!(weighted_median(std::iter::empty()), None);
+    }
+
+    // ln rates are interpolated between sizes and keep falling past the largest
+    #[test]
+    fn test_rate_curve() {
+        let mut curve = RateCurve::default();
+        assert_eq!(curve.log_rate_at(100.0), None);
+        curve.insert(1000.0, -0.1);
+        curve.insert(100.0, 0.0);
+        curve.insert(400.0, -0.03);
+
+        let close = |amount: f64, expected: f64| {
+            (curve.log_rate_at(amount).unwrap() - expected).abs() < 1e-12
+        };
+        assert!(close(10.0, 0.0));
+        assert!(close(250.0, -0.015));
+        assert!(close(700.0, -0.065));
+        assert!(close(1600.0, -0.17));
+    }
+
+    // A path scores the sum of its reference ln rates, unrated or unknown steps make it -inf
+    #[test]
+    fn test_log_score() {
+        let log_rates = [0.01, -0.002, f64::NEG_INFINITY];
+        assert!((sum_log_rates(&log_rates, &[0, 1]) - 0.008).abs() < 1e-12);
+        assert_eq!(sum_log_rates(&log_rates, &[0, 2]), f64::NEG_INFINITY);
+        assert_eq!(sum_log_rates(&log_rates, &[0, 3]), f64::NEG_INFINITY);
+    }
 }
@@ -1,6 +1,7 @@
 use alloy::primitives::Address;
 use alloy::primitives::U256;
 use alloy::rpc::types::Header;
+use pool_sync::Pool;
 use std::collections::HashSet;
 
 use crate::swap::SwapPath;
@@ -13,3 +14,10 @@ pub enum Event {
     PoolsTouched(HashSet<Address>, u64),
     NewBlock(Header),
 }
+
+// Changes to the working set while running, applied by the searcher between blocks
+#[derive(Debug, Clone)]
+pub enum PoolDelta {
+    Add(Vec<Pool>),
+    Remove(HashSet<Address>),
+}
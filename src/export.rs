@@ -0,0 +1,235 @@
+use alloy::primitives::{Address, U256};
+use petgraph::visit::EdgeRef;
+use pool_sync::{Pool, PoolInfo};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::Write;
+
+use crate::graph::ArbGraph;
+use crate::swap::SwapPath;
+
+// Snapshot of the pool graph and the estimated rates for external tools. Nodes are
+// tokens, edges are swap directions through a pool
+#[derive(Debug, Serialize)]
+pub struct GraphExport {
+    pub nodes: Vec<TokenNode>,
+    pub edges: Vec<PoolDirection>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TokenNode {
+    pub address: Address,
+    // only known for tokens that are token0 or token1 of some pool
+    pub symbol: Option<String>,
+    pub decimals: Option<u8>,
+    // pools the token can be swapped in, zero once all of them were removed
+    pub pools: usize,
+    // amount of the token worth our input and the block it was priced at
+    pub value: Option<U256>,
+    pub value_block: Option<u64>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct PoolDirection {
+    pub pool: Address,
+    pub protocol: String,
+    pub token_in: Address,
+    pub token_out: Address,
+    pub fee: u32,
+    // sqrt(reserve0 * reserve1) for v2, liquidity for v3
+    pub liquidity: Option<U256>,
+    pub reserve_in: Option<U256>,
+    pub reserve_out: Option<U256>,
+    // estimated rate of token_out per token_in, none if the estimator has not rated it
+    pub rate: Option<f64>,
+    // pools between the pool and a base when it was rated
+    pub rate_hops: Option<usize>,
+}
+
+impl GraphExport {
+    // The rates, values and pool state all have to come from the same block. state gives
+    // the v2 reserves as (reserve0, reserve1) and the liquidity of a pool
+    pub fn new<F, V, S>(graph: &ArbGraph, rate: F, value: V, state: S) -> Self
+    where
+        F: Fn(&Address, &Address, &Address) -> Option<(f64, usize)>,
+        V: Fn(&Address) -> Option<(U256, u64)>,
+        S: Fn(&Pool) -> (Option<(U256, U256)>, Option<U256>),
+    {
+        let graph_ref = graph.graph();
+        let mut symbols: HashMap<Address, (String, u8)> = HashMap::new();
+        let mut pools: HashMap<Address, Vec<Address>> = HashMap::new();
+        let mut edges = Vec::with_capacity(graph_ref.edge_count());
+        for edge in graph_ref.edge_references() {
+            let pool = graph.pool(edge.weight());
+            let token_in = graph_ref[edge.source()];
+            let token_out = graph_ref[edge.target()];
+            symbols.insert(pool.token0_address(), (pool.token0_name(), pool.token0_decimals()));
+            symbols.insert(pool.token1_address(), (pool.token1_name(), pool.token1_decimals()));
+            pools.entry(token_in).or_default().push(pool.address());
+
+            let (reserves, liquidity) = state(pool);
+            let (reserve_in, reserve_out) = match reserves {
+                Some((reserve0, reserve1)) if token_in == pool.token0_address() => {
+                    (Some(reserve0), Some(reserve1))
+                }
+                Some((reserve0, reserve1)) => (Some(reserve1), Some(reserve0)),
+                None => (None, None),
+            };
+            let rated = rate(&pool.address(), &token_in, &token_out);
+            edges.push(PoolDirection {
+                pool: pool.address(),
+                protocol: format!("{:?}", pool.pool_type()),
+                token_in,
+                token_out,
+                fee: edge.weight().fee,
+                liquidity,
+                reserve_in,
+                reserve_out,
+                rate: rated.map(|(rate, _)| rate),
+                rate_hops: rated.map(|(_, hops)| hops),
+            });
+        }
+
+        let nodes = graph_ref
+            .node_weights()
+            .map(|token| {
+                let known = symbols.get(token);
+                let mut token_pools = pools.get(token).cloned().unwrap_or_default();
+                token_pools.sort();
+                token_pools.dedup();
+                let priced = value(token);
+                TokenNode {
+                    address: *token,
+                    symbol: known.map(|(symbol, _)| symbol.clone()),
+                    decimals: known.map(|(_, decimals)| *decimals),
+                    pools: token_pools.len(),
+                    value: priced.map(|(amount, _)| amount),
+                    value_block: priced.map(|(_, block)| block),
+                }
+            })
+            .collect();
+        Self { nodes, edges }
+    }
+
+    // Graphviz digraph labelled with symbols, protocols and rates. Directions without a rate
+    // are dashed and isolated tokens are grey, so gaps stand out
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::from("digraph arb {\n    node [shape=ellipse];\n");
+        for node in &self.nodes {
+            let label = node.symbol.clone().unwrap_or_else(|| node.address.to_string());
+            let style = if node.pools == 0 { ", color=grey" } else { "" };
+            let _ = writeln!(dot, "    \"{}\" [label=\"{}\"{}];", node.address, escape(&label), style);
+        }
+        for edge in &self.edges {
+            let (rate, style) = match edge.rate.zip(edge.rate_hops) {
+                Some((rate, hops)) => (format!("{rate:.6} ({hops} hops)"), ""),
+                None => ("no rate".to_string(), ", style=dashed"),
+            };
+            let _ = writeln!(
+                dot,
+                "    \"{}\" -> \"{}\" [label=\"{} {} fee {}\\n{}\"{}];",
+                edge.token_in, edge.token_out, edge.protocol, edge.pool, edge.fee, rate, style
+            );
+        }
+        dot.push_str("}\n");
+        dot
+    }
+}
+
+// Every pool with the number of paths through it and their hashes, tab separated
+pub fn path_index_table(cycles: &[SwapPath]) -> String {
+    let mut index: BTreeMap<Address, Vec<u64>> = BTreeMap::new();
+    for path in cycles {
+        for step in &path.steps {
+            index.entry(step.pool_address).or_default().push(path.hash);
+        }
+    }
+
+    let mut table = String::from("pool\tpaths\thashes\n");
+    for (pool, hashes) in index {
+        let hashes: Vec<String> = hashes.iter().map(|hash| hash.to_string()).collect();
+        let _ = writeln!(table, "{}\t{}\t{}", pool, hashes.len(), hashes.join(","));
+    }
+    table
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod export_tests {
+    use super::*;
+    use crate::config::CycleConfig;
+    use crate::tests::helpers::fixtures::pools::{weth_usdc, USDC, WETH};
+    use pool_sync::UniswapV2Pool;
+
+    fn graph() -> ArbGraph {
+        let pool = |id: u8, protocol: fn(UniswapV2Pool) -> Pool| {
+            weth_usdc(Address::with_last_byte(id), protocol, (100, 400))
+        };
+        let config = CycleConfig {
+            max_hops: 2,
+            min_liquidity: U256::ZERO,
+            max_cycles_per_pool: None,
+            cache_dir: None,
+        };
+        ArbGraph::build(
+            vec![pool(1, Pool::UniswapV2), pool(2, Pool::SushiSwapV2)],
+            vec![WETH],
+            config,
+        )
+    }
+
+    // Both directions of each pool are exported with reserves oriented to the direction,
+    // taken from the state the rates came from rather than the synced pools
+    #[test]
+    fn test_export() {
+        let graph = graph();
+        let export = GraphExport::new(
+            &graph,
+            |pool, token_in, _| {
+                (*pool == Address::with_last_byte(1) && *token_in == WETH).then_some((3.9, 1))
+            },
+            |token| (*token == USDC).then_some((U256::from(4), 7)),
+            |_| (Some((U256::from(150), U256::from(600))), Some(U256::from(300))),
+        );
+        assert_eq!(export.nodes.len(), 2);
+        assert!(export.nodes.iter().all(|node| node.pools == 2));
+        let weth = export.nodes.iter().find(|node| node.address == WETH).unwrap();
+        assert_eq!(weth.symbol.as_deref(), Some("WETH"));
+        assert_eq!(weth.decimals, Some(18));
+        assert_eq!(weth.value, None);
+        let usdc = export.nodes.iter().find(|node| node.address == USDC).unwrap();
+        assert_eq!(usdc.value, Some(U256::from(4)));
+        assert_eq!(usdc.value_block, Some(7));
+
+        assert_eq!(export.edges.len(), 4);
+        let rated: Vec<&PoolDirection> = export.edges.iter().filter(|edge| edge.rate.is_some()).collect();
+        assert_eq!(rated.len(), 1);
+        assert_eq!(rated[0].token_in, WETH);
+        assert_eq!(rated[0].reserve_in, Some(U256::from(150)));
+        assert_eq!(rated[0].reserve_out, Some(U256::from(600)));
+        assert_eq!(rated[0].liquidity, Some(U256::from(300)));
+        let back = export.edges.iter().find(|edge| edge.token_in == USDC).unwrap();
+        assert_eq!(back.reserve_in, Some(U256::from(600)));
+        assert_eq!(rated[0].rate_hops, Some(1));
+
+        let dot = export.to_dot();
+        assert!(dot.starts_with("digraph arb {"));
+        assert_eq!(dot.matches(" -> ").count(), 4);
+        assert_eq!(dot.matches("style=dashed").count(), 3);
+        assert!(serde_json::to_string(&export).is_ok());
+    }
+
+    // One row per pool, each listing the paths through it
+    #[test]
+    fn test_path_index_table() {
+        let graph = graph();
+        let table = path_index_table(graph.cycles());
+        let rows: Vec<&str> = table.lines().collect();
+        assert_eq!(rows[0], "pool\tpaths\thashes");
+        assert_eq!(rows.len(), 3);
+        assert!(rows[1..].iter().all(|row| row.split('\t').nth(1) == Some("2")));
+    }
+}
@@ -1,13 +1,17 @@
 use crate::gen::ERC20Token::{self, approveCall};
 use crate::gen::{V2Aerodrome, V2Swap, V3Swap, V3SwapDeadline, V3SwapDeadlineTick};
-use crate::AMOUNT;
+use crate::chain::SwapType;
+use crate::commands::sync_pools;
+use crate::config::BotConfig;
+use crate::events::PoolDelta;
+use crate::supervisor::{wait_for_shutdown, ShutdownSignal};
 use alloy::primitives::{address, Address, U160, U256};
 use alloy::sol_types::{SolCall, SolValue};
 use anyhow::Result;
 use lazy_static::lazy_static;
-use log::{info, debug};
+use log::{debug, info, warn};
 use node_db::{InsertionType, NodeDB};
-use pool_sync::{Chain, Pool, PoolInfo, PoolType};
+use pool_sync::{Chain, Pool, PoolInfo};
 use reqwest::header::{HeaderMap, HeaderValue};
 use revm::primitives::{Bytes, TransactTo, ExecutionResult, FixedBytes};
 use revm::{inspector_handle_register, Evm};
@@ -17,13 +21,15 @@ use std::io::{BufReader, BufWriter};
 use std::path::Path;
 use std::str::FromStr;
 use std::collections::{HashSet, HashMap};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::mpsc::Sender;
 use revm_inspectors::access_list::AccessListInspector;
 use rayon::prelude::*;
 
 // Blacklisted tokens we dont want to consider
 lazy_static! {
     static ref BLACKLIST: Vec<Address> = vec![address!("be5614875952b1683cb0a2c20e6509be46d353a4")];
-    static ref WETH_ADDRESS: Address = address!("4200000000000000000000000000000000000006");
 }
 
 // Serialializtion/Deserialization Structs
@@ -45,22 +51,16 @@ struct Token {
     address: String,
 }
 
-// enum for swap dispatch
-#[derive(Copy, Clone)]
-enum SwapType {
-    V2Basic,        // standard univ2 swap
-    V2Aerodrome,    // aerodrome swap
-    V3Basic,        // univ3 swap w/o deadline
-    V3Deadline,     // univ3 swap w/ deadline
-    V3DeadlineTick, // Slipstream v3 deadline and tick
-}
-
 // Given a set of pools, filter them down to a proper working set
-pub async fn filter_pools(pools: Vec<Pool>, num_results: usize, chain: Chain) -> Vec<Pool> {
+pub async fn filter_pools(
+    pools: Vec<Pool>,
+    num_results: usize,
+    config: &BotConfig,
+) -> Vec<Pool> {
     info!("Initial pool count before filter: {}", pools.len());
 
     // get all of the top volume tokens from birdeye, we imply volume = volatility
-    let top_volume_tokens = get_top_volume_tokens(chain, num_results)
+    let top_volume_tokens = get_top_volume_tokens(config.chain.chain, num_results, &config.birdeye_key)
         .await
         .expect("Failed to get top volume tokens");
 
@@ -81,22 +81,79 @@ pub async fn filter_pools(pools: Vec<Pool>, num_results: usize, chain: Chain) ->
 
     // There are lots of token contracts with various different balance slots,
     // try to figure out the balance slot for each token
-    let slot_map = construct_slot_map(&pools);
+    let slot_map = construct_slot_map(&pools, &config.db_path);
 
     // simulate swap on every pool that we have, this will filter out pools that have a pair we
     // want but dont have any liq to swap with
-    let pools = filter_by_swap(pools, slot_map).await;
+    let pools = filter_by_swap(pools, slot_map, config).await;
     debug!("Pool count after swap filter: {}", pools.len());
     pools
 }
 
+// Sync and filter the pools again every interval and send the changes to the working set to
+// the searcher. The working set is shared so a restarted refresher diffs against the last one
+pub async fn refresh_working_set(
+    working_set: Arc<Mutex<HashSet<Address>>>,
+    delta_tx: Sender<PoolDelta>,
+    interval: Duration,
+    config: Arc<BotConfig>,
+    mut shutdown: ShutdownSignal,
+) {
+    loop {
+        let refresh = async {
+            tokio::time::sleep(interval).await;
+            let (pools, _) = sync_pools(&config).await?;
+            anyhow::Ok(filter_pools(pools, config.num_tokens, &config).await)
+        };
+        let pools = tokio::select! {
+            _ = wait_for_shutdown(&mut shutdown) => {
+                info!("Pool refresher shutting down");
+                return;
+            }
+            pools = refresh => pools,
+        };
+        let pools = match pools {
+            Ok(pools) => pools,
+            Err(e) => {
+                warn!("Failed to refresh the working set: {:#}", e);
+                continue;
+            }
+        };
+
+        let (added, removed) = working_set_delta(&mut working_set.lock().unwrap(), pools);
+        info!("Working set refreshed, {} pools added, {} removed", added.len(), removed.len());
+        if !removed.is_empty() && delta_tx.send(PoolDelta::Remove(removed)).await.is_err() {
+            return;
+        }
+        if !added.is_empty() && delta_tx.send(PoolDelta::Add(added)).await.is_err() {
+            return;
+        }
+    }
+}
+
+// Pools that joined and left the working set, which is replaced by the new one
+fn working_set_delta(working_set: &mut HashSet<Address>, pools: Vec<Pool>) -> (Vec<Pool>, HashSet<Address>) {
+    let current: HashSet<Address> = pools.iter().map(|pool| pool.address()).collect();
+    let removed = working_set.difference(&current).copied().collect();
+    let added = pools
+        .into_iter()
+        .filter(|pool| !working_set.contains(&pool.address()))
+        .collect();
+    *working_set = current;
+    (added, removed)
+}
+
 
 // ---------------------------------------------------
 // Helper functions to get all data and filter the pools
 // ---------------------------------------------------
 
 // fetch all the top volume tokens from birdeye
-async fn get_top_volume_tokens(chain: Chain, num_results: usize) -> Result<Vec<Address>> {
+async fn get_top_volume_tokens(
+    chain: Chain,
+    num_results: usize,
+    api_key: &str,
+) -> Result<Vec<Address>> {
     // if we have cached these tokens, just read them in
     let cache_file = format!("cache/top_volume_tokens_{}.json", chain);
     if Path::new(&cache_file).exists() {
@@ -104,7 +161,7 @@ async fn get_top_volume_tokens(chain: Chain, num_results: usize) -> Result<Vec<A
     }
 
     // cache for tokens does not exist, fetch them from birdeye
-    let top_volume_tokens = fetch_top_volume_tokens(num_results, chain).await;
+    let top_volume_tokens = fetch_top_volume_tokens(num_results, chain, api_key).await;
 
     // write tokens to file
     create_dir_all("cache").unwrap();
@@ -131,11 +188,10 @@ fn read_addresses_from_file(filename: &str) -> Result<Vec<Address>> {
 }
 
 // fetch the top volume tokens from birdeye
-async fn fetch_top_volume_tokens(num_results: usize, chain: Chain) -> Vec<Address> {
+async fn fetch_top_volume_tokens(num_results: usize, chain: Chain, api_key: &str) -> Vec<Address> {
     let client = reqwest::Client::new();
     let mut headers = HeaderMap::new();
-    let api_key = std::env::var("BIRDEYE_KEY").unwrap();
-    headers.insert("X-API-KEY", HeaderValue::from_str(&api_key).unwrap());
+    headers.insert("X-API-KEY", HeaderValue::from_str(api_key).unwrap());
     if chain == Chain::Ethereum {
         headers.insert("x-chain", HeaderValue::from_static("ethereum"));
     } else if chain == Chain::Base {
@@ -185,7 +241,11 @@ async fn fetch_top_volume_tokens(num_results: usize, chain: Chain) -> Vec<Addres
 
 // Go through the pools and try to perform a swap on it. This is to test liquidity depth as we
 // dont want to include paths that dont have enough liq for a swap
-async fn filter_by_swap(pools: Vec<Pool>, slot_map: HashMap<Address, FixedBytes<32>>) -> Vec<Pool> {
+async fn filter_by_swap(
+    pools: Vec<Pool>,
+    slot_map: HashMap<Address, FixedBytes<32>>,
+    config: &BotConfig,
+) -> Vec<Pool> {
     // pools that pass through swap filter
     let mut filtered_pools: Vec<Pool> = vec![];
 
@@ -194,86 +254,22 @@ async fn filter_by_swap(pools: Vec<Pool>, slot_map: HashMap<Address, FixedBytes<
     let lots_of_tokens = U256::from(1e70);
 
     // construct the db
-    let database_path = std::env::var("DB_PATH").unwrap();
-    let mut nodedb = NodeDB::new(database_path).unwrap();
+    let mut nodedb = NodeDB::new(config.db_path.clone()).unwrap();
 
     // go through all the pools and try a swap on each one
     for pool in pools {
-        // get the router address
-        let (router_address, swap_type) = match pool.pool_type() {
-            PoolType::UniswapV2 => (
-                address!("4752ba5dbc23f44d87826276bf6fd6b1c372ad24"),
-                SwapType::V2Basic,
-            ),
-            PoolType::SushiSwapV2 => (
-                address!("6BDED42c6DA8FBf0d2bA55B2fa120C5e0c8D7891"),
-                SwapType::V2Basic,
-            ),
-            PoolType::PancakeSwapV2 => (
-                address!("8cFe327CEc66d1C090Dd72bd0FF11d690C33a2Eb"),
-                SwapType::V2Basic,
-            ),
-            PoolType::BaseSwapV2 => (
-                address!("327Df1E6de05895d2ab08513aaDD9313Fe505d86"),
-                SwapType::V2Basic,
-            ),
-            PoolType::SwapBasedV2 => (
-                address!("aaa3b1F1bd7BCc97fD1917c18ADE665C5D31F066"),
-                SwapType::V2Basic,
-            ),
-            PoolType::DackieSwapV2 => (
-                address!("Ca4EAa32E7081b0c4Ba47e2bDF9B7163907Fe56f"),
-                SwapType::V2Basic,
-            ),
-            PoolType::AlienBaseV2 => (
-                address!("8c1A3cF8f83074169FE5D7aD50B978e1cD6b37c7"),
-                SwapType::V2Basic,
-            ),
-            PoolType::UniswapV3 => (
-                address!("2626664c2603336E57B271c5C0b26F421741e481"),
-                SwapType::V3Basic,
-            ),
-            PoolType::AlienBaseV3 => (
-                address!("B20C411FC84FBB27e78608C24d0056D974ea9411"),
-                SwapType::V3Basic,
-            ),
-            PoolType::DackieSwapV3 => (
-                address!("195FBc5B8Fbd5Ac739C1BA57D4Ef6D5a704F34f7"),
-                SwapType::V3Basic,
-            ),
-            PoolType::PancakeSwapV3 => (
-                address!("678Aa4bF4E210cf2166753e054d5b7c31cc7fa86"),
-                SwapType::V3Basic,
-            ),
-            PoolType::SushiSwapV3 => (
-                address!("FB7eF66a7e61224DD6FcD0D7d9C3be5C8B049b9f"),
-                SwapType::V3Deadline,
-            ),
-            PoolType::SwapBasedV3 => (
-                address!("756C6BbDd915202adac7beBB1c6C89aC0886503f"),
-                SwapType::V3Deadline,
-            ),
-            PoolType::BaseSwapV3 => (
-                address!("1B8eea9315bE495187D873DA7773a874545D9D48"),
-                SwapType::V3Deadline,
-            ),
-            PoolType::Aerodrome => (
-                address!("cF77a3Ba9A5CA399B7c97c74d54e5b1Beb874E43"),
-                SwapType::V2Aerodrome,
-            ),
-            PoolType::Slipstream => (
-                address!("BE6D8f0d05cC4be24d5167a3eF062215bE6D18a5"),
-                SwapType::V3DeadlineTick,
-            ),
-            _ => panic!("will not reach here"),
+        // get the router address, skip protocols we cant swap through on this chain
+        let (router_address, swap_type) = match config.chain.router(pool.pool_type()) {
+            Some(router) => (router.address, router.swap_type),
+            None => continue,
         };
 
         // Determine if this is a WETH pool and set swap direction
         let is_weth_pool =
-            pool.token0_address() == *WETH_ADDRESS || pool.token1_address() == *WETH_ADDRESS;
+            pool.token0_address() == config.weth || pool.token1_address() == config.weth;
         let zero_to_one = if is_weth_pool {
             // If WETH pool, first swap should be WETH -> Token
-            pool.token0_address() == *WETH_ADDRESS
+            pool.token0_address() == config.weth
         } else {
             // For non-WETH pools, keep original direction
             true
@@ -330,7 +326,7 @@ async fn filter_by_swap(pools: Vec<Pool>, slot_map: HashMap<Address, FixedBytes<
         // we now have some of the input token and we have approved the router to spend it
         // try a swap to see if if it is valid
         //let amt = U256::from(1e18);
-        let amt = *AMOUNT;
+        let amt = config.amount;
         let lower_bound = amt
             .checked_mul(U256::from(95))
             .unwrap()
@@ -479,7 +475,7 @@ fn setup_router_calldata(
 }
 
 // For each token, determine the balance slot 
-fn construct_slot_map(pools: &Vec<Pool>) -> HashMap<Address, FixedBytes<32>> {
+fn construct_slot_map(pools: &Vec<Pool>, db_path: &str) -> HashMap<Address, FixedBytes<32>> {
     // Known common slots with their semantic meaning
     let known_slots = [
         FixedBytes::<32>::from_str("bbc70db1b6c7afd11e79c0fb0051300458f1a3acb8ee9789d9b6b26c61ad9bc7").unwrap(),
@@ -505,8 +501,7 @@ fn construct_slot_map(pools: &Vec<Pool>) -> HashMap<Address, FixedBytes<32>> {
         .collect();
 
     // nodedb for the provider
-    let database_path = std::env::var("DB_PATH").unwrap();
-    let mut nodedb = NodeDB::new(database_path).unwrap();
+    let mut nodedb = NodeDB::new(db_path.to_string()).unwrap();
 
     // dummy account w/ calldata
     let account = address!("0000000000000000000000000000000000000001");
@@ -5,16 +5,19 @@ use alloy::primitives::U256;
 use alloy::eips::eip1559::BaseFeeParams;
 use alloy::eips::calc_next_block_base_fee;
 use crate::events::Event;
+use crate::supervisor::{wait_for_shutdown, ShutdownSignal};
 
 // Handles all gas state and calculations
 pub struct GasStation {
     base_fee: AtomicU64,
+    base_fee_params: BaseFeeParams,
 }
 
 impl GasStation {
-    pub fn new() -> Self {
+    pub fn new(base_fee_params: BaseFeeParams) -> Self {
         Self {
             base_fee: AtomicU64::new(0),
+            base_fee_params,
         }
     }
 
@@ -27,16 +30,21 @@ impl GasStation {
         (base_fee + priority_fee, priority_fee)
     }
 
-    // Continuously update the gas fees
-    pub async fn update_gas(&self, mut block_rx: Receiver<Event>) {
-        let base_fee_params = BaseFeeParams::optimism_canyon();
-
-        while let Ok(Event::NewBlock(header)) = block_rx.recv().await {
+    // Continuously update the gas fees until shutdown
+    pub async fn update_gas(&self, mut block_rx: Receiver<Event>, mut shutdown: ShutdownSignal) {
+        loop {
+            let header = tokio::select! {
+                _ = wait_for_shutdown(&mut shutdown) => return,
+                event = block_rx.recv() => match event {
+                    Ok(Event::NewBlock(header)) => header,
+                    _ => return,
+                },
+            };
             let base_fee = header.inner.base_fee_per_gas.unwrap();
             let gas_used = header.inner.gas_used;
             let gas_limit = header.inner.gas_limit;
 
-            let next_base_fee = calc_next_block_base_fee(gas_used, gas_limit, base_fee, base_fee_params);
+            let next_base_fee = calc_next_block_base_fee(gas_used, gas_limit, base_fee, self.base_fee_params);
 
             self.base_fee.store(next_base_fee, Ordering::Relaxed);
         }
@@ -21,6 +21,7 @@ sol!(
     contract ERC20Token {
         function approve(address spender, uint256 amount) external returns (bool success);
         function balanceOf(address account) external view returns (uint256);
+        event Transfer(address indexed from, address indexed to, uint256 value);
     }
 );
 
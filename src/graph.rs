@@ -1,245 +1,384 @@
+use crate::config::{BotConfig, CycleConfig};
+use crate::cycle_cache::CycleCache;
 use crate::swap::{SwapPath, SwapStep};
-use alloy::primitives::Address;
-use petgraph::graph::UnGraph;
+use alloy::primitives::{Address, U256};
+use log::{info, warn};
+use petgraph::graph::DiGraph;
 use petgraph::prelude::*;
-use pool_sync::{BalancerV2Pool, CurveTriCryptoPool, Pool, PoolInfo};
-use std::collections::HashSet;
-use std::hash::Hash;
-use std::hash::{DefaultHasher, Hasher};
+use pool_sync::{Pool, PoolInfo};
+use std::collections::{HashMap, HashSet, VecDeque};
+
+// The pool graph and every cycle through each base token. Pools can be added and removed
+// while running, which only enumerates or retires the cycles through those pools
+pub struct ArbGraph {
+    // one edge per swap direction, multi token pools have an edge for every ordered pair
+    graph: DiGraph<Address, PoolEdge>,
+    nodes: HashMap<Address, NodeIndex>,
+    // every pool in the graph, stored once and referred to by index from the edges
+    pools: Vec<Pool>,
+    pool_index: HashMap<Address, u32>,
+    start_tokens: Vec<Address>,
+    search: CycleSearch,
+    cycles: Vec<SwapPath>,
+    // position of each cycle in cycles by its hash
+    positions: HashMap<u64, usize>,
+}
+
+// A swap direction through a pool. Anything that differs per direction lives here
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolEdge {
+    // index of the pool in the pool table
+    pub pool: u32,
+    pub fee: u32,
+    // depth used to order and prune the search, none for multi token pools
+    pub liquidity: Option<U256>,
+}
+
+// Cycles that changed after pools were added or removed
+#[derive(Debug, Default)]
+pub struct CycleDelta {
+    pub added: Vec<SwapPath>,
+    pub retired: Vec<SwapPath>,
+}
 
-pub struct ArbGraph;
 impl ArbGraph {
-    // Constructor, takes the set of working tokens we are interested in searching over
-    pub async fn generate_cycles(working_pools: Vec<Pool>) -> Vec<SwapPath> {
-        // build the graph
-        let token: Address = std::env::var("WETH").unwrap().parse().unwrap();
-        let graph = ArbGraph::build_graph(working_pools);
-
-        // get start node and construct cycles
-        let start_node = graph
-            .node_indices()
-            .find(|node| graph[*node] == token)
-            .unwrap();
-        let cycles = ArbGraph::find_all_arbitrage_paths(&graph, start_node, 2);
+    // Build the graph over the working set and enumerate all of its cycles. With a cache
+    // dir the cycles are reloaded instead if nothing they depend on changed
+    pub fn new(working_pools: Vec<Pool>, config: &BotConfig) -> Self {
+        let start_tokens: Vec<Address> = config.bases.iter().map(|base| base.token).collect();
+        let Some(dir) = &config.cycles.cache_dir else {
+            return Self::build(working_pools, start_tokens, config.cycles.clone());
+        };
 
-        // form our swappaths
-        let swappaths: Vec<SwapPath> = cycles
-            .iter()
-            .map(|cycle| {
-                let mut hasher = DefaultHasher::new();
-                cycle.iter().for_each(|step| step.hash(&mut hasher));
-                let output_hash = hasher.finish();
-                SwapPath {
-                    steps: cycle.clone(),
-                    hash: output_hash,
-                }
-            })
-            .collect();
-        swappaths
+        let cache = CycleCache::new(dir, &working_pools, &start_tokens, &config.cycles);
+        if let Some(cycles) = cache.load() {
+            info!("Loaded {} cycles from the cache, key {}", cycles.len(), cache.key());
+            return Self::restore(working_pools, start_tokens, config.cycles.clone(), cycles);
+        }
+        let graph = Self::build(working_pools, start_tokens, config.cycles.clone());
+        match cache.save(&graph.cycles) {
+            Ok(()) => info!("Saved {} cycles to the cache, key {}", graph.cycles.len(), cache.key()),
+            Err(e) => warn!("Failed to save the cycle cache: {e:#}"),
+        }
+        graph
     }
 
-    // Build the graph from the working set of pools
-    pub fn build_graph(working_pools: Vec<Pool>) -> UnGraph<Address, Pool> {
-        let mut graph: UnGraph<Address, Pool> = UnGraph::new_undirected();
-        let mut inserted_nodes: HashSet<Address> = HashSet::new();
+    // Build the graph from the start tokens and cycle limits directly
+    pub fn build(working_pools: Vec<Pool>, start_tokens: Vec<Address>, config: CycleConfig) -> Self {
+        let mut graph = Self::empty(working_pools, start_tokens, config);
+        graph.cycles = graph.find_cycles(None);
+        graph.index_cycles();
+        graph
+    }
 
+    // Build the graph around cycles generated earlier. The search state is rebuilt from them
+    // so pools can still be added and removed incrementally
+    pub fn restore(
+        working_pools: Vec<Pool>,
+        start_tokens: Vec<Address>,
+        config: CycleConfig,
+        cycles: Vec<SwapPath>,
+    ) -> Self {
+        let mut graph = Self::empty(working_pools, start_tokens, config);
+        for path in &cycles {
+            graph.search.restore_cycle(&path.steps);
+        }
+        graph.cycles = cycles;
+        graph.index_cycles();
+        graph
+    }
+
+    // The graph over the pools without any cycles
+    fn empty(working_pools: Vec<Pool>, start_tokens: Vec<Address>, config: CycleConfig) -> Self {
+        let mut graph = Self {
+            graph: DiGraph::with_capacity(working_pools.len(), working_pools.len() * 2),
+            nodes: HashMap::with_capacity(working_pools.len()),
+            pools: Vec::with_capacity(working_pools.len()),
+            pool_index: HashMap::with_capacity(working_pools.len()),
+            start_tokens,
+            search: CycleSearch::new(config),
+            cycles: Vec::new(),
+            positions: HashMap::new(),
+        };
         for pool in working_pools {
-            // add the nodes ot the graph if they have not already been added
-            match pool {
-                Pool::BalancerV2(balancer_pool) => {
-                    Self::add_balancer_pool_to_graph(
-                        &mut graph,
-                        &mut inserted_nodes,
-                        balancer_pool,
-                    );
-                }
-                Pool::CurveTriCrypto(curve_pool) => {
-                    Self::add_curve_pool_to_graph(&mut graph, &mut inserted_nodes, curve_pool);
-                }
-                _ => {
-                    Self::add_simple_pool_to_graph(&mut graph, &mut inserted_nodes, pool);
-                }
-            }
+            graph.insert_pool(pool);
         }
         graph
     }
 
-    fn add_simple_pool_to_graph(
-        graph: &mut UnGraph<Address, Pool>,
-        inserted_nodes: &mut HashSet<Address>,
-        pool: Pool,
-    ) {
-        let token0 = pool.token0_address();
-        let token1 = pool.token1_address();
+    // Constructor, takes the set of working tokens we are interested in searching over
+    pub async fn generate_cycles(working_pools: Vec<Pool>, config: &BotConfig) -> Vec<SwapPath> {
+        Self::new(working_pools, config).cycles
+    }
 
-        // Add nodes if they don't exist
-        if !inserted_nodes.contains(&token0) {
-            graph.add_node(token0);
-            inserted_nodes.insert(token0);
-        }
-        if !inserted_nodes.contains(&token1) {
-            graph.add_node(token1);
-            inserted_nodes.insert(token1);
-        }
+    pub fn cycles(&self) -> &[SwapPath] {
+        &self.cycles
+    }
+
+    // A cycle in the graph by its hash
+    pub fn cycle(&self, hash: u64) -> Option<&SwapPath> {
+        self.positions.get(&hash).map(|&index| &self.cycles[index])
+    }
 
-        // Get the node indices
-        let node0 = graph.node_indices().find(|&n| graph[n] == token0).unwrap();
-        let node1 = graph.node_indices().find(|&n| graph[n] == token1).unwrap();
+    fn index_cycles(&mut self) {
+        self.positions = self
+            .cycles
+            .iter()
+            .enumerate()
+            .map(|(index, path)| (path.hash, index))
+            .collect();
+    }
 
-        // Add the edge (pool)
-        graph.add_edge(node0, node1, pool);
+    pub fn graph(&self) -> &DiGraph<Address, PoolEdge> {
+        &self.graph
     }
 
-    fn add_curve_pool_to_graph(
-        graph: &mut UnGraph<Address, Pool>,
-        inserted_nodes: &mut HashSet<Address>,
-        curve_pool: CurveTriCryptoPool,
-    ) {
-        let tokens = curve_pool.get_tokens();
+    // The node of a token, if any pool in the graph trades it
+    pub fn node_index(&self, token: &Address) -> Option<NodeIndex> {
+        self.nodes.get(token).copied()
+    }
 
-        // Add nodes for all tokens in the pool
-        for &token in &tokens {
-            if !inserted_nodes.contains(&token) {
-                graph.add_node(token);
-                inserted_nodes.insert(token);
+    // The pool an edge swaps through
+    pub fn pool(&self, edge: &PoolEdge) -> &Pool {
+        &self.pools[edge.pool as usize]
+    }
+
+    // The same swap through every other pool for the pair, the pools a step can be split
+    // across. Pools the path already swaps through are left out, splitting into one would
+    // price it as if the other swap through it never happened
+    pub fn parallel_steps(&self, step: &SwapStep, used: &HashSet<Address>) -> Vec<SwapStep> {
+        let (Some(from), Some(to)) = (self.node_index(&step.token_in), self.node_index(&step.token_out)) else {
+            return Vec::new();
+        };
+        self.graph
+            .edges_connecting(from, to)
+            .map(|edge| (edge.weight(), self.pool(edge.weight())))
+            .filter(|(_, pool)| pool.address() != step.pool_address && !used.contains(&pool.address()))
+            .filter(|(_, pool)| !matches!(pool, Pool::CurveTriCrypto(_)))
+            .map(|(edge, pool)| SwapStep {
+                pool_address: pool.address(),
+                token_in: step.token_in,
+                token_out: step.token_out,
+                protocol: pool.pool_type(),
+                fee: edge.fee,
+            })
+            .collect()
+    }
+
+    // Add pools to the graph, returning the cycles they complete. Pools already in the
+    // graph are ignored
+    pub fn add_pools(&mut self, pools: Vec<Pool>) -> CycleDelta {
+        let mut new_pools = HashSet::new();
+        for pool in pools {
+            if let Some(index) = self.insert_pool(pool) {
+                new_pools.insert(index);
             }
         }
+        if new_pools.is_empty() {
+            return CycleDelta::default();
+        }
 
-        // Add edges for all possible token pairs with non-zero balances
-        for (i, &token_in) in tokens.iter().enumerate() {
-            for &token_out in tokens.iter().skip(i + 1) {
-                // Only add edge if both balances are non-zero
-                let node_in = graph
-                    .node_indices()
-                    .find(|&n| graph[n] == token_in)
-                    .unwrap();
-                let node_out = graph
-                    .node_indices()
-                    .find(|&n| graph[n] == token_out)
-                    .unwrap();
-
-                // Create a new Pool::BalancerV2 for each edge
-                let pool = Pool::CurveTriCrypto(curve_pool.clone());
-                graph.add_edge(node_in, node_out, pool);
-            }
+        let added = self.find_cycles(Some(&new_pools));
+        for path in &added {
+            self.positions.insert(path.hash, self.cycles.len());
+            self.cycles.push(path.clone());
+        }
+        CycleDelta {
+            added,
+            retired: Vec::new(),
         }
     }
 
-    fn add_balancer_pool_to_graph(
-        graph: &mut UnGraph<Address, Pool>,
-        inserted_nodes: &mut HashSet<Address>,
-        balancer_pool: BalancerV2Pool,
-    ) {
-        let tokens = balancer_pool.get_tokens();
+    // Remove pools from the graph, returning the cycles that went through them. Cycles
+    // dropped earlier for the per pool cap are only picked up again by a rebuild
+    pub fn remove_pools(&mut self, pools: &HashSet<Address>) -> CycleDelta {
+        let removed: HashSet<u32> = pools
+            .iter()
+            .filter_map(|pool| self.pool_index.get(pool))
+            .copied()
+            .collect();
+        if removed.is_empty() {
+            return CycleDelta::default();
+        }
+        self.graph.retain_edges(|graph, edge| !removed.contains(&graph[edge].pool));
+        self.compact_pools(&removed);
 
-        // Add nodes for all tokens in the pool
-        for &token in &tokens {
-            if !inserted_nodes.contains(&token) {
-                graph.add_node(token);
-                inserted_nodes.insert(token);
+        let (retired, kept): (Vec<SwapPath>, Vec<SwapPath>) = std::mem::take(&mut self.cycles)
+            .into_iter()
+            .partition(|path| path.steps.iter().any(|step| pools.contains(&step.pool_address)));
+        self.cycles = kept;
+        self.index_cycles();
+        for path in &retired {
+            self.search.remove_cycle(&path.steps);
+        }
+        CycleDelta {
+            added: Vec::new(),
+            retired,
+        }
+    }
+
+    // Drop removed pools from the pool table and point the remaining edges at their new index
+    fn compact_pools(&mut self, removed: &HashSet<u32>) {
+        let mut remap = vec![None; self.pools.len()];
+        let mut kept = Vec::with_capacity(self.pools.len() - removed.len());
+        for (index, pool) in std::mem::take(&mut self.pools).into_iter().enumerate() {
+            if removed.contains(&(index as u32)) {
+                self.pool_index.remove(&pool.address());
+            } else {
+                remap[index] = Some(kept.len() as u32);
+                self.pool_index.insert(pool.address(), kept.len() as u32);
+                kept.push(pool);
+            }
+        }
+        self.pools = kept;
+        for edge in self.graph.edge_weights_mut() {
+            if let Some(index) = remap[edge.pool as usize] {
+                edge.pool = index;
             }
         }
+    }
 
-        // Add edges for all possible token pairs with non-zero balances
-        for (i, &token_in) in tokens.iter().enumerate() {
-            for &token_out in tokens.iter().skip(i + 1) {
-                let balance_in = balancer_pool.get_balance(&token_in);
-                let balance_out = balancer_pool.get_balance(&token_out);
-
-                // Only add edge if both balances are non-zero
-                if !balance_in.is_zero() && !balance_out.is_zero() {
-                    let node_in = graph
-                        .node_indices()
-                        .find(|&n| graph[n] == token_in)
-                        .unwrap();
-                    let node_out = graph
-                        .node_indices()
-                        .find(|&n| graph[n] == token_out)
-                        .unwrap();
-
-                    // Create a new Pool::BalancerV2 for each edge
-                    let pool = Pool::BalancerV2(balancer_pool.clone());
-                    graph.add_edge(node_in, node_out, pool);
-                }
+    // Add a pool to the graph if it is not already in it, returning its index
+    fn insert_pool(&mut self, pool: Pool) -> Option<u32> {
+        if self.pool_index.contains_key(&pool.address()) {
+            return None;
+        }
+        let index = self.pools.len() as u32;
+        let tokens = pool_tokens(&pool);
+        let edge = PoolEdge {
+            pool: index,
+            fee: pool.fee(),
+            liquidity: pool_liquidity(&pool),
+        };
+        self.pool_index.insert(pool.address(), index);
+        self.pools.push(pool);
+
+        // an edge each way between every pair of tokens
+        for (i, &token_a) in tokens.iter().enumerate() {
+            for &token_b in tokens.iter().skip(i + 1) {
+                let node_a = self.node(token_a);
+                let node_b = self.node(token_b);
+                self.graph.add_edge(node_a, node_b, edge);
+                self.graph.add_edge(node_b, node_a, edge);
             }
         }
+        Some(index)
     }
 
-    fn find_all_arbitrage_paths(
-        graph: &UnGraph<Address, Pool>,
-        start_node: NodeIndex,
-        max_hops: usize,
-    ) -> Vec<Vec<SwapStep>> {
-        //let mut all_paths = Vec::new();
-        let mut all_paths: Vec<Vec<SwapStep>> = Vec::new();
-        let mut current_path = Vec::new();
-        let mut visited = HashSet::new();
-
-        Self::construct_cycles(
-            graph,
-            start_node,
-            start_node,
-            max_hops,
-            &mut current_path,
-            &mut visited,
-            &mut all_paths,
-        );
+    // Get the node for a token, adding it if it does not exist
+    fn node(&mut self, token: Address) -> NodeIndex {
+        let graph = &mut self.graph;
+        *self.nodes.entry(token).or_insert_with(|| graph.add_node(token))
+    }
+
+    // Enumerate the cycles through each base that are not already known. If new pools are
+    // given, only cycles through at least one of them are searched
+    fn find_cycles(&mut self, new_pools: Option<&HashSet<u32>>) -> Vec<SwapPath> {
+        let new_pools = new_pools.map(|pools| NewPools::new(&self.graph, pools, self.search.config.max_hops));
+
+        let mut found = Vec::new();
+        for token in &self.start_tokens {
+            let Some(&start_node) = self.nodes.get(token) else {
+                continue;
+            };
+            let mut current_path = Vec::new();
+            let mut visited = HashSet::new();
+            Self::construct_cycles(
+                &self.graph,
+                &self.pools,
+                start_node,
+                start_node,
+                &mut current_path,
+                &mut visited,
+                &mut self.search,
+                new_pools.as_ref(),
+                &mut found,
+            );
+        }
 
-        all_paths
+        // form our swappaths
+        found.into_iter().map(SwapPath::new).collect()
     }
 
-    // Build all of the cycles
+    // Build all of the cycles. Each cycle is found once per direction by the dfs, so it
+    // is reduced to a canonical key before it is kept
+    #[allow(clippy::too_many_arguments)]
     fn construct_cycles(
-        graph: &UnGraph<Address, Pool>,
+        graph: &DiGraph<Address, PoolEdge>,
+        pools: &[Pool],
         current_node: NodeIndex,
         start_node: NodeIndex,
-        max_hops: usize,
-        current_path: &mut Vec<(NodeIndex, Pool, NodeIndex)>,
+        current_path: &mut Vec<(NodeIndex, PoolEdge, NodeIndex)>,
         visited: &mut HashSet<NodeIndex>,
-        all_paths: &mut Vec<Vec<SwapStep>>,
+        search: &mut CycleSearch,
+        new_pools: Option<&NewPools>,
+        found: &mut Vec<Vec<SwapStep>>,
     ) {
-        if current_path.len() >= max_hops {
+        if current_path.len() >= search.config.max_hops {
             return;
         }
+        if let Some(new_pools) = new_pools {
+            if !new_pools.reachable(current_node, current_path, search.config.max_hops) {
+                return;
+            }
+        }
 
-        for edge in graph.edges(current_node) {
-            let next_node = edge.target();
-            let protocol = edge.weight().clone();
+        // explore the deepest pools first so they claim the per pool cap before shallow ones
+        let mut edges: Vec<(NodeIndex, PoolEdge)> = graph
+            .edges(current_node)
+            .filter(|edge| {
+                search.should_explore(edge.weight(), &pools[edge.weight().pool as usize], current_path)
+            })
+            .map(|edge| (edge.target(), *edge.weight()))
+            .collect();
+        edges.sort_by(|a, b| b.1.liquidity.cmp(&a.1.liquidity));
 
+        for (next_node, edge) in edges {
             if next_node == start_node {
                 if current_path.len() >= 2
                     || (current_path.len() == 1
-                        && current_path[0].1.pool_type() != protocol.pool_type())
+                        && pools[current_path[0].1.pool as usize].pool_type()
+                            != pools[edge.pool as usize].pool_type())
                 {
                     let mut new_path = current_path.clone();
-                    new_path.push((current_node, protocol, next_node));
+                    new_path.push((current_node, edge, next_node));
 
                     let mut swap_path = Vec::new();
-                    for (base, pool, quote) in new_path.iter() {
+                    for (base, edge, quote) in new_path.iter() {
+                        let pool = &pools[edge.pool as usize];
                         let swap = SwapStep {
                             pool_address: pool.address(),
                             token_in: graph[*base],
                             token_out: graph[*quote],
                             protocol: pool.pool_type(),
-                            fee: pool.fee(),
+                            fee: edge.fee,
                         };
                         swap_path.push(swap);
                     }
 
-                    all_paths.push(swap_path);
+                    let through_new = new_pools.map_or(true, |new_pools| {
+                        new_path
+                            .iter()
+                            .any(|(_, edge, _)| new_pools.pools.contains(&edge.pool))
+                    });
+                    if through_new {
+                        search.add_cycle(swap_path, found);
+                    }
                 }
             } else if !visited.contains(&next_node) {
-                current_path.push((current_node, protocol, next_node));
+                current_path.push((current_node, edge, next_node));
                 visited.insert(next_node);
 
                 Self::construct_cycles(
                     graph,
+                    pools,
                     next_node,
                     start_node,
-                    max_hops,
                     current_path,
                     visited,
-                    all_paths,
+                    search,
+                    new_pools,
+                    found,
                 );
 
                 current_path.pop();
@@ -248,3 +387,392 @@ impl ArbGraph {
         }
     }
 }
+
+// Cycles kept so far and how many each pool is part of
+struct CycleSearch {
+    config: CycleConfig,
+    seen: HashSet<(Address, Vec<(Address, Address)>)>,
+    pool_counts: HashMap<Address, usize>,
+}
+
+impl CycleSearch {
+    fn new(config: CycleConfig) -> Self {
+        Self {
+            config,
+            seen: HashSet::new(),
+            pool_counts: HashMap::new(),
+        }
+    }
+
+    // A pool is skipped if it is already in the path, too shallow, or at its cap
+    fn should_explore(
+        &self,
+        edge: &PoolEdge,
+        pool: &Pool,
+        current_path: &[(NodeIndex, PoolEdge, NodeIndex)],
+    ) -> bool {
+        if current_path.iter().any(|(_, e, _)| e.pool == edge.pool) {
+            return false;
+        }
+        if let Some(liquidity) = edge.liquidity {
+            if liquidity < self.config.min_liquidity {
+                return false;
+            }
+        }
+        !self.at_cap(&pool.address())
+    }
+
+    fn at_cap(&self, pool: &Address) -> bool {
+        match self.config.max_cycles_per_pool {
+            Some(cap) => self.pool_counts.get(pool).copied().unwrap_or_default() >= cap,
+            None => false,
+        }
+    }
+
+    // Keep a cycle unless an equivalent one was already kept. Both directions of a cycle
+    // are different trades, only one of them can be profitable at a time, so they are kept
+    // together and count once against the per pool cap
+    fn add_cycle(&mut self, steps: Vec<SwapStep>, found: &mut Vec<Vec<SwapStep>>) {
+        if self.seen.contains(&canonical_key(&steps)) {
+            return;
+        }
+        if steps.iter().any(|step| self.at_cap(&step.pool_address)) {
+            return;
+        }
+        self.seen.insert(canonical_key(&steps));
+        for step in &steps {
+            *self.pool_counts.entry(step.pool_address).or_default() += 1;
+        }
+
+        let reversed = steps
+            .iter()
+            .rev()
+            .map(|step| SwapStep {
+                token_in: step.token_out,
+                token_out: step.token_in,
+                ..step.clone()
+            })
+            .collect();
+        found.push(steps);
+        found.push(reversed);
+    }
+
+    // Count a cycle found by an earlier run, its reverse shares the key so it counts once
+    fn restore_cycle(&mut self, steps: &[SwapStep]) {
+        if !self.seen.insert(canonical_key(steps)) {
+            return;
+        }
+        for step in steps {
+            *self.pool_counts.entry(step.pool_address).or_default() += 1;
+        }
+    }
+
+    // Forget a retired cycle, its reverse shares the key so the counts drop only once
+    fn remove_cycle(&mut self, steps: &[SwapStep]) {
+        if !self.seen.remove(&canonical_key(steps)) {
+            return;
+        }
+        for step in steps {
+            if let Some(count) = self.pool_counts.get_mut(&step.pool_address) {
+                *count = count.saturating_sub(1);
+            }
+        }
+    }
+}
+
+// Pools added to a running graph. Only cycles through one of them are new, so the dfs is
+// pruned once a new pool can no longer be reached within the hop limit
+struct NewPools {
+    pools: HashSet<u32>,
+    // hops from each node to the nearest token of a new pool
+    distance: HashMap<NodeIndex, usize>,
+}
+
+impl NewPools {
+    fn new(graph: &DiGraph<Address, PoolEdge>, pools: &HashSet<u32>, max_hops: usize) -> Self {
+        let mut distance = HashMap::new();
+        let mut queue = VecDeque::new();
+        for edge in graph.edge_references() {
+            if pools.contains(&edge.weight().pool) && distance.insert(edge.source(), 0).is_none() {
+                queue.push_back(edge.source());
+            }
+        }
+
+        // breadth first out to the hop limit
+        while let Some(node) = queue.pop_front() {
+            let hops = distance[&node] + 1;
+            if hops >= max_hops {
+                continue;
+            }
+            for neighbor in graph.neighbors(node) {
+                if !distance.contains_key(&neighbor) {
+                    distance.insert(neighbor, hops);
+                    queue.push_back(neighbor);
+                }
+            }
+        }
+
+        Self {
+            pools: pools.clone(),
+            distance,
+        }
+    }
+
+    // If the path has not used a new pool yet, there must be enough hops left to get to
+    // one and swap through it
+    fn reachable(
+        &self,
+        node: NodeIndex,
+        current_path: &[(NodeIndex, PoolEdge, NodeIndex)],
+        max_hops: usize,
+    ) -> bool {
+        if current_path
+            .iter()
+            .any(|(_, edge, _)| self.pools.contains(&edge.pool))
+        {
+            return true;
+        }
+        match self.distance.get(&node) {
+            Some(hops) => current_path.len() + hops + 1 <= max_hops,
+            None => false,
+        }
+    }
+}
+
+// Smallest rotation of the cycle in either direction, equal for all equivalent cycles. The
+// same loop from another base is a different trade, so the base is part of the key
+fn canonical_key(steps: &[SwapStep]) -> (Address, Vec<(Address, Address)>) {
+    let base = steps.first().map_or(Address::ZERO, |step| step.token_in);
+    let forward: Vec<(Address, Address)> = steps
+        .iter()
+        .map(|step| (step.pool_address, step.token_in))
+        .collect();
+    let reverse: Vec<(Address, Address)> = steps
+        .iter()
+        .rev()
+        .map(|step| (step.pool_address, step.token_out))
+        .collect();
+    let rotation = (0..steps.len())
+        .flat_map(|i| {
+            let mut forward = forward.clone();
+            let mut reverse = reverse.clone();
+            forward.rotate_left(i);
+            reverse.rotate_left(i);
+            [forward, reverse]
+        })
+        .min()
+        .unwrap_or_default();
+    (base, rotation)
+}
+
+// Tokens a pool swaps between. Balancer tokens without a balance can not be swapped
+pub fn pool_tokens(pool: &Pool) -> Vec<Address> {
+    match pool {
+        Pool::BalancerV2(balancer_pool) => balancer_pool
+            .get_tokens()
+            .into_iter()
+            .filter(|token| !balancer_pool.get_balance(token).is_zero())
+            .collect(),
+        Pool::CurveTriCrypto(curve_pool) => curve_pool.get_tokens(),
+        _ => vec![pool.token0_address(), pool.token1_address()],
+    }
+}
+
+// Liquidity of a two token pool, sqrt(reserve0 * reserve1) for v2 so that it compares with
+// v3 liquidity. Multi token pools are not measured and never pruned
+fn pool_liquidity(pool: &Pool) -> Option<U256> {
+    if pool.is_v2() {
+        let pool = pool.get_v2()?;
+        Some(pool.token0_reserves.saturating_mul(pool.token1_reserves).root(2))
+    } else if pool.is_v3() {
+        Some(U256::from(pool.get_v3()?.liquidity))
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod graph_tests {
+    use super::*;
+    use crate::tests::helpers::fixtures::pools::{pool, v2_pool, DAI, USDC, WETH};
+    use pool_sync::{PoolType, UniswapV2Pool};
+
+    // Two weth/usdc pools on different protocols, and a weth/usdc/dai triangle
+    fn pools(sushi_reserves: u128) -> Vec<Pool> {
+        vec![
+            pool(1, WETH, USDC),
+            Pool::SushiSwapV2(UniswapV2Pool {
+                token0_reserves: U256::from(sushi_reserves),
+                token1_reserves: U256::from(sushi_reserves),
+                ..v2_pool(2, WETH, USDC)
+            }),
+            pool(3, USDC, DAI),
+            pool(4, DAI, WETH),
+        ]
+    }
+
+    fn cycles(pools: Vec<Pool>, config: CycleConfig) -> Vec<SwapPath> {
+        ArbGraph::build(pools, vec![WETH], config).cycles
+    }
+
+    fn config(max_hops: usize) -> CycleConfig {
+        CycleConfig {
+            max_hops,
+            min_liquidity: U256::ZERO,
+            max_cycles_per_pool: None,
+            cache_dir: None,
+        }
+    }
+
+    // Each cycle is found once in each direction and closes back on weth
+    #[test]
+    fn test_hop_count() {
+        let two_hop = cycles(pools(1_000_000), config(2));
+        assert_eq!(two_hop.len(), 2);
+
+        let three_hop = cycles(pools(1_000_000), config(3));
+        assert_eq!(three_hop.len(), 6);
+        let unique: HashSet<&Vec<SwapStep>> = three_hop.iter().map(|path| &path.steps).collect();
+        assert_eq!(unique.len(), three_hop.len());
+        for path in &three_hop {
+            assert_eq!(path.steps.first().unwrap().token_in, WETH);
+            assert_eq!(path.steps.last().unwrap().token_out, WETH);
+            assert!(path.steps.windows(2).all(|w| w[0].token_out == w[1].token_in));
+        }
+    }
+
+    // Pools below the minimum liquidity are never routed through
+    #[test]
+    fn test_min_liquidity() {
+        let config = CycleConfig {
+            min_liquidity: U256::from(1000),
+            ..config(3)
+        };
+        let found = cycles(pools(10), config);
+        assert_eq!(found.len(), 2);
+        assert!(found
+            .iter()
+            .flat_map(|path| &path.steps)
+            .all(|step| step.pool_address != Address::with_last_byte(2)));
+    }
+
+    // No pool is part of more cycles than the cap, counting both directions once
+    #[test]
+    fn test_pool_cap() {
+        let config = CycleConfig {
+            max_cycles_per_pool: Some(1),
+            ..config(3)
+        };
+        let found = cycles(pools(1_000_000), config);
+        let mut counts: HashMap<Address, usize> = HashMap::new();
+        for step in found.iter().flat_map(|path| &path.steps) {
+            *counts.entry(step.pool_address).or_default() += 1;
+        }
+        assert!(counts.values().all(|count| *count <= 2));
+    }
+
+    // Adding a pool finds only the cycles it completes, removing it retires them again
+    #[test]
+    fn test_add_remove_pools() {
+        let mut all_pools = pools(1_000_000);
+        let dai_weth = all_pools.pop().unwrap();
+        let mut graph = ArbGraph::build(all_pools.clone(), vec![WETH], config(3));
+        assert_eq!(graph.cycles().len(), 2);
+
+        let delta = graph.add_pools(vec![dai_weth.clone()]);
+        assert_eq!(delta.added.len(), 4);
+        assert!(delta.retired.is_empty());
+        assert!(graph.add_pools(vec![dai_weth.clone()]).added.is_empty());
+
+        // the same cycles as building the graph from scratch
+        all_pools.push(dai_weth);
+        let rebuilt: HashSet<u64> = cycles(all_pools, config(3)).iter().map(|path| path.hash).collect();
+        let incremental: HashSet<u64> = graph.cycles().iter().map(|path| path.hash).collect();
+        assert_eq!(rebuilt, incremental);
+
+        let delta = graph.remove_pools(&HashSet::from([Address::with_last_byte(3)]));
+        assert_eq!(delta.retired.len(), 4);
+        assert_eq!(graph.cycles().len(), 2);
+        assert!(delta.retired.iter().all(|path| graph.cycle(path.hash).is_none()));
+        assert!(graph.cycles().iter().all(|path| graph.cycle(path.hash) == Some(path)));
+
+        // the remaining edges still point at their own pools after the table is compacted
+        assert_eq!(graph.graph().edge_count(), 6);
+        for edge in graph.graph().edge_references() {
+            let pool = graph.pool(edge.weight());
+            assert_ne!(pool.address(), Address::with_last_byte(3));
+            let tokens = [graph.graph()[edge.source()], graph.graph()[edge.target()]];
+            assert!(tokens.contains(&pool.token0_address()) && tokens.contains(&pool.token1_address()));
+        }
+    }
+
+    // Every other weth/usdc pool is parallel to a weth/usdc step
+    #[test]
+    fn test_parallel_steps() {
+        let graph = ArbGraph::build(pools(1_000_000), vec![WETH], config(2));
+        let step = SwapStep {
+            pool_address: Address::with_last_byte(1),
+            token_in: USDC,
+            token_out: WETH,
+            protocol: PoolType::UniswapV2,
+            fee: 0,
+        };
+        let parallel = graph.parallel_steps(&step, &HashSet::new());
+        assert_eq!(parallel.len(), 1);
+        assert_eq!(parallel[0].pool_address, Address::with_last_byte(2));
+        assert_eq!(parallel[0].protocol, PoolType::SushiSwapV2);
+        assert_eq!((parallel[0].token_in, parallel[0].token_out), (USDC, WETH));
+    }
+
+    // A pool that is a later step of the path is not parallel to an earlier one
+    #[test]
+    fn test_parallel_steps_skip_used() {
+        let mut pools = pools(1_000_000);
+        pools.push(pool(5, WETH, USDC));
+        let graph = ArbGraph::build(pools, vec![WETH], config(2));
+        let path = graph
+            .cycles()
+            .iter()
+            .find(|path| {
+                path.steps[0].pool_address == Address::with_last_byte(1)
+                    && path.steps[1].pool_address == Address::with_last_byte(2)
+            })
+            .unwrap();
+        let used: HashSet<Address> = path.steps.iter().map(|step| step.pool_address).collect();
+
+        let parallel = graph.parallel_steps(&path.steps[0], &used);
+        assert_eq!(parallel.len(), 1);
+        assert_eq!(parallel[0].pool_address, Address::with_last_byte(5));
+        let parallel = graph.parallel_steps(&path.steps[1], &used);
+        assert_eq!(parallel.len(), 1);
+        assert_eq!(parallel[0].pool_address, Address::with_last_byte(5));
+    }
+
+    // A graph restored from saved cycles tracks them like one that generated them
+    #[test]
+    fn test_restore() {
+        let built = ArbGraph::build(pools(1_000_000), vec![WETH], config(3));
+        let mut restored =
+            ArbGraph::restore(pools(1_000_000), vec![WETH], config(3), built.cycles().to_vec());
+        assert_eq!(restored.search.seen, built.search.seen);
+        assert_eq!(restored.search.pool_counts, built.search.pool_counts);
+
+        let removed = HashSet::from([Address::with_last_byte(4)]);
+        let dai_weth = pools(1_000_000).pop().unwrap();
+        assert_eq!(restored.remove_pools(&removed).retired.len(), 4);
+        assert_eq!(restored.add_pools(vec![dai_weth]).added.len(), 4);
+        assert_eq!(restored.cycles().len(), built.cycles().len());
+    }
+
+    // Every base gets its own cycles, the same loop from another base is kept
+    #[test]
+    fn test_multiple_bases() {
+        let graph = ArbGraph::build(pools(1_000_000), vec![WETH, USDC], config(3));
+        assert_eq!(graph.cycles().len(), 12);
+        for base in [WETH, USDC] {
+            let from_base = graph.cycles().iter().filter(|path| path.base_token() == base);
+            assert!(from_base.clone().all(|path| path.steps.last().unwrap().token_out == base));
+            assert_eq!(from_base.count(), 6);
+        }
+    }
+}
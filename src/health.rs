@@ -0,0 +1,245 @@
+use bytes::Bytes;
+use http_body_util::Full;
+use hyper::body::Incoming;
+use hyper::server::conn::http1;
+use hyper::service::service_fn;
+use hyper::{Request, Response, StatusCode};
+use hyper_util::rt::TokioIo;
+use log::{debug, info, warn};
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, RwLock};
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+
+use crate::metrics;
+use crate::supervisor::{wait_for_shutdown, ShutdownSignal};
+
+// Lifecycle of the bot, from startup until it is searching live blocks
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Stage {
+    SyncingPools,
+    Filtering,
+    CatchingUp,
+    Estimating,
+    GeneratingCycles,
+    Live,
+    ShuttingDown,
+}
+
+// Liveness of a single supervised worker
+#[derive(Clone, Debug, Serialize)]
+pub struct WorkerHealth {
+    pub alive: bool,
+    pub restarts: u32,
+}
+
+// Point in time view of the pipeline, served on the health endpoint
+#[derive(Debug, Serialize)]
+pub struct HealthSnapshot {
+    pub stage: Stage,
+    pub ready: bool,
+    pub caught_up: bool,
+    pub last_block: u64,
+    pub head_block: u64,
+    pub lag: u64,
+    pub workers: BTreeMap<String, WorkerHealth>,
+}
+
+// Shared health state. Workers report into this and the endpoint reads from it
+pub struct Health {
+    stage: watch::Sender<Stage>,
+    caught_up: watch::Sender<bool>,
+    last_block: AtomicU64,
+    head_block: AtomicU64,
+    workers: RwLock<BTreeMap<String, WorkerHealth>>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self {
+            stage: watch::channel(Stage::SyncingPools).0,
+            caught_up: watch::channel(false).0,
+            last_block: AtomicU64::new(0),
+            head_block: AtomicU64::new(0),
+            workers: RwLock::new(BTreeMap::new()),
+        }
+    }
+
+    // Move the bot to a new stage
+    pub fn set_stage(&self, stage: Stage) {
+        info!("Entering stage {:?}", stage);
+        self.stage.send_replace(stage);
+    }
+
+    pub fn stage(&self) -> Stage {
+        *self.stage.borrow()
+    }
+
+    // Mark whether the market state has caught up to the head of the chain
+    pub fn set_caught_up(&self, caught_up: bool) {
+        self.caught_up.send_replace(caught_up);
+    }
+
+    // Wait until the market state has caught up to the head of the chain
+    pub async fn wait_until_caught_up(&self) {
+        let mut caught_up = self.caught_up.subscribe();
+        let _ = caught_up.wait_for(|caught_up| *caught_up).await;
+    }
+
+    // Record the last block our state has been updated to
+    pub fn block_processed(&self, block_number: u64) {
+        self.last_block.store(block_number, Ordering::Relaxed);
+        self.head_seen(block_number);
+    }
+
+    // Record the latest block we know the chain is at
+    pub fn head_seen(&self, block_number: u64) {
+        self.head_block.fetch_max(block_number, Ordering::Relaxed);
+    }
+
+    // Latest block we know the chain is at
+    pub fn head_block(&self) -> u64 {
+        self.head_block.load(Ordering::Relaxed)
+    }
+
+    // An event produced for a block older than the head is stale and should be dropped
+    pub fn is_stale(&self, block_number: u64) -> bool {
+        block_number < self.head_block()
+    }
+
+    // Record that a supervised worker has been started
+    pub fn worker_started(&self, name: &str, restarts: u32) {
+        let mut workers = self.workers.write().unwrap();
+        workers.insert(name.to_string(), WorkerHealth { alive: true, restarts });
+    }
+
+    // Record that a supervised worker has stopped
+    pub fn worker_stopped(&self, name: &str) {
+        let mut workers = self.workers.write().unwrap();
+        if let Some(worker) = workers.get_mut(name) {
+            worker.alive = false;
+        }
+    }
+
+    // We are ready once we are live, caught up, and every worker is running
+    pub fn is_ready(&self) -> bool {
+        self.stage() == Stage::Live
+            && *self.caught_up.borrow()
+            && self.workers.read().unwrap().values().all(|worker| worker.alive)
+    }
+
+    pub fn snapshot(&self) -> HealthSnapshot {
+        let last_block = self.last_block.load(Ordering::Relaxed);
+        let head_block = self.head_block.load(Ordering::Relaxed);
+        HealthSnapshot {
+            stage: self.stage(),
+            ready: self.is_ready(),
+            caught_up: *self.caught_up.borrow(),
+            last_block,
+            head_block,
+            lag: head_block.saturating_sub(last_block),
+            workers: self.workers.read().unwrap().clone(),
+        }
+    }
+}
+
+// Serve the health and metrics endpoints until shutdown
+//   /health  -> always 200 with the full snapshot, for liveness
+//   /ready   -> 200 once the pipeline is live, 503 otherwise, for gating traffic
+//   /metrics -> prometheus metrics for the whole pipeline
+pub async fn serve_health(addr: SocketAddr, health: Arc<Health>, mut shutdown: ShutdownSignal) {
+    let listener = TcpListener::bind(addr).await.unwrap();
+    info!("Health and metrics endpoint listening on {}", addr);
+
+    loop {
+        let stream = tokio::select! {
+            _ = wait_for_shutdown(&mut shutdown) => return,
+            conn = listener.accept() => match conn {
+                Ok((stream, _)) => stream,
+                Err(e) => {
+                    warn!("Failed to accept health connection: {}", e);
+                    continue;
+                }
+            },
+        };
+
+        let health = health.clone();
+        tokio::spawn(async move {
+            let service = service_fn(move |req: Request<Incoming>| {
+                let (status, content_type, body) = respond(req.uri().path(), &health);
+                let response = Response::builder()
+                    .status(status)
+                    .header("content-type", content_type)
+                    .body(Full::new(Bytes::from(body)))
+                    .unwrap();
+                async move { Ok::<_, Infallible>(response) }
+            });
+            if let Err(e) = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await
+            {
+                debug!("Health connection error: {}", e);
+            }
+        });
+    }
+}
+
+// Route a request to its status, content type, and body
+fn respond(path: &str, health: &Health) -> (StatusCode, &'static str, String) {
+    const JSON: &str = "application/json";
+    match path {
+        "/health" => (StatusCode::OK, JSON, serde_json::to_string(&health.snapshot()).unwrap()),
+        "/ready" => {
+            let snapshot = health.snapshot();
+            let status = if snapshot.ready {
+                StatusCode::OK
+            } else {
+                StatusCode::SERVICE_UNAVAILABLE
+            };
+            (status, JSON, serde_json::to_string(&snapshot).unwrap())
+        }
+        "/metrics" => (StatusCode::OK, "text/plain; version=0.0.4", metrics::gather()),
+        _ => (StatusCode::NOT_FOUND, JSON, String::new()),
+    }
+}
+
+#[cfg(test)]
+mod health_tests {
+    use super::*;
+
+    // Only ready once live, caught up, and all workers are running
+    #[test]
+    fn test_readiness() {
+        let health = Health::new();
+        health.worker_started("state updater", 0);
+        assert_eq!(respond("/ready", &health).0, StatusCode::SERVICE_UNAVAILABLE);
+
+        health.set_caught_up(true);
+        health.set_stage(Stage::Live);
+        assert_eq!(respond("/ready", &health).0, StatusCode::OK);
+
+        health.worker_stopped("state updater");
+        assert_eq!(respond("/ready", &health).0, StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(respond("/health", &health).0, StatusCode::OK);
+        assert_eq!(respond("/metrics", &health).0, StatusCode::OK);
+        assert_eq!(respond("/missing", &health).0, StatusCode::NOT_FOUND);
+    }
+
+    // Lag is how far our processed state is behind the head
+    #[test]
+    fn test_lag() {
+        let health = Health::new();
+        health.head_seen(110);
+        health.block_processed(100);
+        let snapshot = health.snapshot();
+        assert_eq!(snapshot.lag, 10);
+        assert_eq!(snapshot.head_block, 110);
+        assert!(health.is_stale(109));
+        assert!(!health.is_stale(110));
+    }
+}
@@ -22,6 +22,8 @@ use std::path::Path;
 use std::sync::atomic::{AtomicU64, Ordering};
 use std::sync::{Arc, RwLock};
 
+use crate::errors::StateError;
+
 // Main structure for the Node Database
 pub struct HistoryDB {
     db_provider: StateProviderBox,
@@ -48,7 +50,7 @@ impl HistoryDB {
             );
 
 
-        let provider = factory.history_by_block_number(block).expect("Unable to create provider");
+        let provider = factory.history_by_block_number(block)?;
 
 
         Ok(Self {
@@ -60,14 +62,14 @@ impl HistoryDB {
 
 
 impl Database for HistoryDB {
-    type Error = eyre::Error;
+    type Error = StateError;
 
     fn basic(&mut self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
         Self::basic_ref(self, address)
     }
 
-    fn code_by_hash(&mut self, _code_hash: B256) -> Result<Bytecode, Self::Error> {
-        panic!("This should not be called, as the code is already loaded");
+    fn code_by_hash(&mut self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        Self::code_by_hash_ref(self, code_hash)
     }
 
     fn storage(&mut self, address: Address, index: U256) -> Result<U256, Self::Error> {
@@ -80,7 +82,7 @@ impl Database for HistoryDB {
 }
 
 impl DatabaseRef for HistoryDB {
-    type Error = eyre::Error;
+    type Error = StateError;
 
     fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
         let account = self.db_provider.basic_account(&address).unwrap_or_default().unwrap_or_default();
@@ -103,12 +105,16 @@ impl DatabaseRef for HistoryDB {
         Ok(Some(account_info))
     }
 
-    fn code_by_hash_ref(&self, _code_hash: B256) -> Result<Bytecode, Self::Error> {
-        panic!("This should not be called, as the code is already loaded");
+    // code is returned with the account, so this should never be called
+    fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
+        Err(StateError::MissingCode(code_hash))
     }
 
     fn storage_ref(&self, address: Address, index: U256) -> Result<U256, Self::Error> {
-        let value = self.db_provider.storage(address, StorageKey::from(index))?;
+        let value = self
+            .db_provider
+            .storage(address, StorageKey::from(index))
+            .map_err(|e| StateError::Provider(e.to_string()))?;
 
         Ok(value.unwrap_or_default())
     }
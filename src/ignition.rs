@@ -1,15 +1,18 @@
+use alloy::primitives::Address;
 use alloy::providers::ProviderBuilder;
+use anyhow::Result;
 use log::info;
-use pool_sync::{Chain, Pool};
-use std::sync::mpsc;
-use std::thread;
-use std::sync::Arc;
-use std::sync::atomic::AtomicBool;
-use std::sync::atomic::Ordering::Relaxed;
+use pool_sync::{Pool, PoolInfo};
+use std::collections::HashSet;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{broadcast, mpsc};
 
-use crate::events::Event;
-use crate::filter::filter_pools;
+use crate::config::BotConfig;
+use crate::events::{Event, PoolDelta};
+use crate::filter::{filter_pools, refresh_working_set};
 use crate::graph::ArbGraph;
+use crate::health::{Health, Stage};
+use crate::journal::{Journal, JournalRecord, JournalWriter};
 use crate::market_state::MarketState;
 use crate::searcher::Searchoor;
 use crate::simulator::simulate_paths;
@@ -17,80 +20,189 @@ use crate::stream::stream_new_blocks;
 use crate::tx_sender::TransactionSender;
 use crate::gas_station::GasStation;
 use crate::estimator::Estimator;
+use crate::supervisor::{RestartPolicy, Supervisor};
 
-/// Start all of the workers
-pub async fn start_workers(pools: Vec<Pool>, last_synced_block: u64) {
-    // all of the sender and receiversb
-    let (block_sender, block_receiver) = tokio::sync::broadcast::channel::<Event>(100);
-    let (address_sender, address_receiver) = mpsc::channel::<Event>();
-    let (paths_sender, paths_receiver) = mpsc::channel::<Event>();
-    let (profitable_sender, profitable_receiver) = mpsc::channel::<Event>();
+/// Start all of the workers under the supervisor. Stops early without an error if a signal
+/// or a failing worker stops the pipeline during startup, the supervisor then reports it
+pub async fn start_workers(
+    supervisor: &mut Supervisor,
+    health: Arc<Health>,
+    pools: Vec<Pool>,
+    last_synced_block: u64,
+    config: Arc<BotConfig>,
+) -> Result<()> {
+    // all of the sender and receivers. Every channel is bounded so a slow stage applies
+    // backpressure instead of letting work for old blocks pile up
+    let (block_sender, block_receiver) = broadcast::channel::<Event>(100);
+    let (address_sender, address_receiver) = mpsc::channel::<Event>(config.channel_capacity);
+    let (paths_sender, paths_receiver) = mpsc::channel::<Event>(config.channel_capacity);
+    let (profitable_sender, profitable_receiver) = mpsc::channel::<Event>(config.channel_capacity);
+    let (delta_sender, delta_receiver) = mpsc::channel::<PoolDelta>(config.channel_capacity);
 
     // filter the pools here to smartly select the working set
+    health.set_stage(Stage::Filtering);
     info!("Pool count before filter {}", pools.len());
-    let pools = filter_pools(pools, 4000, Chain::Base).await;
+    let Some(pools) = supervisor
+        .run_until(filter_pools(pools, config.num_tokens, &config))
+        .await?
+    else {
+        return Ok(());
+    };
     info!("Pool count after filter {}", pools.len());
 
+    // the block stream, gas station and state updater can pick up where they left off
+    // so they are restarted
+    let restart = RestartPolicy::from_config(&config.supervisor);
+
     // start the block stream so we dont miss any blocks
-    tokio::spawn(stream_new_blocks(block_sender));
+    supervisor.spawn_restartable("block stream", restart, {
+        let block_sender = block_sender.clone();
+        let ipc_path = config.ipc_path.clone();
+        let health = health.clone();
+        let shutdown = supervisor.shutdown_signal();
+        move || {
+            stream_new_blocks(block_sender.clone(), ipc_path.clone(), health.clone(), shutdown.clone())
+        }
+    });
 
     // Construct and start the gas station
-    let gas_station = Arc::new(GasStation::new());
-    tokio::spawn( {
+    let gas_station = Arc::new(GasStation::new(config.chain.base_fee_params));
+    supervisor.spawn_restartable("gas station", restart, {
         let gas_station = gas_station.clone();
-        let block_rx = block_receiver.resubscribe();
-        async move { gas_station.update_gas(block_rx).await }
+        let block_sender = block_sender.clone();
+        let shutdown = supervisor.shutdown_signal();
+        move || {
+            let gas_station = gas_station.clone();
+            let block_rx = block_sender.subscribe();
+            let shutdown = shutdown.clone();
+            async move { gas_station.update_gas(block_rx, shutdown).await }
+        }
     });
 
-    // Signal for if the blocks are caught up 
-    let caught_up = Arc::new(AtomicBool::new(false));
-
     // Initialize our market state, this is a wrapper over the REVM database with all our pool state
     // then start the updater
     info!("Initializing market state...");
-    let http_url = std::env::var("FULL").unwrap().parse().unwrap();
-    let provider = ProviderBuilder::new().on_http(http_url);
-    let market_state = MarketState::init_state_and_start_stream(
-        pools.clone(),
-        block_receiver,
-        address_sender,
-        last_synced_block,
-        provider,
-        caught_up.clone()
-    )
-    .await
-    .unwrap();
+    let provider = ProviderBuilder::new().on_http(config.http_url.clone());
+    let init = MarketState::init_state(pools.clone(), last_synced_block, provider, &config);
+    let Some(market_state) = supervisor.run_until(init).await? else {
+        return Ok(());
+    };
+    let market_state = market_state?;
+
+    // start the journal writer before any worker so every event is recorded, it stops once
+    // every worker holding the journal has stopped. Each rotated file starts from a
+    // checkpoint of the market state so pruning old files never leaves nothing to replay from
+    let journal = match &config.journal {
+        Some(journal_config) => {
+            let writer = JournalWriter::new(journal_config)?.with_checkpoints({
+                let market_state = market_state.clone();
+                move || market_state.checkpoint()
+            });
+            let (journal, journal_rx) = Journal::new(config.channel_capacity * 16);
+            supervisor.spawn_blocking("journal", move || writer.run(journal_rx));
+            journal
+        }
+        None => Journal::disabled(),
+    };
+
+    // record the starting state so the journal can be replayed offline
+    if journal.is_enabled() {
+        let state = market_state.db.read().unwrap().snapshot();
+        journal.record(JournalRecord::Snapshot {
+            block_number: last_synced_block,
+            pools: pools.clone(),
+            state,
+        });
+    }
+    health.set_stage(Stage::CatchingUp);
+    supervisor.spawn_restartable("state updater", restart, {
+        let market_state = market_state.clone();
+        let health = health.clone();
+        let journal = journal.clone();
+        let config = config.clone();
+        let shutdown = supervisor.shutdown_signal();
+        // the first run uses the receiver created before the stream started so no blocks are missed
+        let mut block_rx = Some(block_receiver);
+        move || {
+            let block_rx = block_rx.take().unwrap_or_else(|| block_sender.subscribe());
+            market_state.clone().state_updater(
+                block_rx,
+                address_sender.clone(),
+                health.clone(),
+                journal.clone(),
+                config.clone(),
+                shutdown.clone(),
+            )
+        }
+    });
     info!("Initialized market state!");
         
     // Construct and populate the estimator
-    // wait until we have caught up to all the blocks before we start estimating the rates
+    // wait until we have caught up to all the blocks before we start estimating the rates.
+    // The state updater can run out of restarts while catching up, so keep supervising
+    if supervisor.run_until(health.wait_until_caught_up()).await?.is_none() {
+        return Ok(());
+    }
+    health.set_stage(Stage::Estimating);
     info!("Calculating initial rates in estimator...");
-    let mut estimator = Estimator::new(market_state.clone());
-    // spin why we are not caught up, then calculate rates for the updates pools
-    while !caught_up.load(Relaxed) {}
+    let mut estimator = Estimator::new(market_state.clone(), config.clone());
     estimator.process_pools(pools.clone());
     info!("Calculated initial rates!");
 
     // generate the graph
+    health.set_stage(Stage::GeneratingCycles);
     info!("Generating cycles...");
-    let cycles = ArbGraph::generate_cycles(pools.clone()).await;
-    info!("Generated {} cycles", cycles.len());
+    let graph = ArbGraph::new(pools.clone(), &config);
+    info!("Generated {} cycles", graph.cycles().len());
 
     // start the simulator
     info!("Starting the simulator...");
-    tokio::spawn(simulate_paths(
-        profitable_sender,
-        paths_receiver,
-        market_state.clone(),
-    ));
+    supervisor.spawn(
+        "simulator",
+        simulate_paths(
+            profitable_sender,
+            paths_receiver,
+            market_state.clone(),
+            health.clone(),
+            journal.clone(),
+            config.clone(),
+        ),
+    );
 
     // start the searcher
     info!("Starting arbitrage searcher...");
-    let mut searcher = Searchoor::new(cycles, market_state.clone(), estimator);
-    thread::spawn(move || searcher.search_paths(paths_sender, address_receiver));
+    let mut searcher = Searchoor::new(graph, market_state.clone(), estimator, config.clone());
+    supervisor.spawn_blocking("searcher", move || {
+        searcher.search_paths(paths_sender, address_receiver, delta_receiver, journal)
+    });
+
+    // refresh the working set, the searcher picks up the changes between blocks
+    if let Some(interval) = config.refresh_interval {
+        let working_set: HashSet<Address> = pools.iter().map(|pool| pool.address()).collect();
+        let working_set = Arc::new(Mutex::new(working_set));
+        let shutdown = supervisor.shutdown_signal();
+        supervisor.spawn_restartable("pool refresher", restart, {
+            let config = config.clone();
+            move || {
+                refresh_working_set(
+                    working_set.clone(),
+                    delta_sender.clone(),
+                    interval,
+                    config.clone(),
+                    shutdown.clone(),
+                )
+            }
+        });
+    }
 
     // start the tx sender
     info!("Starting transaction sender...");
-    let mut tx_sender = TransactionSender::new(gas_station.clone()).await;
-    tokio::spawn(async move { tx_sender.send_transactions(profitable_receiver).await });
+    let mut tx_sender = TransactionSender::new(gas_station.clone(), config.clone()).await?;
+    supervisor.spawn("tx sender", {
+        let health = health.clone();
+        async move { tx_sender.send_transactions(profitable_receiver, health).await }
+    });
+
+    health.set_stage(Stage::Live);
+    Ok(())
 }
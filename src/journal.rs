@@ -0,0 +1,520 @@
+use alloy::primitives::{Address, U256};
+use alloy::rpc::types::trace::geth::AccountState;
+use alloy::rpc::types::Header;
+use anyhow::{Context, Result};
+use log::{error, info, warn};
+use pool_sync::Pool;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, VecDeque};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Lines, Write};
+use std::path::{Path, PathBuf};
+use tokio::sync::mpsc::{self, error::TrySendError};
+
+use crate::config::JournalConfig;
+use crate::events::Event;
+use crate::gen::FlashQuoter::SwapParams;
+use crate::metrics::JOURNAL_DROPPED;
+use crate::state_db::StateSnapshot;
+use crate::swap::SwapPath;
+
+// A single line in the journal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct JournalEntry {
+    // unix time in milliseconds the record was taken
+    pub timestamp_ms: i64,
+    #[serde(flatten)]
+    pub record: JournalRecord,
+}
+
+// Everything the pipeline saw, in the order it saw it
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum JournalRecord {
+    // working set and full db state once the market state is initialized, the
+    // starting point for replay
+    Snapshot {
+        block_number: u64,
+        pools: Vec<Pool>,
+        state: StateSnapshot,
+    },
+    // the same as a snapshot but taken while running, every rotated file starts with one.
+    // Blocks up to block_number are already applied to the state
+    Checkpoint {
+        block_number: u64,
+        pools: Vec<Pool>,
+        state: StateSnapshot,
+    },
+    NewBlock {
+        header: Header,
+    },
+    // post state of every account touched in the block, as returned by debug_trace_block
+    StateDiff {
+        block_number: u64,
+        diffs: Vec<BTreeMap<Address, AccountState>>,
+    },
+    PoolsTouched {
+        block_number: u64,
+        pools: Vec<Address>,
+    },
+    // pools that joined or left the working set before block_number was searched. The
+    // state is the db once the added pools were loaded, none if nothing was added
+    PoolDelta {
+        block_number: u64,
+        added: Vec<Pool>,
+        removed: Vec<Address>,
+        state: Option<StateSnapshot>,
+    },
+    ArbPath {
+        block_number: u64,
+        path: SwapPath,
+        output: U256,
+    },
+    ValidPath {
+        block_number: u64,
+        // none in journals recorded before bases were configurable, see migrate
+        #[serde(default)]
+        token_in: Option<Address>,
+        pools: Vec<Address>,
+        pool_versions: Vec<u8>,
+        // token every pool swaps into, recorded since balancer pools were supported
+        #[serde(default)]
+        tokens_out: Vec<Address>,
+        // share of its leg per pool, empty if no leg is split
+        #[serde(default)]
+        splits: Vec<u16>,
+        amount_in: U256,
+        profit: U256,
+    },
+}
+
+impl From<&Event> for JournalRecord {
+    fn from(event: &Event) -> Self {
+        match event {
+            Event::NewBlock(header) => JournalRecord::NewBlock {
+                header: header.clone(),
+            },
+            Event::PoolsTouched(pools, block_number) => JournalRecord::PoolsTouched {
+                block_number: *block_number,
+                pools: pools.iter().copied().collect(),
+            },
+            Event::ArbPath((path, output, block_number)) => JournalRecord::ArbPath {
+                block_number: *block_number,
+                path: path.clone(),
+                output: *output,
+            },
+            Event::ValidPath((params, profit, block_number)) => {
+                let SwapParams {
+                    tokenIn,
+                    pools,
+                    poolVersions,
+                    tokensOut,
+                    splits,
+                    amountIn,
+                } = params.clone();
+                JournalRecord::ValidPath {
+                    block_number: *block_number,
+                    token_in: Some(tokenIn),
+                    pools,
+                    pool_versions: poolVersions,
+                    tokens_out: tokensOut,
+                    splits,
+                    amount_in: amountIn,
+                    profit: *profit,
+                }
+            }
+        }
+    }
+}
+
+impl JournalRecord {
+    // Fill in what older journals did not record. Before bases were configurable every
+    // path started in the weth of the chain the journal was recorded on
+    pub fn migrate(&mut self, weth: Address) {
+        if let JournalRecord::ValidPath { token_in, .. } = self {
+            token_in.get_or_insert(weth);
+        }
+    }
+}
+
+// Cheap handle workers use to record into the journal. Recording never blocks, if the
+// writer falls behind the record is dropped and counted
+#[derive(Clone)]
+pub struct Journal {
+    tx: Option<mpsc::Sender<JournalEntry>>,
+}
+
+impl Journal {
+    // Create a journal along with the receiver the writer consumes
+    pub fn new(capacity: usize) -> (Self, mpsc::Receiver<JournalEntry>) {
+        let (tx, rx) = mpsc::channel(capacity);
+        (Self { tx: Some(tx) }, rx)
+    }
+
+    // A journal that records nothing
+    pub fn disabled() -> Self {
+        Self { tx: None }
+    }
+
+    pub fn is_enabled(&self) -> bool {
+        self.tx.is_some()
+    }
+
+    pub fn record(&self, record: JournalRecord) {
+        let Some(tx) = &self.tx else {
+            return;
+        };
+        let entry = JournalEntry {
+            timestamp_ms: chrono::Utc::now().timestamp_millis(),
+            record,
+        };
+        match tx.try_send(entry) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => JOURNAL_DROPPED.inc(),
+            Err(TrySendError::Closed(_)) => warn!("Journal writer has stopped"),
+        }
+    }
+
+    pub fn record_event(&self, event: &Event) {
+        if self.tx.is_some() {
+            self.record(event.into());
+        }
+    }
+}
+
+// Writes journal entries as json lines, rotating to a new file once the current
+// one is full and removing the oldest files past the limit
+pub struct JournalWriter {
+    dir: PathBuf,
+    max_file_bytes: u64,
+    max_files: usize,
+    file: Option<BufWriter<File>>,
+    written: u64,
+    sequence: u64,
+    // source of the checkpoint every rotated file starts with
+    checkpoint: Option<Box<dyn Fn() -> JournalRecord + Send>>,
+}
+
+impl JournalWriter {
+    pub fn new(config: &JournalConfig) -> Result<Self> {
+        fs::create_dir_all(&config.dir)
+            .with_context(|| format!("Failed to create journal dir {}", config.dir.display()))?;
+        Ok(Self {
+            dir: config.dir.clone(),
+            max_file_bytes: config.max_file_bytes,
+            max_files: config.max_files,
+            file: None,
+            written: 0,
+            sequence: 0,
+            checkpoint: None,
+        })
+    }
+
+    // Start every rotated file with a checkpoint so replay can begin from any file that
+    // survives pruning
+    pub fn with_checkpoints(mut self, checkpoint: impl Fn() -> JournalRecord + Send + 'static) -> Self {
+        self.checkpoint = Some(Box::new(checkpoint));
+        self
+    }
+
+    // Write entries until every journal handle has been dropped. Runs on a blocking thread
+    pub fn run(mut self, mut rx: mpsc::Receiver<JournalEntry>) {
+        info!("Journaling to {}", self.dir.display());
+        while let Some(entry) = rx.blocking_recv() {
+            if let Err(e) = self.write(&entry) {
+                error!("Failed to write journal entry: {:#}", e);
+            }
+            // flush once we have caught up with the queue
+            if rx.is_empty() {
+                if let Err(e) = self.flush() {
+                    error!("Failed to flush journal: {:#}", e);
+                }
+            }
+        }
+        let _ = self.flush();
+        info!("Journal closed");
+    }
+
+    pub fn write(&mut self, entry: &JournalEntry) -> Result<()> {
+        if self.file.is_none() || self.written >= self.max_file_bytes {
+            self.rotate()?;
+        }
+        let mut line = serde_json::to_vec(entry)?;
+        line.push(b'\n');
+        self.file.as_mut().unwrap().write_all(&line)?;
+        self.written += line.len() as u64;
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        if let Some(file) = self.file.as_mut() {
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    // Start a new file and prune old ones
+    fn rotate(&mut self) -> Result<()> {
+        self.flush()?;
+        // the first file starts with the snapshot recorded at startup
+        let rotated = self.file.is_some();
+        let name = format!(
+            "journal-{:013}-{:06}.jsonl",
+            chrono::Utc::now().timestamp_millis(),
+            self.sequence
+        );
+        self.sequence += 1;
+        let path = self.dir.join(name);
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open journal file {}", path.display()))?;
+        let mut file = BufWriter::new(file);
+        if let Some(checkpoint) = self.checkpoint.as_ref().filter(|_| rotated) {
+            let entry = JournalEntry {
+                timestamp_ms: chrono::Utc::now().timestamp_millis(),
+                record: checkpoint(),
+            };
+            let mut line = serde_json::to_vec(&entry)?;
+            line.push(b'\n');
+            file.write_all(&line)?;
+        }
+        // the checkpoint does not count towards the size, a state larger than the limit
+        // would rotate on every entry otherwise
+        self.file = Some(file);
+        self.written = 0;
+
+        let files = journal_files(&self.dir)?;
+        if files.len() > self.max_files {
+            for old in &files[..files.len() - self.max_files] {
+                fs::remove_file(old)
+                    .with_context(|| format!("Failed to remove journal file {}", old.display()))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// All journal files in a directory, oldest first
+pub fn journal_files(dir: &Path) -> Result<Vec<PathBuf>> {
+    let mut files: Vec<PathBuf> = fs::read_dir(dir)
+        .with_context(|| format!("Failed to read journal dir {}", dir.display()))?
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| {
+            path.file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("journal-") && name.ends_with(".jsonl"))
+        })
+        .collect();
+    files.sort();
+    Ok(files)
+}
+
+// Reads every entry in a journal directory in the order it was written
+pub struct JournalReader {
+    files: VecDeque<PathBuf>,
+    lines: Option<Lines<BufReader<File>>>,
+}
+
+impl JournalReader {
+    pub fn open(dir: &Path) -> Result<Self> {
+        Ok(Self {
+            files: journal_files(dir)?.into(),
+            lines: None,
+        })
+    }
+}
+
+impl Iterator for JournalReader {
+    type Item = Result<JournalEntry>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(lines) = self.lines.as_mut() {
+                match lines.next() {
+                    Some(Ok(line)) if line.trim().is_empty() => continue,
+                    Some(Ok(line)) => {
+                        return Some(serde_json::from_str(&line).context("Invalid journal entry"));
+                    }
+                    Some(Err(e)) => return Some(Err(e.into())),
+                    None => self.lines = None,
+                }
+            }
+
+            let path = self.files.pop_front()?;
+            match File::open(&path) {
+                Ok(file) => self.lines = Some(BufReader::new(file).lines()),
+                Err(e) => {
+                    return Some(Err(anyhow::Error::new(e)
+                        .context(format!("Failed to open journal file {}", path.display()))));
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod journal_tests {
+    use super::*;
+    use crate::replay::replay_journal;
+    use crate::tests::helpers::test_utils::utils::test_config;
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("journal_tests_{}_{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    // Entries should survive a round trip through the file
+    #[test]
+    fn test_write_and_read_back() {
+        let dir = test_dir("roundtrip");
+        let config = JournalConfig {
+            dir: dir.clone(),
+            max_file_bytes: 1 << 20,
+            max_files: 4,
+        };
+        let (journal, rx) = Journal::new(16);
+        journal.record_event(&Event::PoolsTouched(HashSet::from([Address::ZERO]), 10));
+        drop(journal);
+        JournalWriter::new(&config).unwrap().run(rx);
+
+        assert_eq!(journal_files(&dir).unwrap().len(), 1);
+        let entries: Vec<JournalEntry> = JournalReader::open(&dir)
+            .unwrap()
+            .collect::<Result<_>>()
+            .unwrap();
+        assert_eq!(entries.len(), 1);
+        match entries[0].record.clone() {
+            JournalRecord::PoolsTouched { block_number, pools } => {
+                assert_eq!(block_number, 10);
+                assert_eq!(pools, vec![Address::ZERO]);
+            }
+            _ => panic!("unexpected record"),
+        }
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    // Valid paths recorded before bases were configurable start in weth once migrated
+    #[test]
+    fn test_migrate_token_in() {
+        let weth = Address::with_last_byte(6);
+        let old = r#"{"timestamp_ms":0,"kind":"valid_path","block_number":1,"pools":[],"pool_versions":[],"amount_in":"0x1","profit":"0x0"}"#;
+        let mut entry: JournalEntry = serde_json::from_str(old).unwrap();
+        entry.record.migrate(weth);
+        match entry.record {
+            JournalRecord::ValidPath { token_in, .. } => assert_eq!(token_in, Some(weth)),
+            _ => panic!("unexpected record"),
+        }
+
+        // recorded tokens are kept
+        let base = Address::with_last_byte(7);
+        let mut record = JournalRecord::ValidPath {
+            block_number: 1,
+            token_in: Some(base),
+            pools: vec![],
+            pool_versions: vec![],
+            tokens_out: vec![],
+            splits: vec![],
+            amount_in: U256::from(1),
+            profit: U256::ZERO,
+        };
+        record.migrate(weth);
+        assert!(matches!(record, JournalRecord::ValidPath { token_in: Some(token), .. } if token == base));
+    }
+
+    // Full files should be rotated and only the newest kept
+    #[test]
+    fn test_rotation() {
+        let dir = test_dir("rotation");
+        let config = JournalConfig {
+            dir: dir.clone(),
+            max_file_bytes: 1,
+            max_files: 2,
+        };
+        let mut writer = JournalWriter::new(&config).unwrap();
+        for block_number in 0..5 {
+            let entry = JournalEntry {
+                timestamp_ms: 0,
+                record: JournalRecord::PoolsTouched {
+                    block_number,
+                    pools: vec![],
+                },
+            };
+            writer.write(&entry).unwrap();
+        }
+        writer.flush().unwrap();
+
+        let files = journal_files(&dir).unwrap();
+        assert_eq!(files.len(), 2);
+        let last = fs::read_to_string(files.last().unwrap()).unwrap();
+        assert!(last.contains("\"block_number\":4"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    // Once the file with the startup snapshot is pruned, replay starts from the checkpoint
+    // at the head of the oldest file left
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_rotation_replay() {
+        let dir = test_dir("rotation_replay");
+        let config = JournalConfig {
+            dir: dir.clone(),
+            max_file_bytes: 1,
+            max_files: 2,
+        };
+        let applied = Arc::new(AtomicU64::new(0));
+        let mut writer = JournalWriter::new(&config).unwrap().with_checkpoints({
+            let applied = applied.clone();
+            move || JournalRecord::Checkpoint {
+                block_number: applied.load(Ordering::Relaxed),
+                pools: vec![],
+                state: StateSnapshot::default(),
+            }
+        });
+        let write = |writer: &mut JournalWriter, record| {
+            let entry = JournalEntry {
+                timestamp_ms: 0,
+                record,
+            };
+            writer.write(&entry).unwrap();
+        };
+        write(
+            &mut writer,
+            JournalRecord::Snapshot {
+                block_number: 0,
+                pools: vec![],
+                state: StateSnapshot::default(),
+            },
+        );
+        for block_number in 1..=5 {
+            write(
+                &mut writer,
+                JournalRecord::StateDiff {
+                    block_number,
+                    diffs: vec![],
+                },
+            );
+            applied.store(block_number, Ordering::Relaxed);
+        }
+        writer.flush().unwrap();
+
+        // the snapshot is gone, every file left starts with a checkpoint
+        let files = journal_files(&dir).unwrap();
+        assert_eq!(files.len(), 2);
+        for file in &files {
+            let contents = fs::read_to_string(file).unwrap();
+            let first: JournalEntry = serde_json::from_str(contents.lines().next().unwrap()).unwrap();
+            assert!(matches!(first.record, JournalRecord::Checkpoint { .. }));
+        }
+
+        // the oldest file left starts after block 3 and holds block 4, the last one block 5
+        let report = replay_journal(&dir, test_config()).await.unwrap();
+        assert_eq!(report.start_block, 3);
+        assert_eq!(report.blocks, 2);
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
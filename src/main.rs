@@ -1,37 +1,51 @@
-use alloy::{eips::{BlockId, BlockNumberOrTag}, primitives::U256, providers::Provider, rpc::types::{BlockTransactions, BlockTransactionsKind}};
+use alloy::{eips::{BlockId, BlockNumberOrTag}, providers::Provider, rpc::types::{BlockTransactions, BlockTransactionsKind}};
 use anyhow::Result;
+use cli::{Cli, Command};
+use config::BotConfig;
+use health::{serve_health, Health, Stage};
 use ignition::start_workers;
-use lazy_static::lazy_static;
-use log::{info, LevelFilter};
-use pool_sync::*;
+use log::LevelFilter;
+use replay::replay_journal;
+use std::sync::Arc;
+use supervisor::{RestartPolicy, Supervisor};
 
 mod bytecode;
 mod cache;
 mod calculation;
+mod calibration;
+mod chain;
+mod cli;
+mod commands;
+mod config;
+mod cycle_cache;
+mod errors;
 mod estimator;
 mod events;
+mod export;
 mod filter;
 mod gas_station;
 mod gen;
 mod graph;
+mod health;
 mod ignition;
+mod journal;
 mod market_state;
+mod metrics;
+mod negative_cycle;
 mod quoter;
+mod replay;
+mod router;
 mod searcher;
 mod simulator;
 mod state_db;
 mod stream;
+mod supervisor;
 mod swap;
 mod tests;
 mod tracing;
 mod tx_sender;
 mod history_db;
 
-// initial amount we are trying to arb over
-lazy_static! {
-    pub static ref AMOUNT: U256 = U256::from(1e15); 
-}
-
 #[tokio::main]
 async fn main() -> Result<()> {
     // init dots and logger
@@ -40,30 +54,57 @@ async fn main() -> Result<()> {
         .filter_module("BaseBuster", LevelFilter::Info)
         .init();
 
-    // Load in all the pools
-    info!("Loading and syncing pools...");
-    let pool_sync = PoolSync::builder()
-        .add_pools(&[
-            PoolType::UniswapV2,
-            PoolType::PancakeSwapV2,
-            PoolType::SushiSwapV2,
-            PoolType::UniswapV3,
-            PoolType::SushiSwapV3,
-            PoolType::BaseSwapV2,
-            PoolType::BaseSwapV3,
-            //PoolType::Aerodrome,
-            PoolType::Slipstream,
-            PoolType::AlienBaseV2,
-            PoolType::AlienBaseV3
-        ])
-        .chain(Chain::Base)
-        .rate_limit(1000)
-        .build()?;
-    let (pools, last_synced_block) = pool_sync.sync_pools().await?;
+    // Parse the command line, then load and validate the config
+    let cli = match Cli::parse(std::env::args().skip(1)) {
+        Ok(cli) => cli,
+        Err(e) => {
+            eprintln!("{e}");
+            std::process::exit(2);
+        }
+    };
+    let config = Arc::new(BotConfig::load(cli.config_path.as_deref())?);
 
-    start_workers(pools, last_synced_block).await;
-    loop {
-        tokio::time::sleep(std::time::Duration::from_secs(1000)).await;
+    match cli.command {
+        Command::Run => run(config).await,
+        Command::SyncPools => commands::print_pool_counts(&config).await,
+        Command::Filter => commands::print_working_set(&config).await,
+        Command::Cycles => commands::print_cycles(&config).await,
+        Command::Quote(pools) => commands::quote(config, pools).await,
+        Command::TraceBlock(block_number) => commands::trace_block(&config, block_number).await,
+        Command::Route {
+            token_in,
+            token_out,
+            amount,
+            max_hops,
+        } => commands::route(config, token_in, token_out, amount, max_hops).await,
+        Command::Export(dir) => commands::export(config, &dir).await,
+        Command::Replay(dir) => {
+            let report = replay_journal(&dir, config).await?;
+            report.log();
+            Ok(())
+        }
     }
-    Ok(())
+}
+
+// Sync, filter, and search live blocks until we are told to stop
+async fn run(config: Arc<BotConfig>) -> Result<()> {
+    // The supervisor owns every worker. Start the health endpoint first so
+    // orchestration can see us while we sync
+    let health = Arc::new(Health::new());
+    let mut supervisor = Supervisor::new(config.supervisor.drain_timeout, health.clone());
+    let restart = RestartPolicy::from_config(&config.supervisor);
+    supervisor.spawn_restartable("health server", restart, {
+        let health = health.clone();
+        let addr = config.health_addr;
+        let shutdown = supervisor.shutdown_signal();
+        move || serve_health(addr, health.clone(), shutdown.clone())
+    });
+
+    // Load in all the pools
+    health.set_stage(Stage::SyncingPools);
+    let (pools, last_synced_block) = commands::sync_pools(&config).await?;
+
+    // run until we are told to stop or a worker fails for good
+    start_workers(&mut supervisor, health, pools, last_synced_block, config).await?;
+    supervisor.run().await
 }
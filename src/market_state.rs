@@ -1,32 +1,40 @@
 use alloy::network::Network;
 use alloy::primitives::{address, Address, U256};
 use alloy::providers::{Provider, ProviderBuilder, RootProvider};
+use alloy::rpc::types::trace::geth::AccountState;
 use alloy::rpc::types::BlockNumberOrTag;
 use alloy::sol_types::{SolCall, SolValue};
 use alloy::transports::http::{Client, Http};
 use alloy::transports::Transport;
-use anyhow::Result;
-use log::{debug, error, info};
+use anyhow::{anyhow, ensure, Result};
+use log::{debug, error, info, warn};
 use pool_sync::Pool;
 use pool_sync::PoolInfo;
 use revm::primitives::keccak256;
 use revm::primitives::{AccountInfo, Bytecode, TransactTo};
 use revm::Evm;
-use std::collections::HashSet;
-use std::sync::mpsc::Sender;
+use std::collections::{BTreeMap, HashSet};
 use std::sync::Arc;
 use std::sync::RwLock;
 use std::time::Instant;
+use tokio::sync::broadcast::error::RecvError;
 use tokio::sync::broadcast::Receiver;
+use tokio::sync::mpsc::Sender;
 use std::sync::atomic::Ordering;
-use std::sync::atomic::AtomicBool;
+use std::sync::atomic::AtomicU64;
 
+use crate::config::BotConfig;
 use crate::events::Event;
 use crate::gen::ERC20Token;
 use crate::gen::FlashQuoter;
-use crate::state_db::{BlockStateDB, InsertionType};
+use crate::health::Health;
+use crate::journal::{Journal, JournalRecord};
+use crate::metrics::{BLOCKS_PROCESSED, BLOCK_PROCESSING_SECONDS, LAGGED_BLOCKS, POOLS_TOUCHED};
+use crate::quoter::dispatches;
+use crate::state_db::{BlockStateDB, InsertionType, StateSnapshot, BALANCER_VAULT};
+use crate::swap::pool_version;
+use crate::supervisor::{detached_signal, wait_for_shutdown, ShutdownSignal};
 use crate::tracing::debug_trace_block;
-use crate::AMOUNT;
 
 // Internal representation of the current state of the blockchain
 pub struct MarketState<T, N, P>
@@ -36,6 +44,8 @@ where
     P: Provider<T, N>,
 {
     pub db: RwLock<BlockStateDB<T, N, P>>,
+    // last block our state has been updated to, a restarted updater resumes from here
+    pub last_block: AtomicU64,
 }
 
 impl<T, N, P> MarketState<T, N, P>
@@ -45,53 +55,97 @@ where
     P: Provider<T, N> + 'static + Clone,
 {
     // constuct the market state with a populated db
-    pub async fn init_state_and_start_stream(
-        pools: Vec<Pool>,          // the pools we are searching over
-        block_rx: Receiver<Event>, // receiver for new blocks
-        address_tx: Sender<Event>, // sender for touched addresses in a block
-        last_synced_block: u64,    // the last block that was synced too
+    pub async fn init_state(
+        pools: Vec<Pool>,       // the pools we are searching over
+        last_synced_block: u64, // the last block that was synced too
         provider: P,
-        caught_up: Arc<AtomicBool>
+        config: &BotConfig,
     ) -> Result<Arc<Self>> {
         debug!("Populating the db with {} pools", pools.len());
 
+        // a quoter built from an older FlashQuoter.sol reverts every quote
+        ensure!(
+            dispatches(&FlashQuoter::DEPLOYED_BYTECODE, FlashQuoter::quoteArbitrageCall::SELECTOR),
+            "src/abi/FlashQuoter.json does not match the quoter abi, run forge build and copy the artifact"
+        );
+
         // construct, warm up, and populate the db
         let mut db = BlockStateDB::new(provider).unwrap();
-        Self::warm_up_database(&pools, &mut db);
-        Self::populate_db_with_pools(pools.clone(), &mut db);
+        Self::warm_up_database(&pools, &mut db, config);
+        Self::populate_db_with_pools(pools, &mut db);
 
-        // init the market state with the db
-        let market_state = Arc::new(Self {
+        Ok(Arc::new(Self {
             db: RwLock::new(db),
-        });
+            last_block: AtomicU64::new(last_synced_block),
+        }))
+    }
+
+    // constuct the market state from a journaled snapshot. The db is offline so
+    // the provider is never queried
+    pub fn from_snapshot(
+        pools: Vec<Pool>,
+        snapshot: StateSnapshot,
+        block_number: u64,
+        provider: P,
+    ) -> Result<Arc<Self>> {
+        debug!("Restoring the db with {} pools at block {}", pools.len(), block_number);
+
+        let mut db = BlockStateDB::new_offline(provider)
+            .ok_or_else(|| anyhow!("Failed to construct offline db"))?;
+        db.restore(pools, snapshot);
+
+        Ok(Arc::new(Self {
+            db: RwLock::new(db),
+            last_block: AtomicU64::new(block_number),
+        }))
+    }
+
+    // constuct the market state and start an unsupervised updater
+    pub async fn init_state_and_start_stream(
+        pools: Vec<Pool>,          // the pools we are searching over
+        block_rx: Receiver<Event>, // receiver for new blocks
+        address_tx: Sender<Event>, // sender for touched addresses in a block
+        last_synced_block: u64,    // the last block that was synced too
+        provider: P,
+        health: Arc<Health>,
+        config: Arc<BotConfig>,
+    ) -> Result<Arc<Self>> {
+        let market_state = Self::init_state(pools, last_synced_block, provider, &config).await?;
 
         // start the state updater
-        tokio::spawn(Self::state_updater(
-            market_state.clone(),
+        tokio::spawn(market_state.clone().state_updater(
             block_rx,
             address_tx,
-            last_synced_block,
-            caught_up,
+            health,
+            Journal::disabled(),
+            config,
+            detached_signal(),
         ));
 
         Ok(market_state)
     }
 
-    // task to retrieve new blockchain state and update our db
-    async fn state_updater(
+    // task to retrieve new blockchain state and update our db. Resumes from the
+    // last block we processed so it can be restarted
+    pub async fn state_updater(
         self: Arc<Self>,
         mut block_rx: Receiver<Event>,
         address_tx: Sender<Event>,
-        mut last_synced_block: u64,
-        caught_up: Arc<AtomicBool>
+        health: Arc<Health>,
+        journal: Journal,
+        config: Arc<BotConfig>,
+        mut shutdown: ShutdownSignal,
     ) {
+        let mut last_synced_block = self.last_block.load(Ordering::Relaxed);
+
         // setup a provider for tracing
-        let http_url = std::env::var("FULL").unwrap().parse().unwrap();
-        let http = Arc::new(ProviderBuilder::new().on_http(http_url));
+        let http = Arc::new(ProviderBuilder::new().on_http(config.http_url.clone()));
 
         // fast block times mean we can fall behind while initializing
         // catch up to the head to we are not missing any state
+        health.set_caught_up(false);
         let mut current_block = http.get_block_number().await.unwrap();
+        health.head_seen(current_block);
 
         while last_synced_block < current_block {
             debug!(
@@ -100,17 +154,40 @@ where
             );
             for block_num in (last_synced_block + 1)..=current_block {
                 debug!("Processing block {block_num}");
-                let _ = self.update_state(http.clone(), block_num).await;
+                let _ = self.update_state(http.clone(), block_num, &journal).await;
+                self.last_block.store(block_num, Ordering::Relaxed);
+                health.block_processed(block_num);
             }
             last_synced_block = current_block;
             current_block = http.get_block_number().await.unwrap();
+            health.head_seen(current_block);
         }
 
         // signal that we are caught up
-        caught_up.store(true, Ordering::Relaxed);
+        health.set_caught_up(true);
 
-        // stream in new blocks
-        while let Ok(Event::NewBlock(block_header)) = block_rx.recv().await {
+        // stream in new blocks until shutdown
+        loop {
+            let block_header = tokio::select! {
+                _ = wait_for_shutdown(&mut shutdown) => {
+                    info!("State updater shutting down");
+                    return;
+                }
+                event = block_rx.recv() => match event {
+                    Ok(Event::NewBlock(block_header)) => block_header,
+                    Ok(_) => continue,
+                    // the next block traces the state forward, missed headers only cost freshness
+                    Err(RecvError::Lagged(missed)) => {
+                        warn!("State updater lagged behind, missed {missed} block headers");
+                        LAGGED_BLOCKS.inc_by(missed);
+                        continue;
+                    }
+                    Err(RecvError::Closed) => return,
+                },
+            };
+            journal.record(JournalRecord::NewBlock {
+                header: block_header.clone(),
+            });
             let start = Instant::now();
             let block_number = block_header.inner.number;
 
@@ -123,22 +200,29 @@ where
 
             // update the state and get the list of updated pools
             debug!("Processing block {block_number}");
-            let updated_pools = self.update_state(http.clone(), block_number).await;
+            let updated_pools = self.update_state(http.clone(), block_number, &journal).await;
             debug!("Processed the block {block_number}");
 
             // send the updated pools
+            BLOCK_PROCESSING_SECONDS.observe(start.elapsed().as_secs_f64());
+            BLOCKS_PROCESSED.inc();
+            POOLS_TOUCHED.observe(updated_pools.len() as f64);
             info!(
                 "Block processed {} updates and sent in {:?}",
                 updated_pools.len(),
                 start.elapsed()
             );
-            if let Err(e) = address_tx.send(Event::PoolsTouched(updated_pools, block_number)) {
+            let event = Event::PoolsTouched(updated_pools, block_number);
+            journal.record_event(&event);
+            if let Err(e) = address_tx.send(event).await {
                 error!("Failed to send updated pools: {}", e);
             } else {
                 debug!("Sent updated addresses for block {}", block_number);
             }
 
             last_synced_block = block_number;
+            self.last_block.store(block_number, Ordering::Relaxed);
+            health.block_processed(block_number);
         }
     }
 
@@ -147,34 +231,115 @@ where
         &self,
         provider: Arc<RootProvider<Http<Client>>>,
         block_num: u64,
+        journal: &Journal,
     ) -> HashSet<Address> {
-        // all of the pools that were updated in this block
-        let mut updated_pools: HashSet<Address> = HashSet::new();
-
         // trace the block to get all post state changes
         let updates = debug_trace_block(provider, BlockNumberOrTag::Number(block_num), true).await;
+        let updated_pools = self.apply_diffs(&updates);
+        journal.record(JournalRecord::StateDiff {
+            block_number: block_num,
+            diffs: updates,
+        });
+        updated_pools
+    }
+
+    // Apply the post state of a block to the db, returning the pools that were touched
+    pub fn apply_diffs(&self, updates: &[BTreeMap<Address, AccountState>]) -> HashSet<Address> {
+        // all of the pools that were updated in this block
+        let mut updated_pools: HashSet<Address> = HashSet::new();
 
         // aquire write access so we can update the db and go over all updates
         let mut db = self.db.write().unwrap();
         for (address, account_state) in updates.iter().flat_map(|btree_map| btree_map.iter()) {
             if db.tracking_pool(address) {
                 debug!("Updating state for pool {address}");
-                db.update_all_slots(*address, account_state.clone())
-                    .unwrap();
+                if let Err(e) = db.update_all_slots(*address, account_state.clone()) {
+                    warn!("Failed to update state for pool {}: {:#}", address, e);
+                    continue;
+                }
                 updated_pools.insert(*address);
+            } else if *address == BALANCER_VAULT {
+                // balancer balances live in the Vault, the slots that changed tell which pools
+                let slots: HashSet<U256> = account_state.storage.keys().map(|slot| (*slot).into()).collect();
+                let pools = db.balancer_pools_at(&slots);
+                if let Err(e) = db.update_all_slots(*address, account_state.clone()) {
+                    warn!("Failed to update state for the balancer vault: {:#}", e);
+                    continue;
+                }
+                updated_pools.extend(pools);
+            }
+        }
+
+        // decode the touched balancer pools again from the updated storage
+        let balancer_pools: Vec<Address> =
+            updated_pools.iter().filter(|pool| db.is_balancer(pool)).copied().collect();
+        for pool in balancer_pools {
+            if let Err(e) = db.refresh_balancer(pool) {
+                warn!("Failed to refresh balancer pool {}: {}", pool, e);
             }
         }
 
         updated_pools
     }
 
+    // Load new pools into the running db, returning the ones that are now tracked
+    pub fn add_pools(&self, pools: Vec<Pool>, config: &BotConfig) -> HashSet<Address> {
+        let mut db = self.db.write().unwrap();
+        let pools: Vec<Pool> = pools
+            .into_iter()
+            .filter(|pool| !db.tracking_pool(&pool.address()))
+            .collect();
+        let addresses: Vec<Address> = pools.iter().map(|pool| pool.address()).collect();
+        debug!("Adding {} pools to the db", pools.len());
+
+        Self::warm_up_database(&pools, &mut db, config);
+        Self::populate_db_with_pools(pools, &mut db);
+        addresses
+            .into_iter()
+            .filter(|address| db.tracking_pool(address))
+            .collect()
+    }
+
+    // Load journaled pools and state into the db without fetching anything
+    pub fn restore(&self, pools: Vec<Pool>, snapshot: StateSnapshot) {
+        self.db.write().unwrap().restore(pools, snapshot);
+    }
+
+    // The working set and full db state as of the last block applied, written at the head
+    // of every rotated journal file
+    pub fn checkpoint(&self) -> JournalRecord {
+        let db = self.db.read().unwrap();
+        JournalRecord::Checkpoint {
+            block_number: self.last_block.load(Ordering::Relaxed),
+            pools: db.pool_info.values().cloned().collect(),
+            state: db.snapshot(),
+        }
+    }
+
+    // Stop tracking pools, their state is no longer updated
+    pub fn remove_pools(&self, pools: &HashSet<Address>) {
+        let mut db = self.db.write().unwrap();
+        for pool in pools {
+            db.remove_pool(pool);
+        }
+    }
+
     // Insert pool information into the database
     fn populate_db_with_pools(pools: Vec<Pool>, db: &mut BlockStateDB<T, N, P>) {
         for pool in pools {
-            if pool.is_v2() {
-                db.insert_v2(pool);
+            let address = pool.address();
+            let inserted = if pool.is_v2() {
+                db.insert_v2(pool).map_err(anyhow::Error::from)
             } else if pool.is_v3() {
-                db.insert_v3(pool).unwrap();
+                db.insert_v3(pool)
+            } else if matches!(pool, Pool::BalancerV2(_)) {
+                db.insert_balancer(pool).map_err(anyhow::Error::from)
+            } else {
+                Ok(())
+            };
+            // one bad pool should not keep us from starting
+            if let Err(e) = inserted {
+                warn!("Skipping pool {}: {:#}", address, e);
             }
         }
     }
@@ -182,7 +347,7 @@ where
     // this function will insert any approvals/balances we need and also
     // fetch extraneous contracts/values needed for simulation swaps and
     // insert into the db
-    fn warm_up_database(pools: &Vec<Pool>, db: &mut BlockStateDB<T, N, P>) {
+    fn warm_up_database(pools: &Vec<Pool>, db: &mut BlockStateDB<T, N, P>, config: &BotConfig) {
         // state addresses
         let account = address!("d8da6bf26964af9d7eed9e03e53415d37aa96045");
         let quoter: Address = address!("0000000000000000000000000000000000001000");
@@ -201,19 +366,59 @@ where
         };
         db.insert_account_info(quoter, quoter_acc_info, InsertionType::Custom);
 
-        // go over all the pools and try to simulate a swap.
-        // we have already filtered all of these pools, so we can assume
-        // that these are good to go and load up db with info
-        for pool in pools {
-            // give some balance of the input token
+        // the quoter reads the v2 factory fees from storage, seed them from the chain profile
+        for (factory, fee) in &config.chain.v2_factories {
+            let fee_slot = keccak256((*factory, U256::ZERO).abi_encode());
+            db.insert_account_storage(quoter, fee_slot.into(), U256::from(*fee), InsertionType::Custom)
+                .unwrap();
+        }
+
+        // every path starts in a base, give the account enough of each to quote and optimize
+        // any input and approve the quoter to spend all of it
+        for base in &config.bases {
+            let base_slot = keccak256((account, U256::from(base.balance_slot)).abi_encode());
             db.insert_account_storage(
-                pool.token0_address(),
-                balance_slot.into(),
-                ten_units,
+                base.token,
+                base_slot.into(),
+                base.amount.saturating_mul(U256::from(1000)),
                 InsertionType::OnChain,
             )
             .unwrap();
 
+            let approve_calldata = ERC20Token::approveCall {
+                spender: quoter,
+                amount: U256::MAX,
+            }
+            .abi_encode();
+            let mut evm = Evm::builder()
+                .with_db(&mut *db)
+                .modify_tx_env(|tx| {
+                    tx.caller = account;
+                    tx.data = approve_calldata.into();
+                    tx.transact_to = TransactTo::Call(base.token);
+                })
+                .build();
+            evm.transact_commit().unwrap();
+        }
+
+        // go over all the pools and try to simulate a swap.
+        // we have already filtered all of these pools, so we can assume
+        // that these are good to go and load up db with info
+        for pool in pools {
+            let token_in = pool.token0_address();
+
+            // give some balance of the input token, bases were already funded above
+            let is_base = config.base(&token_in).is_some();
+            if !is_base {
+                db.insert_account_storage(
+                    token_in,
+                    balance_slot.into(),
+                    ten_units,
+                    InsertionType::OnChain,
+                )
+                .unwrap();
+            }
+
             // approve the quoter to spend the input token
             let approve_calldata = ERC20Token::approveCall {
                 spender: quoter,
@@ -225,22 +430,21 @@ where
                 .modify_tx_env(|tx| {
                     tx.caller = account;
                     tx.data = approve_calldata.into();
-                    tx.transact_to = TransactTo::Call(pool.token0_address());
+                    tx.transact_to = TransactTo::Call(token_in);
                 })
                 .build();
-            evm.transact_commit().unwrap();
+            if !is_base {
+                evm.transact_commit().unwrap();
+            }
 
             // Try to do the swap from input to output token
-            let is_v3 = if pool.is_v3() {
-                1
-            } else {
-                0
-            };
-
             let quote_path = FlashQuoter::SwapParams {
+                tokenIn: token_in,
                 pools: vec![pool.address()],
-                poolVersions: vec![is_v3],
-                amountIn: *AMOUNT
+                poolVersions: vec![pool_version(pool.pool_type())],
+                tokensOut: vec![pool.token1_address()],
+                splits: Vec::new(),
+                amountIn: config.base_amount(&token_in)
             };
 
             let quote_calldata = FlashQuoter::quoteArbitrageCall {
@@ -0,0 +1,135 @@
+use lazy_static::lazy_static;
+use prometheus::{
+    register_counter, register_gauge, register_histogram, register_histogram_vec,
+    register_int_counter, register_int_counter_vec, register_int_gauge, Counter, Encoder, Gauge,
+    Histogram, HistogramVec, IntCounter, IntCounterVec, IntGauge, TextEncoder,
+};
+
+// Metrics for every stage of the pipeline, registered in the default registry and
+// served on the /metrics endpoint
+lazy_static! {
+    // channels
+    pub static ref STALE_EVENTS: IntCounterVec = register_int_counter_vec!(
+        "stale_events_total",
+        "Events dropped because they were produced for a block older than the head",
+        &["stage"]
+    )
+    .unwrap();
+    pub static ref LAGGED_BLOCKS: IntCounter = register_int_counter!(
+        "lagged_blocks_total",
+        "Block headers the state updater missed because it fell behind the block stream"
+    )
+    .unwrap();
+
+    // journal
+    pub static ref JOURNAL_DROPPED: IntCounter = register_int_counter!(
+        "journal_dropped_total",
+        "Journal records dropped because the writer fell behind"
+    )
+    .unwrap();
+
+    // state updater
+    pub static ref BLOCK_PROCESSING_SECONDS: Histogram = register_histogram!(
+        "block_processing_seconds",
+        "Time taken to trace a block and update the market state",
+        vec![0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0, 2.0, 5.0]
+    )
+    .unwrap();
+    pub static ref BLOCKS_PROCESSED: IntCounter =
+        register_int_counter!("blocks_processed_total", "Blocks applied to the market state").unwrap();
+    pub static ref POOLS_TOUCHED: Histogram = register_histogram!(
+        "pools_touched_per_block",
+        "Number of tracked pools updated in a block",
+        vec![0.0, 1.0, 5.0, 10.0, 25.0, 50.0, 100.0, 250.0, 500.0]
+    )
+    .unwrap();
+
+    // searcher
+    pub static ref AFFECTED_PATHS: IntCounter =
+        register_int_counter!("affected_paths_total", "Paths touched by updated pools").unwrap();
+    pub static ref ESTIMATED_PATHS: IntCounter = register_int_counter!(
+        "estimated_profitable_paths_total",
+        "Paths the estimator flagged as profitable"
+    )
+    .unwrap();
+    pub static ref CALCULATED_PATHS: IntCounter = register_int_counter!(
+        "calculated_profitable_paths_total",
+        "Paths confirmed profitable by the calculator and sent to the simulator"
+    )
+    .unwrap();
+    pub static ref SPLIT_PATHS: IntCounter = register_int_counter!(
+        "split_paths_total",
+        "Paths whose output improved by splitting legs across parallel pools"
+    )
+    .unwrap();
+    pub static ref DETECTED_CYCLES: IntCounter = register_int_counter!(
+        "detected_cycles_total",
+        "Negative cycles found in the rate graph"
+    )
+    .unwrap();
+    pub static ref ESTIMATE_ERROR: HistogramVec = register_histogram_vec!(
+        "estimate_error_ratio",
+        "Calculated over estimated output per step of the paths calculated, by protocol",
+        &["protocol"],
+        vec![0.5, 0.8, 0.9, 0.95, 0.99, 1.0, 1.01, 1.05, 1.1, 1.25, 2.0]
+    )
+    .unwrap();
+    pub static ref SEARCH_SECONDS: Histogram = register_histogram!(
+        "search_seconds",
+        "Time taken to search a block for arbitrage",
+        vec![0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 0.5, 1.0]
+    )
+    .unwrap();
+
+    // simulator
+    pub static ref QUOTES: IntCounterVec = register_int_counter_vec!(
+        "quotes_total",
+        "Path simulations against the quoter by result",
+        &["result"]
+    )
+    .unwrap();
+    pub static ref BLACKLISTED_PATHS: IntGauge =
+        register_int_gauge!("blacklisted_paths", "Paths blacklisted after a failed quote").unwrap();
+
+    // tx sender
+    pub static ref TXS_SENT: IntCounter =
+        register_int_counter!("txs_sent_total", "Transactions submitted").unwrap();
+    pub static ref TXS_LANDED: IntCounter =
+        register_int_counter!("txs_landed_total", "Transactions included with a successful status").unwrap();
+    pub static ref TXS_FAILED: IntCounter = register_int_counter!(
+        "txs_failed_total",
+        "Transactions that failed to submit, reverted, or never landed"
+    )
+    .unwrap();
+    pub static ref GAS_SPENT_WEI: Counter =
+        register_counter!("gas_spent_wei_total", "Gas spent on landed transactions in wei").unwrap();
+    pub static ref REALIZED_PROFIT_WEI: Gauge = register_gauge!(
+        "realized_profit_wei",
+        "Base token paid out to us by landed transactions in wei, net of the gas of all included ones"
+    )
+    .unwrap();
+}
+
+// Encode all registered metrics in the prometheus text format
+pub fn gather() -> String {
+    let mut buffer = Vec::new();
+    TextEncoder::new()
+        .encode(&prometheus::gather(), &mut buffer)
+        .unwrap();
+    String::from_utf8(buffer).unwrap()
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use super::*;
+
+    // Recorded values should show up in the text output
+    #[test]
+    fn test_gather() {
+        TXS_SENT.inc();
+        QUOTES.with_label_values(&["success"]).inc();
+        let output = gather();
+        assert!(output.contains("txs_sent_total"));
+        assert!(output.contains("quotes_total{result=\"success\"}"));
+    }
+}
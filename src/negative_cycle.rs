@@ -0,0 +1,303 @@
+use alloy::primitives::Address;
+use petgraph::graph::{EdgeIndex, NodeIndex};
+use petgraph::visit::EdgeRef;
+use pool_sync::{Pool, PoolInfo};
+use std::collections::{HashSet, VecDeque};
+
+use crate::graph::ArbGraph;
+use crate::swap::{SwapPath, SwapStep};
+
+// Improvements smaller than this are float noise, not a cycle
+const EPSILON: f64 = 1e-12;
+
+// A swap direction of the rate graph, weighted -ln(rate)
+struct RateEdge {
+    to: NodeIndex,
+    edge: EdgeIndex,
+    pool: u32,
+    weight: f64,
+}
+
+// A step of a detected cycle as (from, pool edge, to)
+type CycleStep = (NodeIndex, EdgeIndex, NodeIndex);
+
+// Find profitable cycles straight from the estimated rates instead of the enumerated paths.
+// Every swap direction is weighted -ln(rate), so a cycle whose rates multiply to more than
+// one has a negative weight. Spfa runs from the tokens of the touched pools and the pools of
+// each cycle it finds are taken out of the graph before searching again. Cycles are rotated
+// to start at a base, ones that do not pass through a base can not be flash loaned and are
+// skipped
+pub fn find_negative_cycles<F>(
+    graph: &ArbGraph,
+    touched: &HashSet<Address>,
+    bases: &HashSet<Address>,
+    max_cycles: usize,
+    rate: F,
+) -> Vec<SwapPath>
+where
+    F: Fn(&Address, &Address, &Address) -> Option<f64>,
+{
+    let adjacency = rate_edges(graph, rate);
+    let sources: Vec<NodeIndex> = graph
+        .graph()
+        .edge_references()
+        .filter(|edge| touched.contains(&graph.pool(edge.weight()).address()))
+        .map(|edge| edge.source())
+        .collect::<HashSet<NodeIndex>>()
+        .into_iter()
+        .collect();
+
+    let mut banned: HashSet<u32> = HashSet::new();
+    let mut found = Vec::new();
+    for _ in 0..max_cycles * 4 {
+        let Some(cycle) = spfa(&adjacency, &sources, &banned) else {
+            break;
+        };
+        banned.extend(cycle.iter().map(|(_, edge, _)| graph.graph()[*edge].pool));
+        if let Some(path) = to_swap_path(graph, cycle, bases) {
+            found.push(path);
+            if found.len() == max_cycles {
+                break;
+            }
+        }
+    }
+    found
+}
+
+// Weighted edges out of every node. Curve pools are not rated, so they are left out
+fn rate_edges<F>(graph: &ArbGraph, rate: F) -> Vec<Vec<RateEdge>>
+where
+    F: Fn(&Address, &Address, &Address) -> Option<f64>,
+{
+    let graph_ref = graph.graph();
+    let mut adjacency: Vec<Vec<RateEdge>> = (0..graph_ref.node_count()).map(|_| Vec::new()).collect();
+    for edge in graph_ref.edge_references() {
+        let pool = graph.pool(edge.weight());
+        if matches!(pool, Pool::CurveTriCrypto(_)) {
+            continue;
+        }
+        let (token_in, token_out) = (&graph_ref[edge.source()], &graph_ref[edge.target()]);
+        let Some(rate) = rate(&pool.address(), token_in, token_out) else {
+            continue;
+        };
+        if rate > 0.0 && rate.is_finite() {
+            adjacency[edge.source().index()].push(RateEdge {
+                to: edge.target(),
+                edge: edge.id(),
+                pool: edge.weight().pool,
+                weight: -rate.ln(),
+            });
+        }
+    }
+    adjacency
+}
+
+// Shortest paths from the sources, returning the first negative cycle found. A shortest path
+// never has as many hops as there are nodes, so a path that does has looped
+fn spfa(
+    adjacency: &[Vec<RateEdge>],
+    sources: &[NodeIndex],
+    banned: &HashSet<u32>,
+) -> Option<Vec<CycleStep>> {
+    let n = adjacency.len();
+    let mut dist = vec![f64::INFINITY; n];
+    let mut hops = vec![0usize; n];
+    let mut pred: Vec<Option<(NodeIndex, EdgeIndex)>> = vec![None; n];
+    let mut queued = vec![false; n];
+    let mut queue = VecDeque::new();
+    for source in sources {
+        dist[source.index()] = 0.0;
+        queued[source.index()] = true;
+        queue.push_back(*source);
+    }
+
+    while let Some(node) = queue.pop_front() {
+        queued[node.index()] = false;
+        for edge in &adjacency[node.index()] {
+            if banned.contains(&edge.pool) {
+                continue;
+            }
+            let to = edge.to.index();
+            let next = dist[node.index()] + edge.weight;
+            if next < dist[to] - EPSILON {
+                dist[to] = next;
+                pred[to] = Some((node, edge.edge));
+                hops[to] = hops[node.index()] + 1;
+                if hops[to] >= n {
+                    return extract_cycle(&pred, edge.to, n);
+                }
+                if !queued[to] {
+                    queued[to] = true;
+                    queue.push_back(edge.to);
+                }
+            }
+        }
+    }
+    None
+}
+// Walk back far enough to be inside the loop, then follow it around once
+fn extract_cycle(
+    pred: &[Option<(NodeIndex, EdgeIndex)>],
+    start: NodeIndex,
+    n: usize,
+) -> Option<Vec<CycleStep>> {
+    let mut node = start;
+    for _ in 0..n {
+        node = pred[node.index()]?.0;
+    }
+
+    let mut cycle = Vec::new();
+    let mut current = node;
+    loop {
+        let (prev, edge) = pred[current.index()]?;
+        cycle.push((prev, edge, current));
+        current = prev;
+        if current == node {
+            break;
+        }
+        if cycle.len() > n {
+            return None;
+        }
+    }
+    cycle.reverse();
+    Some(cycle)
+}
+
+// Rotate the cycle to start at a base and form the swap path
+fn to_swap_path(
+    graph: &ArbGraph,
+    mut cycle: Vec<CycleStep>,
+    bases: &HashSet<Address>,
+) -> Option<SwapPath> {
+    let graph_ref = graph.graph();
+    let start = cycle.iter().position(|(from, _, _)| bases.contains(&graph_ref[*from]))?;
+    cycle.rotate_left(start);
+
+    let mut pools = HashSet::new();
+    let mut steps = Vec::new();
+    for (from, edge, to) in cycle {
+        let edge = &graph_ref[edge];
+        let pool = graph.pool(edge);
+        if !pools.insert(edge.pool) {
+            return None;
+        }
+        steps.push(SwapStep {
+            pool_address: pool.address(),
+            token_in: graph_ref[from],
+            token_out: graph_ref[to],
+            protocol: pool.pool_type(),
+            fee: edge.fee,
+        });
+    }
+    if steps.len() < 2 {
+        return None;
+    }
+    Some(SwapPath::new(steps))
+}
+
+#[cfg(test)]
+mod negative_cycle_tests {
+    use super::*;
+    use crate::config::CycleConfig;
+    use crate::tests::helpers::fixtures::pools::{pool, DAI, DEGEN, USDC, WETH};
+    use alloy::primitives::U256;
+    use std::collections::HashMap;
+
+    // weth -> usdc -> dai -> degen -> weth, with a second weth/usdc pool
+    const LOOP: [(u8, Address, Address); 5] = [
+        (1, WETH, USDC),
+        (2, USDC, DAI),
+        (3, DAI, DEGEN),
+        (4, DEGEN, WETH),
+        (5, USDC, WETH),
+    ];
+
+    fn graph(pools: &[(u8, Address, Address)]) -> ArbGraph {
+        let pools = pools
+            .iter()
+            .map(|(id, token0, token1)| pool(*id, *token0, *token1))
+            .collect();
+        let config = CycleConfig {
+            max_hops: 2,
+            min_liquidity: U256::ZERO,
+            max_cycles_per_pool: None,
+            cache_dir: None,
+        };
+        ArbGraph::build(pools, vec![WETH], config)
+    }
+
+    // Every pool trades at a 1% loss either way unless overridden
+    fn rates(
+        pools: &[(u8, Address, Address)],
+        overrides: &[(u8, Address, f64)],
+    ) -> HashMap<(Address, Address), f64> {
+        let mut rates = HashMap::new();
+        for (id, token0, token1) in pools {
+            rates.insert((Address::with_last_byte(*id), *token0), 0.99);
+            rates.insert((Address::with_last_byte(*id), *token1), 0.99);
+        }
+        for (id, token_in, rate) in overrides {
+            rates.insert((Address::with_last_byte(*id), *token_in), *rate);
+        }
+        rates
+    }
+
+    fn search(
+        pools: &[(u8, Address, Address)],
+        rates: &HashMap<(Address, Address), f64>,
+        touched: &[u8],
+    ) -> Vec<SwapPath> {
+        let touched = touched.iter().map(|id| Address::with_last_byte(*id)).collect();
+        find_negative_cycles(&graph(pools), &touched, &HashSet::from([WETH]), 4, |pool, token, _| {
+            rates.get(&(*pool, *token)).copied()
+        })
+    }
+
+    // Without a loop that gains, nothing is found
+    #[test]
+    fn test_no_cycle() {
+        assert!(search(&LOOP, &rates(&LOOP, &[]), &[1, 2, 3, 4, 5]).is_empty());
+    }
+
+    // A four hop loop is found from a touched pool and rotated to start at weth
+    #[test]
+    fn test_long_cycle() {
+        let rates = rates(&LOOP, &[(3, DAI, 1.1), (3, DEGEN, 0.85)]);
+        let found = search(&LOOP, &rates, &[3]);
+        assert_eq!(found.len(), 1);
+
+        let path = &found[0];
+        assert_eq!(path.steps.len(), 4);
+        assert_eq!(path.base_token(), WETH);
+        assert_eq!(path.steps.last().unwrap().token_out, WETH);
+        assert!(path.steps.windows(2).all(|w| w[0].token_out == w[1].token_in));
+        let product: f64 = path
+            .steps
+            .iter()
+            .map(|step| rates[&(step.pool_address, step.token_in)])
+            .product();
+        assert!(product > 1.0);
+    }
+
+    // Two loops through weth are both found, the second once the first is taken out
+    #[test]
+    fn test_multiple_cycles() {
+        let pools = [
+            (1, WETH, USDC),
+            (5, USDC, WETH),
+            (6, WETH, DAI),
+            (3, DAI, DEGEN),
+            (4, DEGEN, WETH),
+        ];
+        let rates = rates(
+            &pools,
+            &[(1, WETH, 1.05), (1, USDC, 0.9), (3, DAI, 1.1), (3, DEGEN, 0.85)],
+        );
+        let mut hops: Vec<usize> = search(&pools, &rates, &[1, 3])
+            .iter()
+            .map(|path| path.steps.len())
+            .collect();
+        hops.sort();
+        assert_eq!(hops, vec![2, 3]);
+    }
+}
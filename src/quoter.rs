@@ -4,14 +4,33 @@ use alloy::providers::RootProvider;
 use alloy::sol_types::SolCall;
 use alloy::sol_types::SolValue;
 use alloy::transports::http::{Client, Http};
-use anyhow::{anyhow, Result};
 use revm::primitives::{ExecutionResult, TransactTo};
 use revm::Evm;
 use std::sync::Arc;
 
+use crate::config::BaseToken;
+use crate::errors::SimulationError;
 use crate::gen::FlashQuoter;
 use crate::market_state::MarketState;
-use crate::AMOUNT;
+
+// Whether contract code dispatches a function. Solidity compares the calldata selector
+// against a PUSH4 of every external function, so code built from an older source is
+// missing the selectors of the current abi
+pub fn dispatches(code: &[u8], selector: [u8; 4]) -> bool {
+    let mut pc = 0;
+    while pc < code.len() {
+        let op = code[pc];
+        if op == 0x63 && code.get(pc + 1..pc + 5) == Some(&selector[..]) {
+            return true;
+        }
+        // PUSH1 to PUSH32 carry their data inline, skip it
+        if (0x60..=0x7f).contains(&op) {
+            pc += (op - 0x5f) as usize;
+        }
+        pc += 1;
+    }
+    false
+}
 
 // Quoter. This is used to get a simulation quote before sending off a transaction.
 // This will confirm that our offchain calculations are reasonable and make sure we can swap the tokens
@@ -21,7 +40,7 @@ impl Quoter {
     pub fn quote_path(
         quote_params: FlashQuoter::SwapParams,
         market_state: Arc<MarketState<Http<Client>, Ethereum, RootProvider<Http<Client>>>>,
-    ) -> Result<Vec<U256>> {
+    ) -> Result<Vec<U256>, SimulationError> {
         let mut guard = market_state.db.write().unwrap();
         // need to pass this as mut somehow
         let mut evm = Evm::builder().with_db(&mut *guard).build();
@@ -38,19 +57,17 @@ impl Quoter {
         evm.tx_mut().data = quote_calldata.into();
 
         // transact
-        let ref_tx = evm.transact().unwrap();
+        let ref_tx = evm
+            .transact()
+            .map_err(|e| SimulationError::Evm(e.to_string()))?;
         let result = ref_tx.result;
 
         match result {
             ExecutionResult::Success { output: value, .. } => {
-                if let Ok(amount) = Vec::<U256>::abi_decode(value.data(), false) {
-                    Ok(amount)
-                } else {
-                    Err(anyhow!("Failed to decode"))
-                }
+                Vec::<U256>::abi_decode(value.data(), false).map_err(|_| SimulationError::Decode)
             }
-            ExecutionResult::Revert { output, .. } => Err(anyhow!("Simulation reverted {output}")),
-            _ => Err(anyhow!("Failed to simulate")),
+            ExecutionResult::Revert { output, .. } => Err(SimulationError::Revert(output)),
+            ExecutionResult::Halt { reason, .. } => Err(SimulationError::Halt(format!("{reason:?}"))),
         }
     }
 
@@ -60,19 +77,24 @@ impl Quoter {
         quote_path: FlashQuoter::SwapParams,
         initial_out: U256,
         market_state: Arc<MarketState<Http<Client>, Ethereum, RootProvider<Http<Client>>>>,
+        base: &BaseToken,
     ) -> (U256, U256) {
         let mut quote_path = quote_path.clone();
-        let mut curr_input = *AMOUNT;
-        let mut best_input = *AMOUNT;
+        let mut curr_input = base.amount;
+        let mut best_input = base.amount;
         let mut best_output = initial_out;
 
+        // step a fifth of the base amount at a time
+        let step = base.amount / U256::from(5);
         for _ in 0..50 {
-            curr_input = curr_input + U256::from(2e14);
+            curr_input = curr_input + step;
             quote_path.amountIn = curr_input;
 
             match Self::quote_path(quote_path.clone(), market_state.clone()) {
                 Ok(amounts) => {
-                    let output = *amounts.last().unwrap();
+                    let Some(&output) = amounts.last() else {
+                        break;
+                    };
                     if output > curr_input && output > best_output {
                         best_output = output;
                         best_input = curr_input;
@@ -86,3 +108,34 @@ impl Quoter {
         (best_input, best_output)
     }
 }
+
+#[cfg(test)]
+mod quoter_tests {
+    use super::*;
+    use crate::gen::FlashSwap;
+
+    #[test]
+    fn test_dispatches() {
+        // PUSH4 0x11223344 EQ, and the same bytes inside PUSH32 data
+        let code = [0x63, 0x11, 0x22, 0x33, 0x44, 0x14];
+        assert!(dispatches(&code, [0x11, 0x22, 0x33, 0x44]));
+        let mut hidden = vec![0x7f];
+        hidden.extend([0x63, 0x11, 0x22, 0x33, 0x44]);
+        hidden.extend([0u8; 27]);
+        assert!(!dispatches(&hidden, [0x11, 0x22, 0x33, 0x44]));
+    }
+
+    // The checked in artifacts have to be built from the current contract sources, or the
+    // quoter injected into the db and the deployed swap contract reject our calldata
+    #[test]
+    fn test_artifacts_match_abi() {
+        assert!(dispatches(
+            &FlashQuoter::DEPLOYED_BYTECODE,
+            FlashQuoter::quoteArbitrageCall::SELECTOR
+        ));
+        assert!(dispatches(
+            &FlashSwap::DEPLOYED_BYTECODE,
+            FlashSwap::executeArbitrageCall::SELECTOR
+        ));
+    }
+}
@@ -0,0 +1,244 @@
+use alloy::network::Ethereum;
+use alloy::primitives::Address;
+use alloy::providers::{ProviderBuilder, RootProvider};
+use alloy::transports::http::{Client, Http};
+use anyhow::{bail, Result};
+use log::{info, warn};
+use pool_sync::PoolInfo;
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::config::BotConfig;
+use crate::estimator::Estimator;
+use crate::graph::ArbGraph;
+use crate::journal::{JournalReader, JournalRecord};
+use crate::market_state::MarketState;
+use crate::searcher::Searchoor;
+use crate::simulator::simulate_path;
+
+// Outcome of a replay alongside what was recorded live, a difference between the
+// two means the strategy behaves differently on the same blocks
+#[derive(Debug, Default)]
+pub struct ReplayReport {
+    pub start_block: u64,
+    pub blocks: u64,
+    // best path hash found in each block
+    pub arb_paths: BTreeMap<u64, u64>,
+    pub recorded_arb_paths: BTreeMap<u64, u64>,
+    // blocks where the quoter confirmed the path
+    pub valid_paths: HashSet<u64>,
+    pub recorded_valid_paths: HashSet<u64>,
+}
+
+impl ReplayReport {
+    // Blocks where the replayed path differs from the recorded one
+    pub fn mismatched_blocks(&self) -> Vec<u64> {
+        let blocks: HashSet<u64> = self
+            .arb_paths
+            .keys()
+            .chain(self.recorded_arb_paths.keys())
+            .copied()
+            .collect();
+        let mut mismatched: Vec<u64> = blocks
+            .into_iter()
+            .filter(|block| self.arb_paths.get(block) != self.recorded_arb_paths.get(block))
+            .collect();
+        mismatched.sort();
+        mismatched
+    }
+
+    pub fn log(&self) {
+        info!(
+            "Replayed {} blocks from block {}",
+            self.blocks, self.start_block
+        );
+        info!(
+            "Arb paths: {} replayed, {} recorded",
+            self.arb_paths.len(),
+            self.recorded_arb_paths.len()
+        );
+        info!(
+            "Valid paths: {} replayed, {} recorded",
+            self.valid_paths.len(),
+            self.recorded_valid_paths.len()
+        );
+        let mismatched = self.mismatched_blocks();
+        if mismatched.is_empty() {
+            info!("Replay matches the recording");
+        } else {
+            warn!("Replay differs from the recording in blocks {:?}", mismatched);
+        }
+    }
+}
+
+// Replay a recorded journal through the market state, estimator, searcher, calculator,
+// and quoter without touching the network. State comes from the journaled snapshot and
+// the block diffs recorded by the state updater in place of debug_trace_block
+pub async fn replay_journal(dir: &Path, config: Arc<BotConfig>) -> Result<ReplayReport> {
+    let mut entries = JournalReader::open(dir)?;
+
+    // everything starts from the state the bot was initialized with, or the checkpoint
+    // at the head of the oldest file once the files before it were pruned
+    let (start_block, mut pools, state) = loop {
+        match entries.next() {
+            Some(entry) => match entry?.record {
+                JournalRecord::Snapshot {
+                    block_number,
+                    pools,
+                    state,
+                }
+                | JournalRecord::Checkpoint {
+                    block_number,
+                    pools,
+                    state,
+                } => break (block_number, pools, state),
+                _ => {}
+            },
+            None => bail!("No snapshot in journal {}, nothing to replay from", dir.display()),
+        }
+    };
+    info!(
+        "Replaying from block {} with {} pools",
+        start_block,
+        pools.len()
+    );
+
+    // the provider is never queried, the db is offline
+    let provider = ProviderBuilder::new().on_http(config.http_url.clone());
+    let market_state: Arc<MarketState<Http<Client>, Ethereum, RootProvider<Http<Client>>>> =
+        MarketState::from_snapshot(pools.clone(), state, start_block, provider)?;
+
+    // always optimize valid paths as we would live
+    let config = Arc::new(BotConfig {
+        simulate_only: false,
+        ..(*config).clone()
+    });
+
+    let mut report = ReplayReport {
+        start_block,
+        ..Default::default()
+    };
+    let mut searcher = None;
+    let mut blacklisted_paths: HashSet<u64> = HashSet::new();
+    let mut last_block = start_block;
+
+    for entry in entries {
+        let mut record = entry?.record;
+        record.migrate(config.weth);
+        match record {
+            // a new run of the bot, its state is not a continuation of ours
+            JournalRecord::Snapshot { block_number, .. } => {
+                info!("Found a new snapshot at block {}, stopping", block_number);
+                break;
+            }
+            // the same run carried on, our state already follows it
+            JournalRecord::Checkpoint { .. } => {}
+            // live, the searcher only starts once the updater has caught up and
+            // the first new block comes in
+            JournalRecord::NewBlock { .. } if searcher.is_none() => {
+                let mut estimator = Estimator::new(market_state.clone(), config.clone());
+                estimator.process_pools(pools.clone());
+                let graph = ArbGraph::new(pools.clone(), &config);
+                info!("Generated {} cycles", graph.cycles().len());
+                searcher = Some(Searchoor::new(
+                    graph,
+                    market_state.clone(),
+                    estimator,
+                    config.clone(),
+                ));
+            }
+            JournalRecord::StateDiff {
+                block_number,
+                diffs,
+            } => {
+                if block_number <= last_block {
+                    continue;
+                }
+                let updated_pools = market_state.apply_diffs(&diffs);
+                last_block = block_number;
+                report.blocks += 1;
+
+                // blocks while catching up only update the state
+                let Some(searcher) = searcher.as_mut() else {
+                    continue;
+                };
+                // calibrating after the search, as live, keeps the estimates of the next block
+                // the same as they were
+                let found = searcher.search_block(&updated_pools, block_number);
+                searcher.calibrate();
+                let Some((path, expected_out)) = found else {
+                    continue;
+                };
+                report.arb_paths.insert(block_number, path.hash);
+                if simulate_path(
+                    &path,
+                    expected_out,
+                    block_number,
+                    &market_state,
+                    &config,
+                    &mut blacklisted_paths,
+                )
+                .is_some()
+                {
+                    report.valid_paths.insert(block_number);
+                }
+            }
+            // the working set changes between blocks as it did live, added pools come with
+            // the state they were loaded with
+            JournalRecord::PoolDelta {
+                added,
+                removed,
+                state,
+                ..
+            } => {
+                let removed: HashSet<Address> = removed.into_iter().collect();
+                pools.retain(|pool| !removed.contains(&pool.address()));
+                if !removed.is_empty() {
+                    match searcher.as_mut() {
+                        Some(searcher) => {
+                            searcher.remove_pools(&removed);
+                        }
+                        None => market_state.remove_pools(&removed),
+                    }
+                }
+
+                let Some(state) = state else {
+                    continue;
+                };
+                pools.extend(added.iter().cloned());
+                market_state.restore(added.clone(), state);
+                if let Some(searcher) = searcher.as_mut() {
+                    searcher.track_pools(added);
+                }
+            }
+            JournalRecord::ArbPath {
+                block_number, path, ..
+            } => {
+                report.recorded_arb_paths.insert(block_number, path.hash);
+            }
+            JournalRecord::ValidPath { block_number, .. } => {
+                report.recorded_valid_paths.insert(block_number);
+            }
+            _ => {}
+        }
+    }
+
+    Ok(report)
+}
+
+#[cfg(test)]
+mod replay_tests {
+    use super::*;
+
+    // Blocks where only one side found a path, or they found different ones, mismatch
+    #[test]
+    fn test_mismatched_blocks() {
+        let report = ReplayReport {
+            arb_paths: BTreeMap::from([(1, 10), (2, 20), (3, 30)]),
+            recorded_arb_paths: BTreeMap::from([(1, 10), (2, 21), (4, 40)]),
+            ..Default::default()
+        };
+        assert_eq!(report.mismatched_blocks(), vec![2, 3, 4]);
+    }
+}
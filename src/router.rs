@@ -0,0 +1,254 @@
+use alloy::network::Network;
+use alloy::primitives::{Address, U256};
+use alloy::providers::Provider;
+use alloy::transports::Transport;
+use log::debug;
+use petgraph::graph::NodeIndex;
+use petgraph::visit::EdgeRef;
+use pool_sync::PoolInfo;
+use serde::Serialize;
+use std::collections::HashMap;
+
+use crate::calculation::Calculator;
+use crate::graph::ArbGraph;
+use crate::swap::SwapStep;
+
+// A route between two tokens with the amount after every step
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Route {
+    pub steps: Vec<SwapStep>,
+    // amounts[0] is the input, amounts[i + 1] is the output of step i
+    pub amounts: Vec<U256>,
+}
+
+impl Route {
+    pub fn amount_out(&self) -> U256 {
+        self.amounts.last().copied().unwrap_or_default()
+    }
+}
+
+// Quotes swaps between any two tokens in the graph with exact calculator outputs, so the
+// bot can serve as a local aggregator
+pub struct Router<'a, T, N, P>
+where
+    T: Transport + Clone,
+    N: Network,
+    P: Provider<T, N>,
+{
+    graph: &'a ArbGraph,
+    calculator: &'a Calculator<T, N, P>,
+}
+
+impl<'a, T, N, P> Router<'a, T, N, P>
+where
+    T: Transport + Clone,
+    N: Network,
+    P: Provider<T, N>,
+{
+    pub fn new(graph: &'a ArbGraph, calculator: &'a Calculator<T, N, P>) -> Self {
+        Self { graph, calculator }
+    }
+
+    // The route with the most output within max_hops pools, none if the tokens are not
+    // connected. Pools the calculator can not price are skipped
+    pub fn best_route(
+        &self,
+        token_in: Address,
+        token_out: Address,
+        amount_in: U256,
+        max_hops: usize,
+    ) -> Option<Route> {
+        find_best_route(self.graph, token_in, token_out, amount_in, max_hops, |step, amount| {
+            self.calculator
+                .compute_amount_out(
+                    amount,
+                    step.pool_address,
+                    step.token_in,
+                    step.token_out,
+                    step.protocol,
+                    step.fee,
+                )
+                .map_err(|e| debug!("Not routing through {}: {}", step.pool_address, e))
+                .ok()
+        })
+    }
+}
+
+// Bellman-Ford layered by hop count. Round k only extends the routes of round k - 1 by a
+// pool, keeping the best amount reached at each token with exactly k pools. Outputs grow
+// with inputs, so within a layer a route that reaches a token with less can not end up
+// better from there, while a longer route reaching a token with more does not displace a
+// shorter one that still has hops left. Tokens and pools are never visited twice within a
+// route
+pub fn find_best_route<F>(
+    graph: &ArbGraph,
+    token_in: Address,
+    token_out: Address,
+    amount_in: U256,
+    max_hops: usize,
+    quote: F,
+) -> Option<Route>
+where
+    F: Fn(&SwapStep, U256) -> Option<U256>,
+{
+    let graph_ref = graph.graph();
+    let start = graph.node_index(&token_in)?;
+    let end = graph.node_index(&token_out)?;
+    if start == end || amount_in.is_zero() {
+        return None;
+    }
+
+    let mut layer: HashMap<NodeIndex, Route> = HashMap::from([(
+        start,
+        Route {
+            steps: Vec::new(),
+            amounts: vec![amount_in],
+        },
+    )]);
+    let mut best: Option<Route> = None;
+    for _ in 0..max_hops {
+        let mut next_layer: HashMap<NodeIndex, Route> = HashMap::new();
+        for (&node, route) in &layer {
+            // the output token is not routed through
+            if node == end {
+                continue;
+            }
+
+            for edge in graph_ref.edges(node) {
+                let next = edge.target();
+                let pool = graph.pool(edge.weight());
+                let revisits = next == start
+                    || route.steps.iter().any(|step| {
+                        step.pool_address == pool.address() || step.token_in == graph_ref[next]
+                    });
+                if revisits {
+                    continue;
+                }
+
+                let step = SwapStep {
+                    pool_address: pool.address(),
+                    token_in: graph_ref[node],
+                    token_out: graph_ref[next],
+                    protocol: pool.pool_type(),
+                    fee: edge.weight().fee,
+                };
+                let Some(amount_out) = quote(&step, route.amount_out()) else {
+                    continue;
+                };
+                if amount_out.is_zero()
+                    || next_layer.get(&next).is_some_and(|known| known.amount_out() >= amount_out)
+                {
+                    continue;
+                }
+
+                let mut extended = route.clone();
+                extended.steps.push(step);
+                extended.amounts.push(amount_out);
+                next_layer.insert(next, extended);
+            }
+        }
+
+        // a longer route only wins with strictly more output
+        if let Some(route) = next_layer.get(&end) {
+            if !best.as_ref().is_some_and(|known| known.amount_out() >= route.amount_out()) {
+                best = Some(route.clone());
+            }
+        }
+        if next_layer.is_empty() {
+            break;
+        }
+        layer = next_layer;
+    }
+    best
+}
+
+#[cfg(test)]
+mod router_tests {
+    use super::*;
+    use crate::config::CycleConfig;
+    use crate::tests::helpers::fixtures::pools::{pool, DAI, DEGEN, USDC, WETH};
+    use pool_sync::Pool;
+
+    // Routing needs no start tokens and no cycles
+    fn build(pools: Vec<Pool>) -> ArbGraph {
+        let config = CycleConfig {
+            max_hops: 2,
+            min_liquidity: U256::ZERO,
+            max_cycles_per_pool: None,
+            cache_dir: None,
+        };
+        ArbGraph::build(pools, Vec::new(), config)
+    }
+
+    // A direct weth/usdc pool and a better route through dai
+    fn graph() -> ArbGraph {
+        build(vec![pool(1, WETH, USDC), pool(2, WETH, DAI), pool(3, DAI, USDC)])
+    }
+
+    // Each pool pays out a fixed percentage of the input
+    fn quote(step: &SwapStep, amount: U256) -> Option<U256> {
+        let percent = match step.pool_address[19] {
+            1 => 90,
+            2 => 98,
+            _ => 97,
+        };
+        Some(amount * U256::from(percent) / U256::from(100))
+    }
+
+    #[test]
+    fn test_best_route() {
+        let graph = graph();
+        let amount = U256::from(10_000);
+
+        let route = find_best_route(&graph, WETH, USDC, amount, 2, quote).unwrap();
+        assert_eq!(route.steps.len(), 2);
+        assert_eq!(route.steps[0].token_out, DAI);
+        assert_eq!(route.amounts, vec![amount, U256::from(9800), U256::from(9506)]);
+
+        // limited to one hop the direct pool is the only route
+        let route = find_best_route(&graph, WETH, USDC, amount, 1, quote).unwrap();
+        assert_eq!(route.steps.len(), 1);
+        assert_eq!(route.amount_out(), U256::from(9000));
+    }
+
+    // Unknown or identical tokens and unpriceable pools have no route
+    #[test]
+    fn test_no_route() {
+        let graph = graph();
+        let amount = U256::from(10_000);
+        assert!(find_best_route(&graph, WETH, Address::ZERO, amount, 3, quote).is_none());
+        assert!(find_best_route(&graph, WETH, WETH, amount, 3, quote).is_none());
+        assert!(find_best_route(&graph, WETH, USDC, amount, 3, |_, _| None).is_none());
+    }
+
+    // A three hop route reaching dai with more than the direct pool does not displace the
+    // direct pool from the best two hop route through dai
+    #[test]
+    fn test_best_route_by_hops() {
+        let other = Address::with_last_byte(0xff);
+        let graph = build(vec![
+            pool(1, WETH, USDC),
+            pool(2, WETH, DAI),
+            pool(3, DAI, USDC),
+            pool(4, WETH, DEGEN),
+            pool(5, DEGEN, other),
+            pool(6, other, DAI),
+        ]);
+        let quote = |step: &SwapStep, amount: U256| {
+            let percent = [80, 90, 97, 99, 99, 99][step.pool_address[19] as usize - 1];
+            Some(amount * U256::from(percent) / U256::from(100))
+        };
+        let amount = U256::from(1_000_000);
+
+        let route = find_best_route(&graph, WETH, USDC, amount, 3, quote).unwrap();
+        let tokens: Vec<Address> = route.steps.iter().map(|step| step.token_out).collect();
+        assert_eq!(tokens, vec![DAI, USDC]);
+        assert_eq!(route.amount_out(), U256::from(873_000));
+
+        // with a fourth hop the longer route to dai continues to usdc
+        let route = find_best_route(&graph, WETH, USDC, amount, 4, quote).unwrap();
+        assert_eq!(route.steps.len(), 4);
+        assert_eq!(route.amounts[3], U256::from(970_299));
+        assert_eq!(route.amount_out(), U256::from(941_190));
+    }
+}
@@ -2,19 +2,33 @@ use alloy::network::Network;
 use alloy::primitives::{Address, U256};
 use alloy::providers::Provider;
 use alloy::transports::Transport;
-use log::{debug, info};
+use log::{debug, info, warn};
+use pool_sync::{Pool, PoolInfo};
 use rayon::prelude::*;
 use std::collections::{HashMap, HashSet};
-use std::sync::mpsc::{Receiver, Sender};
 use std::sync::Arc;
 use std::time::Instant;
+use tokio::sync::mpsc::{Receiver, Sender};
+
+// Most cycles to pull out of the rate graph per block
+const MAX_DETECTED_CYCLES: usize = 8;
+// Touched paths calculated per block only to calibrate the estimates
+const CALIBRATION_SAMPLES: usize = 4;
 
 use crate::calculation::Calculator;
+use crate::calibration::Calibration;
+use crate::config::{BotConfig, SearchMode};
 use crate::estimator::Estimator;
-use crate::events::Event;
+use crate::events::{Event, PoolDelta};
+use crate::graph::{ArbGraph, CycleDelta};
+use crate::journal::{Journal, JournalRecord};
 use crate::market_state::MarketState;
+use crate::metrics::{
+    AFFECTED_PATHS, CALCULATED_PATHS, DETECTED_CYCLES, ESTIMATED_PATHS, SEARCH_SECONDS,
+    SPLIT_PATHS,
+};
+use crate::negative_cycle::find_negative_cycles;
 use crate::swap::SwapPath;
-use crate::AMOUNT;
 
 // top level sercher struct
 // contains the calculator and all path information
@@ -26,9 +40,18 @@ where
 {
     calculator: Calculator<T, N, P>,
     estimator: Estimator<T, N, P>,
-    path_index: HashMap<Address, Vec<usize>>,
-    cycles: Vec<SwapPath>,
-    min_profit: U256,
+    market_state: Arc<MarketState<T, N, P>>,
+    config: Arc<BotConfig>,
+    graph: ArbGraph,
+    // hashes of the cycles in the graph through each pool
+    path_index: HashMap<Address, Vec<u64>>,
+    // estimator direction ids of the steps of each cycle by its hash, for log space scoring
+    directions: HashMap<u64, Vec<usize>>,
+    // error of the estimates against the calculated outputs, corrects the estimates
+    calibration: Calibration,
+    // paths sampled in the last search with their estimates, calculated once the search
+    // result is on its way
+    pending_calibration: Vec<(SwapPath, U256)>,
 }
 
 impl<T, N, P> Searchoor<T, N, P>
@@ -37,96 +60,470 @@ where
     N: Network,
     P: Provider<T, N>,
 {
-    // Construct the searcher with the calculator and all the swap paths
+    // Construct the searcher with the calculator and the graph holding all the swap paths
     pub fn new(
-        cycles: Vec<SwapPath>,
+        graph: ArbGraph,
         market_state: Arc<MarketState<T, N, P>>,
-        estimator: Estimator<T, N, P>,
+        mut estimator: Estimator<T, N, P>,
+        config: Arc<BotConfig>,
     ) -> Self {
-        let calculator = Calculator::new(market_state);
+        let calculator = Calculator::new(market_state.clone(), config.clone());
 
         // make our path mapper for easily getting touched paths
-        let mut index: HashMap<Address, Vec<usize>> = HashMap::new();
-        for (path_index, path) in cycles.iter().enumerate() {
+        let mut path_index: HashMap<Address, Vec<u64>> = HashMap::new();
+        for path in graph.cycles() {
             for step in &path.steps {
-                index.entry(step.pool_address).or_default().push(path_index)
+                path_index.entry(step.pool_address).or_default().push(path.hash);
             }
         }
-
-        // calculate the min profit percentage
-        let initial_amount = *AMOUNT;
-        let flash_loan_fee = (initial_amount * U256::from(9)) / U256::from(10000);
-        let repayment_amount = initial_amount + flash_loan_fee;
-        let min_profit_percentage = (initial_amount * U256::from(1)) / U256::from(100);
-        let min_profit = repayment_amount + min_profit_percentage;
-
+        let directions = graph
+            .cycles()
+            .iter()
+            .map(|path| (path.hash, estimator.direction_ids(path)))
+            .collect();
         Self {
             calculator,
             estimator,
-            cycles,
-            path_index: index,
-            min_profit,
+            market_state,
+            config,
+            graph,
+            path_index,
+            directions,
+            calibration: Calibration::default(),
+            pending_calibration: Vec::new(),
         }
     }
 
-    pub fn search_paths(&mut self, paths_tx: Sender<Event>, address_rx: Receiver<Event>) {
-        let _sim: bool = std::env::var("SIM").unwrap().parse().unwrap();
+    // Add pools to the running search. Their state is loaded and rated, then the cycles
+    // they complete are indexed so they are searched from the next block
+    pub fn add_pools(&mut self, pools: Vec<Pool>) -> CycleDelta {
+        let pools = self.load_pools(pools);
+        self.track_pools(pools)
+    }
+
+    // Load the state of new pools into the market state, returning the ones it now tracks
+    fn load_pools(&self, pools: Vec<Pool>) -> Vec<Pool> {
+        let tracked = self.market_state.add_pools(pools.clone(), &self.config);
+        pools
+            .into_iter()
+            .filter(|pool| tracked.contains(&pool.address()))
+            .collect()
+    }
+
+    // Search over pools whose state the market state already holds. Replay restores
+    // their state from the journal instead of loading it
+    pub fn track_pools(&mut self, pools: Vec<Pool>) -> CycleDelta {
+        let tracked: HashSet<Address> = pools.iter().map(|pool| pool.address()).collect();
+        self.estimator.update_rates(&tracked);
+
+        let delta = self.graph.add_pools(pools);
+        self.apply_delta(&delta);
+        info!(
+            "Added {} pools, {} new paths",
+            tracked.len(),
+            delta.added.len()
+        );
+        delta
+    }
+
+    // Remove pools from the running search along with every path through them
+    pub fn remove_pools(&mut self, pools: &HashSet<Address>) -> CycleDelta {
+        let delta = self.graph.remove_pools(pools);
+        self.apply_delta(&delta);
+        self.estimator.remove_pools(pools);
+        self.calculator.invalidate_cache(pools);
+        self.market_state.remove_pools(pools);
+        info!(
+            "Removed {} pools, {} retired paths",
+            pools.len(),
+            delta.retired.len()
+        );
+        delta
+    }
 
+    // Update the path index in place, the paths themselves live in the graph
+    fn apply_delta(&mut self, delta: &CycleDelta) {
+        for path in &delta.retired {
+            if self.directions.remove(&path.hash).is_none() {
+                continue;
+            }
+            for step in &path.steps {
+                if let Some(paths) = self.path_index.get_mut(&step.pool_address) {
+                    paths.retain(|&hash| hash != path.hash);
+                }
+            }
+        }
+        self.path_index.retain(|_, paths| !paths.is_empty());
+
+        for path in &delta.added {
+            self.index_path(path);
+        }
+    }
+
+    fn index_path(&mut self, path: &SwapPath) {
+        for step in &path.steps {
+            self.path_index.entry(step.pool_address).or_default().push(path.hash)
+        }
+        self.directions.insert(path.hash, self.estimator.direction_ids(path));
+    }
+
+    // Apply the working set changes that came in since the last block
+    // Apply the changes to the working set before searching a block. They are journaled with
+    // the state the added pools were loaded with, replay has no provider to load them from
+    fn apply_pool_deltas(
+        &mut self,
+        delta_rx: &mut Receiver<PoolDelta>,
+        block_number: u64,
+        journal: &Journal,
+    ) {
+        while let Ok(delta) = delta_rx.try_recv() {
+            match delta {
+                PoolDelta::Add(pools) => {
+                    let pools = self.load_pools(pools);
+                    if journal.is_enabled() {
+                        journal.record(JournalRecord::PoolDelta {
+                            block_number,
+                            added: pools.clone(),
+                            removed: Vec::new(),
+                            state: Some(self.market_state.db.read().unwrap().snapshot()),
+                        });
+                    }
+                    self.track_pools(pools);
+                }
+                PoolDelta::Remove(pools) => {
+                    journal.record(JournalRecord::PoolDelta {
+                        block_number,
+                        added: Vec::new(),
+                        removed: pools.iter().copied().collect(),
+                        state: None,
+                    });
+                    self.remove_pools(&pools);
+                }
+            }
+        }
+    }
+
+    // Runs on a blocking thread, applies backpressure to the state updater while busy.
+    // Pools are added and removed between blocks as the working set changes
+    pub fn search_paths(
+        &mut self,
+        paths_tx: Sender<Event>,
+        mut address_rx: Receiver<Event>,
+        mut delta_rx: Receiver<PoolDelta>,
+        journal: Journal,
+    ) {
         // wait for a new single with the pools that have reserved updated
-        while let Ok(Event::PoolsTouched(pools, block_number)) = address_rx.recv() {
-            info!("Searching for arbs in block {}...", block_number);
-            let res = Instant::now();
+        while let Some(Event::PoolsTouched(pools, block_number)) = address_rx.blocking_recv() {
+            self.apply_pool_deltas(&mut delta_rx, block_number, &journal);
+            if let Some((path, calculated_out)) = self.search_block(&pools, block_number) {
+                let event = Event::ArbPath((path, calculated_out, block_number));
+                journal.record_event(&event);
+                match paths_tx.blocking_send(event) {
+                    Ok(_) => debug!("Sent path"),
+                    Err(_) => debug!("Failed to send path"),
+                }
+            }
+            self.calibrate();
+        }
+    }
 
-            // invalidate all updated pools in the cache
-            self.calculator.invalidate_cache(&pools);
+    // Find profitable cycles through the updated pools from the estimated rates
+    fn detect_cycles(&self, pools: &HashSet<Address>) -> Vec<SwapPath> {
+        let bases: HashSet<Address> = self.config.bases.iter().map(|base| base.token).collect();
+        let detected = find_negative_cycles(
+            &self.graph,
+            pools,
+            &bases,
+            MAX_DETECTED_CYCLES,
+            |pool, token_in, token_out| self.estimator.rate(pool, token_in, token_out),
+        );
+        info!("{} detected cycles", detected.len());
+        DETECTED_CYCLES.inc_by(detected.len() as u64);
+        detected
+    }
+
+    // Split the legs of a path across parallel pools if enabled and it pays more
+    fn split_legs(&self, path: &SwapPath, calculated_out: U256) -> (SwapPath, U256) {
+        if !self.config.split_legs {
+            return (path.clone(), calculated_out);
+        }
+        match self
+            .calculator
+            .split_path(path, |step, used| self.graph.parallel_steps(step, used))
+        {
+            Ok(Some((split, split_out))) if split_out > calculated_out => {
+                info!("Split path {} from {} to {}", path.hash, calculated_out, split_out);
+                SPLIT_PATHS.inc();
+                (split, split_out)
+            }
+            Ok(_) => (path.clone(), calculated_out),
+            Err(e) => {
+                debug!("Failed to split path {}: {}", path.hash, e);
+                (path.clone(), calculated_out)
+            }
+        }
+    }
+
+    // Search the paths touched by the updated pools and return the best one if the
+    // calculator confirms it is profitable
+    pub fn search_block(
+        &mut self,
+        pools: &HashSet<Address>,
+        block_number: u64,
+    ) -> Option<(SwapPath, U256)> {
+        info!("Searching for arbs in block {}...", block_number);
+        let res = Instant::now();
+
+        // invalidate all updated pools in the cache
+        self.calculator.invalidate_cache(pools);
 
-            // update all the rates for the pools that were touched
-            self.estimator.update_rates(&pools);
-            info!("Updated estimations");
+        // update all the rates for the pools that were touched
+        self.estimator.update_rates(pools);
+        info!("Updated estimations");
 
-            // from the updated pools, get all paths that we want to recheck
-            let affected_paths: HashSet<&SwapPath> = pools
+        // cycles detected in the rate graph, these do not have to be enumerated paths
+        let detected = match self.config.search_mode {
+            SearchMode::Paths => Vec::new(),
+            SearchMode::NegativeCycle | SearchMode::Both => self.detect_cycles(pools),
+        };
+
+        // from the updated pools, get all paths that we want to recheck
+        let affected: HashSet<u64> = match self.config.search_mode {
+            SearchMode::NegativeCycle => HashSet::new(),
+            SearchMode::Paths | SearchMode::Both => pools
                 .iter()
                 .filter_map(|pool| self.path_index.get(pool))
                 .flatten()
-                .map(|&index| &self.cycles[index])
-                .collect();
-            info!("{} touched paths", affected_paths.len());
-
-            // get the output amount and check for profitability
-            let profitable_paths: Vec<(SwapPath, U256)> = affected_paths
-                .par_iter()
-                .filter_map(|path| {
-                    // estimate if the path is profitable
-                    let output_est = self.estimator.estimate_output_amount(path);
-                    if output_est >= self.min_profit && output_est < U256::from(1e18) {
-                        Some(((*path).clone(), output_est))
-                    } else {
-                        None
-                    }
+                .copied()
+                .collect(),
+        };
+        let detected: Vec<(SwapPath, Vec<usize>)> = detected
+            .into_iter()
+            .filter(|path| !affected.contains(&path.hash))
+            .map(|path| {
+                let directions = self.estimator.direction_ids(&path);
+                (path, directions)
+            })
+            .collect();
+        let mut affected_paths: Vec<(&SwapPath, &[usize])> = affected
+            .iter()
+            .filter_map(|hash| Some((self.graph.cycle(*hash)?, self.directions.get(hash)?.as_slice())))
+            .collect();
+        affected_paths.extend(
+            detected
+                .iter()
+                .map(|(path, directions)| (path, directions.as_slice())),
+        );
+        info!("{} touched paths", affected_paths.len());
+        AFFECTED_PATHS.inc_by(affected_paths.len() as u64);
+
+        // screen in log space first. A path's score is the sum of the ln rates of its steps,
+        // corrected by how far off estimates have been from the calculator for the same
+        // protocols, and it passes if the output clears the base's threshold
+        let thresholds: HashMap<Address, f64> = self
+            .config
+            .bases
+            .iter()
+            .map(|base| {
+                let ratio = f64::from(base.min_output()) / f64::from(base.amount);
+                (base.token, ratio.ln())
+            })
+            .collect();
+        let screened: Vec<&SwapPath> = affected_paths
+            .par_iter()
+            .filter(|(path, directions)| {
+                thresholds.get(&path.base_token()).is_some_and(|threshold| {
+                    self.estimator.log_score(directions) + self.calibration.log_correction(path)
+                        >= *threshold
                 })
-                .collect();
+            })
+            .map(|(path, _)| *path)
+            .collect();
 
-            info!("{:?} elapsed estimating paths", res.elapsed());
-            info!("{} estimated profitable paths", profitable_paths.len());
+        // the few that pass are estimated with price impact and checked against the
+        // threshold of the base the path starts in. Profits are compared in eth across bases
+        let profitable_paths: Vec<(SwapPath, U256, U256)> = screened
+            .par_iter()
+            .filter_map(|path| {
+                let base = self.config.base(&path.base_token())?;
+                // estimate if the path is profitable
+                let output_est = self.estimator.estimate_output_amount(path, base.amount);
+                let corrected = self.calibration.correct(path, output_est);
+                if corrected >= base.min_output() && base.to_eth(corrected) < U256::from(1e18) {
+                    // rank by the profit at the input the trade would be sized to
+                    let (input, output) = self.estimator.best_input(path, base);
+                    let output = self.calibration.correct(path, output);
+                    let profit = base.to_eth(output.saturating_sub(input));
+                    Some(((*path).clone(), output_est, profit))
+                } else {
+                    None
+                }
+            })
+            .collect();
 
-            if !profitable_paths.is_empty() {
-                // get the best estimated quote and confirm that it is actual in profit
-                let best_path = profitable_paths.iter().max_by_key(|(_, amt)| amt).unwrap();
-                let calculated_out = self.calculator.calculate_output(&best_path.0);
+        info!("{:?} elapsed estimating paths", res.elapsed());
+        info!(
+            "{} paths passed screening, {} estimated profitable",
+            screened.len(),
+            profitable_paths.len()
+        );
+        ESTIMATED_PATHS.inc_by(profitable_paths.len() as u64);
 
-                if calculated_out >= self.min_profit {
-                    info!("Estimated {}. Calculated {}", best_path.1, calculated_out);
-                    match paths_tx.send(Event::ArbPath((
-                        best_path.0.clone(),
-                        calculated_out,
-                        block_number,
-                    ))) {
-                        Ok(_) => debug!("Sent path"),
-                        Err(_) => debug!("Failed to send path"),
+        let mut found = None;
+        if !profitable_paths.is_empty() {
+            // get the best estimated quote and confirm that it is actual in profit
+            let best_path = profitable_paths.iter().max_by_key(|(_, _, profit)| profit).unwrap();
+            let min_output = self
+                .config
+                .base(&best_path.0.base_token())
+                .map_or(U256::MAX, |base| base.min_output());
+            match self.calculator.calculate_output(&best_path.0) {
+                Ok(calculated_out) => {
+                    info!(
+                        "Path {} estimated {}, calculated {}",
+                        best_path.0.hash, best_path.1, calculated_out
+                    );
+                    let (path, calculated_out) = self.split_legs(&best_path.0, calculated_out);
+                    if calculated_out >= min_output {
+                        CALCULATED_PATHS.inc();
+                        found = Some((path, calculated_out));
                     }
                 }
+                Err(e) => warn!("Skipping path {}: {}", best_path.0.hash, e),
+            }
+        }
+
+        // the best path is the one the estimates overrate the most, so calibrating on it
+        // alone would keep pulling estimates down. Sample all rated touched paths instead,
+        // whether they passed screening or not, and estimate them against this block's rates.
+        // Calculating them waits for calibrate so it never delays the path we found
+        let rated = affected_paths
+            .iter()
+            .filter(|(_, directions)| self.estimator.log_score(directions).is_finite())
+            .map(|(path, _)| *path);
+        let pending: Vec<(SwapPath, U256)> = calibration_sample(rated, block_number, CALIBRATION_SAMPLES)
+            .into_iter()
+            .filter_map(|path| {
+                let base = self.config.base(&path.base_token())?;
+                Some((path.clone(), self.estimator.estimate_output_amount(path, base.amount)))
+            })
+            .collect();
+        self.pending_calibration = pending;
+        SEARCH_SECONDS.observe(res.elapsed().as_secs_f64());
+        found
+    }
+
+    // Calculate the paths sampled by the last search and record how far off their
+    // estimates were. Runs once the search result has been sent
+    pub fn calibrate(&mut self) {
+        for (path, estimated) in std::mem::take(&mut self.pending_calibration) {
+            match self.calculator.calculate_output(&path) {
+                Ok(calculated) => {
+                    let error = self.calibration.record(&path, estimated, calculated);
+                    debug!(
+                        "Calibrating on path {}, estimated {}, calculated {}, error {:?}",
+                        path.hash, estimated, calculated, error
+                    );
+                }
+                Err(e) => debug!("Not calibrating on path {}: {}", path.hash, e),
             }
         }
     }
 }
+
+// Paths to calibrate on, a different pick every block. Path hashes are uniform, so the
+// lowest once mixed with the block number are a fair sample
+fn calibration_sample<'a>(
+    paths: impl Iterator<Item = &'a SwapPath>,
+    block_number: u64,
+    count: usize,
+) -> Vec<&'a SwapPath> {
+    let seed = block_number.wrapping_mul(0x9e37_79b9_7f4a_7c15);
+    let mut paths: Vec<&SwapPath> = paths.collect();
+    paths.sort_by_key(|path| (path.hash ^ seed).wrapping_mul(0x9e37_79b9_7f4a_7c15));
+    paths.truncate(count);
+    paths
+}
+
+#[cfg(test)]
+mod searcher_tests {
+    use super::*;
+    use crate::config::CycleConfig;
+    use crate::swap::SwapStep;
+    use crate::tests::helpers::fixtures::pools;
+    use alloy::primitives::address;
+    use alloy::providers::ProviderBuilder;
+    use pool_sync::{PoolType, UniswapV2Pool};
+
+    // WETH/USDC pool on base with mock reserves
+    fn weth_usdc(address: Address, protocol: fn(UniswapV2Pool) -> Pool) -> Pool {
+        pools::weth_usdc(address, protocol, (325032740126871996707, 1014189875851))
+    }
+
+    // Samples are capped, differ between blocks and are the same for the same block
+    #[test]
+    fn test_calibration_sample() {
+        let paths: Vec<SwapPath> = (0..64u8)
+            .map(|id| {
+                let step = SwapStep {
+                    pool_address: Address::with_last_byte(id),
+                    token_in: Address::with_last_byte(1),
+                    token_out: Address::with_last_byte(2),
+                    protocol: PoolType::UniswapV2,
+                    fee: 0,
+                };
+                SwapPath::new(vec![step])
+            })
+            .collect();
+        let hashes = |block: u64| -> Vec<u64> {
+            calibration_sample(paths.iter(), block, 4).iter().map(|path| path.hash).collect()
+        };
+        assert_eq!(hashes(1).len(), 4);
+        assert_eq!(hashes(1), hashes(1));
+        assert_ne!(hashes(1), hashes(2));
+        assert_eq!(calibration_sample(paths[..2].iter(), 1, 4).len(), 2);
+    }
+
+    // Pools added to a running searcher are tracked and indexed with the cycles they
+    // complete, and removing one drops exactly those cycles again
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_add_remove_pools() {
+        dotenv::dotenv().ok();
+        let config = Arc::new(BotConfig::from_env().unwrap());
+        let uni = weth_usdc(address!("88A43bbDF9D098eEC7bCEda4e2494615dfD9bB9C"), Pool::UniswapV2);
+        let sushi = weth_usdc(address!("2F8818D1B0f3e3E295440c1C0cDDf40aAA21fA87"), Pool::SushiSwapV2);
+        let pancake = weth_usdc(address!("79474223AEdD0339780baCcE75aBDa0BE84dcBF9"), Pool::PancakeSwapV2);
+        let pools = vec![uni, sushi];
+
+        let provider = ProviderBuilder::new().on_http(config.http_url.clone());
+        let block = provider.get_block_number().await.unwrap();
+        let market_state = MarketState::init_state(pools.clone(), block, provider, &config)
+            .await
+            .unwrap();
+        let mut estimator = Estimator::new(market_state.clone(), config.clone());
+        estimator.process_pools(pools.clone());
+        let cycle_config = CycleConfig {
+            max_hops: 2,
+            min_liquidity: U256::ZERO,
+            max_cycles_per_pool: None,
+            cache_dir: None,
+        };
+        let graph = ArbGraph::build(pools, vec![config.weth], cycle_config);
+        let mut searcher = Searchoor::new(graph, market_state.clone(), estimator, config);
+        assert_eq!(searcher.directions.len(), 2);
+
+        let delta = searcher.add_pools(vec![pancake.clone()]);
+        assert_eq!(delta.added.len(), 4);
+        assert_eq!(searcher.path_index[&pancake.address()].len(), 4);
+        assert_eq!(searcher.directions.len(), 6);
+        assert!(market_state.db.read().unwrap().tracking_pool(&pancake.address()));
+        assert!(searcher.add_pools(vec![pancake.clone()]).added.is_empty());
+
+        let delta = searcher.remove_pools(&HashSet::from([pancake.address()]));
+        assert_eq!(delta.retired.len(), 4);
+        assert!(!searcher.path_index.contains_key(&pancake.address()));
+        assert_eq!(searcher.directions.len(), 2);
+        assert!(searcher.directions.keys().all(|hash| searcher.graph.cycle(*hash).is_some()));
+        assert!(!market_state.db.read().unwrap().tracking_pool(&pancake.address()));
+    }
+}
@@ -4,88 +4,154 @@ use alloy::transports::http::{Client, Http};
 use alloy::primitives::U256;
 use log::{debug, info, warn};
 use std::collections::HashSet;
-use std::sync::mpsc::{Receiver, Sender};
 use std::sync::Arc;
+use tokio::sync::mpsc::{Receiver, Sender};
 
 use crate::calculation::Calculator;
+use crate::config::BotConfig;
 use crate::events::Event;
-use crate::gen::FlashQuoter;
+use crate::gen::FlashQuoter::SwapParams;
+use crate::health::Health;
+use crate::journal::Journal;
 use crate::market_state::MarketState;
+use crate::metrics::{BLACKLISTED_PATHS, QUOTES, STALE_EVENTS};
 use crate::quoter::Quoter;
-use crate::AMOUNT;
+use crate::swap::SwapPath;
 
 // recieve a stream of potential arbitrage paths from the searcher and
-// simulate them against the contract to determine if they are actually viable
+// simulate them against the contract to determine if they are actually viable.
+// Paths found on a block older than the head are stale and dropped
 pub async fn simulate_paths(
     tx_sender: Sender<Event>,
-    arb_receiver: Receiver<Event>,
+    mut arb_receiver: Receiver<Event>,
     market_state: Arc<MarketState<Http<Client>, Ethereum, RootProvider<Http<Client>>>>,
+    health: Arc<Health>,
+    journal: Journal,
+    config: Arc<BotConfig>,
 ) {
-    // if this is just a sim run or not
-    let sim: bool = std::env::var("SIM").unwrap().parse().unwrap();
-
     // blacklisted paths, some error in swapping that wasnt caught during filter
     let mut blacklisted_paths: HashSet<u64> = HashSet::new();
 
     // recieve new paths from the searcher
-    while let Ok(Event::ArbPath((arb_path, expected_out, block_number))) = arb_receiver.recv() {
-        // convert from searcher format into quoter format
-        let mut converted_path: FlashQuoter::SwapParams = arb_path.clone().into();
-        println!("{:?}", converted_path);
+    while let Some(Event::ArbPath((arb_path, expected_out, block_number))) = arb_receiver.recv().await {
+        if health.is_stale(block_number) {
+            debug!("Dropping stale path from block {}", block_number);
+            STALE_EVENTS.with_label_values(&["simulator"]).inc();
+            continue;
+        }
 
-        // get the quote for the path and handle it appropriately
-        // if we have not blacklisted the path
-        if !blacklisted_paths.contains(&arb_path.hash) {
-            info!("Simulating a new path...");
-            // get an initial quote to see if we can swap
-            // get read access to the db so we can quote the path
-            match Quoter::quote_path(converted_path.clone(), market_state.clone()) {
-                Ok(quote) => {
-                    // if we are just simulated, compare to the expected amount
-                    if sim {
-                        if *(quote.last().unwrap()) == expected_out {
-                            info!(
-                                "Success.. Calculated {expected_out}, Quoted: {}, Path Hash {}",
-                                quote.last().unwrap(),
-                                arb_path.hash
-                            );
-                        } else {
-                            // get a full debug quote path
-                            let calculator = Calculator::new(market_state.clone());
-                            calculator.debug_calculation(&arb_path);
-                        }
-                    } else {
-                        if *quote.last().unwrap() > U256::from(1e18) {
-                            continue;
-                        };
+        let Some((converted_path, profit)) = simulate_path(
+            &arb_path,
+            expected_out,
+            block_number,
+            &market_state,
+            &config,
+            &mut blacklisted_paths,
+        ) else {
+            continue;
+        };
 
-                        info!(
-                            "Sim successful... Estimated output: {}, Block {}",
-                            expected_out, block_number
-                        );
+        // a new block may have come in while we were simulating
+        if health.is_stale(block_number) {
+            debug!("Path from block {} went stale during simulation", block_number);
+            STALE_EVENTS.with_label_values(&["simulator"]).inc();
+            continue;
+        }
+        let event = Event::ValidPath((converted_path, profit, block_number));
+        journal.record_event(&event);
+        match tx_sender.send(event).await {
+            Ok(_) => debug!("Simulator sent path to Tx Sender"),
+            Err(_) => warn!("Simulator: failed to send path to tx sender"),
+        }
+    }
+}
 
+// Quote a single path against the contract and optimize its input. Returns the
+// swap params and expected profit in eth if the path should be sent, paths that fail
+// to quote are blacklisted
+pub fn simulate_path(
+    arb_path: &SwapPath,
+    expected_out: U256,
+    block_number: u64,
+    market_state: &Arc<MarketState<Http<Client>, Ethereum, RootProvider<Http<Client>>>>,
+    config: &Arc<BotConfig>,
+    blacklisted_paths: &mut HashSet<u64>,
+) -> Option<(SwapParams, U256)> {
+    // get the quote for the path and handle it appropriately
+    // if we have not blacklisted the path
+    if blacklisted_paths.contains(&arb_path.hash) {
+        return None;
+    }
+    let Some(base) = config.base(&arb_path.base_token()) else {
+        warn!("Path {} does not start in a base token", arb_path.hash);
+        return None;
+    };
 
+    // convert from searcher format into quoter format
+    let mut converted_path = arb_path.to_quote_params(base.amount);
+    debug!("{:?}", converted_path);
 
-                        // now optimize the input
-                        let optimized_amounts = Quoter::optimize_input(converted_path.clone(), *quote.last().unwrap(), market_state.clone());
-                        info!("Optimized input: {}. Optimized output: {}", optimized_amounts.0, optimized_amounts.1);
-                        let profit = expected_out - *AMOUNT;
-                        converted_path.amountIn = optimized_amounts.0;
+    info!("Simulating a new path...");
+    // get an initial quote to see if we can swap
+    // get read access to the db so we can quote the path
+    let (quote, quoted_out) = match Quoter::quote_path(converted_path.clone(), market_state.clone()) {
+        Ok(quote) if !quote.is_empty() => {
+            QUOTES.with_label_values(&["success"]).inc();
+            let quoted_out = *quote.last().unwrap();
+            (quote, quoted_out)
+        }
+        Ok(_) => {
+            warn!("Quoter returned no amounts for path {}", arb_path.hash);
+            return None;
+        }
+        Err(quote_err) => {
+            QUOTES.with_label_values(&["revert"]).inc();
+            info!(
+                "Failed to simulate quote {}, {:#?} ",
+                quote_err, arb_path.hash
+            );
+            blacklisted_paths.insert(arb_path.hash);
+            BLACKLISTED_PATHS.set(blacklisted_paths.len() as i64);
+            return None;
+        }
+    };
 
-                        match tx_sender.send(Event::ValidPath((converted_path, profit, block_number))) {
-                            Ok(_) => debug!("Simulator sent path to Tx Sender"),
-                            Err(_) => warn!("Simulator: failed to send path to tx sender"),
-                        }
-                    }
-                }
-                Err(quote_err) => {
-                    info!(
-                        "Failed to simulate quote {}, {:#?} ",
-                        quote_err, arb_path.hash
-                    );
-                    blacklisted_paths.insert(arb_path.hash);
-                }
+    // if we are just simulated, compare to the expected amount
+    if config.simulate_only {
+        if quoted_out == expected_out {
+            info!(
+                "Success.. Calculated {expected_out}, Quoted: {}, Path Hash {}",
+                quoted_out, arb_path.hash
+            );
+        } else {
+            // get a full debug quote path
+            let calculator = Calculator::new(market_state.clone(), config.clone());
+            match calculator.debug_calculation(arb_path) {
+                Ok(amounts) => info!("Mismatch.. Quoted {:?}, Calculated {:?}", quote, amounts),
+                Err(e) => warn!("Failed to calculate path {}: {}", arb_path.hash, e),
             }
         }
+        return None;
     }
+
+    if base.to_eth(quoted_out) > U256::from(1e18) {
+        return None;
+    };
+
+    info!(
+        "Sim successful... Estimated output: {}, Block {}",
+        expected_out, block_number
+    );
+
+    // now optimize the input
+    let optimized_amounts = Quoter::optimize_input(
+        converted_path.clone(),
+        quoted_out,
+        market_state.clone(),
+        base,
+    );
+    info!("Optimized input: {}. Optimized output: {}", optimized_amounts.0, optimized_amounts.1);
+    let profit = base.to_eth(expected_out.saturating_sub(base.amount));
+    converted_path.amountIn = optimized_amounts.0;
+    Some((converted_path, profit))
 }
@@ -0,0 +1,286 @@
+use super::BlockStateDB;
+use alloy::network::Network;
+use alloy::primitives::{address, Address, Bytes, B256, U256};
+use alloy::providers::Provider;
+use alloy::sol;
+use alloy::sol_types::SolCall;
+use alloy::transports::Transport;
+use log::trace;
+use pool_sync::{Pool, PoolInfo};
+use revm::primitives::{ExecutionResult, TransactTo};
+use revm::Evm;
+use std::collections::HashSet;
+
+use crate::errors::StateError;
+
+// Every Balancer V2 pool keeps its balances in the one Vault, at the same address on every chain
+pub const BALANCER_VAULT: Address = address!("BA12222222228d8Ba445958a75a0704d566BF2C8");
+
+sol!(
+    #[derive(Debug)]
+    contract BalancerVault {
+        function getPoolTokens(bytes32 poolId) external view returns (
+            address[] tokens,
+            uint256[] balances,
+            uint256 lastChangeBlock
+        );
+    }
+);
+
+sol!(
+    #[derive(Debug)]
+    contract BalancerWeightedPool {
+        function getPoolId() external view returns (bytes32);
+        function getNormalizedWeights() external view returns (uint256[]);
+        function getSwapFeePercentage() external view returns (uint256);
+    }
+);
+
+sol!(
+    #[derive(Debug)]
+    contract BalancerToken {
+        function decimals() external view returns (uint8);
+    }
+);
+
+// State of a weighted pool as the pool and the Vault report it. Balances, weights and the
+// swap fee are 18 decimal fixed point like the contracts use them
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BalancerState {
+    pub pool_id: B256,
+    // in Vault order, every other field is indexed the same way
+    pub tokens: Vec<Address>,
+    pub decimals: Vec<u8>,
+    pub balances: Vec<U256>,
+    pub weights: Vec<U256>,
+    pub swap_fee: U256,
+    // Vault slots the balances were read from, a diff to any of them changes the pool
+    pub vault_slots: HashSet<U256>,
+}
+
+impl BalancerState {
+    pub fn token_index(&self, token: &Address) -> Option<usize> {
+        self.tokens.iter().position(|pool_token| pool_token == token)
+    }
+}
+
+/// balancer db read/write related methods
+impl<T, N, P> BlockStateDB<T, N, P>
+where
+    T: Transport + Clone,
+    N: Network,
+    P: Provider<T, N>,
+{
+    // Insert a new balancer weighted pool into the database. Unlike v2 and v3 the state is not
+    // written into storage from the synced pool, it is read through the pool and the Vault so
+    // it is always decoded the way the contracts see it
+    pub fn insert_balancer(&mut self, pool: Pool) -> Result<(), StateError> {
+        trace!("Adding new balancer pool {}", pool.address());
+        let address = pool.address();
+        if !matches!(pool, Pool::BalancerV2(_)) {
+            return Err(StateError::InvalidPool(address, "not a balancer pool".to_string()));
+        }
+        self.add_pool(pool)?;
+
+        // a pool we can not read is not tracked at all
+        self.load_balancer(address).inspect_err(|_| self.remove_pool(&address))
+    }
+
+    // Decode the state of a tracked balancer pool from scratch
+    pub fn load_balancer(&mut self, pool: Address) -> Result<(), StateError> {
+        let pool_id = self.read_pool_id(pool)?;
+        let state = self.read_balancer(pool, pool_id, None)?;
+        self.balancer_pools.insert(pool, state);
+        Ok(())
+    }
+
+    // Read the pool state again after a block touched the pool or its Vault balances
+    pub fn refresh_balancer(&mut self, pool: Address) -> Result<(), StateError> {
+        let known = self
+            .balancer_pools
+            .get(&pool)
+            .ok_or(StateError::UntrackedPool(pool))?;
+        let (pool_id, known) = (known.pool_id, Some((known.tokens.clone(), known.decimals.clone())));
+        let state = self.read_balancer(pool, pool_id, known)?;
+        self.balancer_pools.insert(pool, state);
+        Ok(())
+    }
+
+    // The decoded state of a balancer pool
+    #[inline]
+    pub fn balancer_state(&self, pool: &Address) -> Result<&BalancerState, StateError> {
+        self.balancer_pools
+            .get(pool)
+            .ok_or(StateError::UntrackedPool(*pool))
+    }
+
+    #[inline]
+    pub fn is_balancer(&self, pool: &Address) -> bool {
+        self.balancer_pools.contains_key(pool)
+    }
+
+    // Balancer pools whose balances live in any of the Vault slots
+    pub fn balancer_pools_at(&self, vault_slots: &HashSet<U256>) -> Vec<Address> {
+        self.balancer_pools
+            .iter()
+            .filter(|(_, state)| !state.vault_slots.is_disjoint(vault_slots))
+            .map(|(pool, _)| *pool)
+            .collect()
+    }
+
+    fn read_pool_id(&mut self, pool: Address) -> Result<B256, StateError> {
+        let output = self.view_call(pool, BalancerWeightedPool::getPoolIdCall {}.abi_encode())?;
+        BalancerWeightedPool::getPoolIdCall::abi_decode_returns(&output, false)
+            .map(|pool_id| pool_id._0)
+            .map_err(|e| StateError::InvalidPool(pool, e.to_string()))
+    }
+
+    // Read the tokens and balances from the Vault and the weights and fee from the pool.
+    // Token decimals never change, so they are only read when the tokens are new
+    fn read_balancer(
+        &mut self,
+        pool: Address,
+        pool_id: B256,
+        known: Option<(Vec<Address>, Vec<u8>)>,
+    ) -> Result<BalancerState, StateError> {
+        let invalid = |e: alloy::sol_types::Error| StateError::InvalidPool(pool, e.to_string());
+
+        let calldata = BalancerVault::getPoolTokensCall { poolId: pool_id }.abi_encode();
+        let (output, vault_slots) = self.view_call_slots(BALANCER_VAULT, calldata)?;
+        let pool_tokens =
+            BalancerVault::getPoolTokensCall::abi_decode_returns(&output, false).map_err(invalid)?;
+
+        let output = self.view_call(pool, BalancerWeightedPool::getNormalizedWeightsCall {}.abi_encode())?;
+        let weights = BalancerWeightedPool::getNormalizedWeightsCall::abi_decode_returns(&output, false)
+            .map_err(invalid)?
+            ._0;
+        let output = self.view_call(pool, BalancerWeightedPool::getSwapFeePercentageCall {}.abi_encode())?;
+        let swap_fee = BalancerWeightedPool::getSwapFeePercentageCall::abi_decode_returns(&output, false)
+            .map_err(invalid)?
+            ._0;
+
+        let decimals = match known {
+            Some((tokens, decimals)) if tokens == pool_tokens.tokens => decimals,
+            _ => {
+                let mut decimals = Vec::with_capacity(pool_tokens.tokens.len());
+                for token in &pool_tokens.tokens {
+                    let output = self.view_call(*token, BalancerToken::decimalsCall {}.abi_encode())?;
+                    let token_decimals = BalancerToken::decimalsCall::abi_decode_returns(&output, false)
+                        .map_err(invalid)?
+                        ._0;
+                    decimals.push(token_decimals);
+                }
+                decimals
+            }
+        };
+
+        if weights.len() != pool_tokens.tokens.len() {
+            return Err(StateError::InvalidPool(pool, "not a weighted pool".to_string()));
+        }
+        if decimals.iter().any(|decimals| *decimals > 18) {
+            return Err(StateError::InvalidPool(pool, "token with more than 18 decimals".to_string()));
+        }
+        Ok(BalancerState {
+            pool_id,
+            tokens: pool_tokens.tokens,
+            decimals,
+            balances: pool_tokens.balances,
+            weights,
+            swap_fee,
+            vault_slots,
+        })
+    }
+
+    fn view_call(&mut self, to: Address, calldata: Vec<u8>) -> Result<Bytes, StateError> {
+        self.view_call_slots(to, calldata).map(|(output, _)| output)
+    }
+
+    // Run a view call against the database, returning its output and the slots of the called
+    // contract it read. Missing state is fetched and kept like any other read
+    fn view_call_slots(
+        &mut self,
+        to: Address,
+        calldata: Vec<u8>,
+    ) -> Result<(Bytes, HashSet<U256>), StateError> {
+        let mut evm = Evm::builder()
+            .with_db(&mut *self)
+            .modify_tx_env(|tx| {
+                tx.caller = Address::ZERO;
+                tx.transact_to = TransactTo::Call(to);
+                tx.data = calldata.into();
+            })
+            .build();
+        let outcome = evm
+            .transact()
+            .map_err(|e| StateError::Provider(e.to_string()))?;
+        let slots = outcome
+            .state
+            .get(&to)
+            .map(|account| account.storage.keys().copied().collect())
+            .unwrap_or_default();
+        match outcome.result {
+            ExecutionResult::Success { output, .. } => Ok((output.into_data(), slots)),
+            _ => Err(StateError::CallReverted(to)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod balancer_db_test {
+    use super::*;
+    use crate::tests::helpers::test_utils::utils::load_and_filter_pools;
+    use alloy::providers::ProviderBuilder;
+    use alloy::rpc::types::trace::geth::AccountState;
+    use pool_sync::PoolType;
+    use revm::DatabaseRef;
+    use std::collections::BTreeMap;
+
+    // Balances written to the Vault through update_all_slots are decoded by refresh_balancer,
+    // the pool is found from the slots it reads, and a snapshot restored into an offline db
+    // decodes the same state again
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_vault_slots() {
+        dotenv::dotenv().ok();
+        let url = std::env::var("FULL").unwrap().parse().unwrap();
+        let provider = ProviderBuilder::new().on_http(url);
+        let mut db = BlockStateDB::new(provider.clone()).unwrap();
+
+        let (pools, _) = load_and_filter_pools(vec![PoolType::BalancerV2]).await;
+        let pool = pools
+            .into_iter()
+            .find(|pool| db.insert_balancer(pool.clone()).is_ok())
+            .expect("no weighted balancer pool in the working set");
+        let address = pool.address();
+        let before = db.balancer_state(&address).unwrap().clone();
+        assert_eq!(db.balancer_pools_at(&before.vault_slots), vec![address]);
+        assert!(db.balancer_pools_at(&HashSet::from([U256::MAX])).is_empty());
+
+        // balances are packed with the last change block in the top 32 bits, double the
+        // cash in the low 112 bits of each
+        let cash = (U256::from(1) << 112) - U256::from(1);
+        let mut storage = BTreeMap::new();
+        for slot in &before.vault_slots {
+            let value = db.storage_ref(BALANCER_VAULT, *slot).unwrap();
+            if !(value >> 224).is_zero() {
+                let doubled = value + (value & cash);
+                storage.insert(B256::from(slot.to_be_bytes::<32>()), B256::from(doubled.to_be_bytes::<32>()));
+            }
+        }
+        assert!(!storage.is_empty());
+        let diff = AccountState {
+            storage,
+            ..Default::default()
+        };
+        db.update_all_slots(BALANCER_VAULT, diff).unwrap();
+        db.refresh_balancer(address).unwrap();
+        let after = db.balancer_state(&address).unwrap().clone();
+        assert_eq!(after.tokens, before.tokens);
+        assert_eq!(after.weights, before.weights);
+        assert!(after.balances.iter().zip(&before.balances).all(|(after, before)| after >= before));
+        assert_ne!(after.balances, before.balances);
+
+        let mut restored = BlockStateDB::new_offline(provider).unwrap();
+        restored.restore(vec![pool], db.snapshot());
+        assert_eq!(restored.balancer_state(&address).unwrap(), &after);
+    }
+}
@@ -4,7 +4,7 @@ use alloy::primitives::{Address, BlockNumber, B256, U256};
 use alloy::providers::Provider;
 use alloy::rpc::types::trace::geth::AccountState as GethAccountState;
 use alloy::rpc::types::BlockId;
-use alloy::transports::{Transport, TransportError};
+use alloy::transports::Transport;
 use anyhow::Result;
 use log::{debug, trace, warn};
 use pool_sync::PoolInfo;
@@ -18,6 +18,9 @@ use std::future::IntoFuture;
 use pool_sync::Pool;
 use tokio::runtime::Handle;
 
+use crate::errors::StateError;
+use crate::state_db::BalancerState;
+
 #[derive(Debug)]
 pub enum HandleOrRuntime {
     Handle(Handle),
@@ -53,15 +56,28 @@ pub struct BlockStateDB<T: Transport + Clone, N: Network, P: Provider<T, N>> {
     pub pools: HashSet<Address>,
     //
     pub pool_info: HashMap<Address, Pool>,
+    // Decoded state of the balancer pools, read through the Vault
+    pub balancer_pools: HashMap<Address, BalancerState>,
     // provider for fetching information
     provider: P,
+    // when offline, the provider is never queried and missing state reads as empty
+    offline: bool,
     runtime: HandleOrRuntime,
     _marker: std::marker::PhantomData<fn() -> (T, N)>,
 }
 
 impl<T: Transport + Clone, N: Network, P: Provider<T, N>> BlockStateDB<T, N, P> {
-    // Construct a new BlockStateDB
+    // Construct a new BlockStateDB that fetches missing state from the provider
     pub fn new(provider: P) -> Option<Self> {
+        Self::with_mode(provider, false)
+    }
+
+    // Construct a new BlockStateDB that never touches the provider, used for replay
+    pub fn new_offline(provider: P) -> Option<Self> {
+        Self::with_mode(provider, true)
+    }
+
+    fn with_mode(provider: P, offline: bool) -> Option<Self> {
         debug!("Creating new BlockStateDB");
         let contracts = HashMap::new();
         contracts.insert(KECCAK_EMPTY, Bytecode::default());
@@ -83,7 +99,9 @@ impl<T: Transport + Clone, N: Network, P: Provider<T, N>> BlockStateDB<T, N, P>
             block_hashes: HashMap::new(),
             pools: HashSet::new(),
             pool_info: HashMap::new(),
+            balancer_pools: HashMap::new(),
             provider,
+            offline,
             runtime: rt,
             _marker: std::marker::PhantomData,
         })
@@ -93,7 +111,7 @@ impl<T: Transport + Clone, N: Network, P: Provider<T, N>> BlockStateDB<T, N, P>
     pub fn add_pool(
         &mut self,
         pool: Pool
-    ) {
+    ) -> Result<(), StateError> {
 
         let pool_address = pool.address();
         trace!("Adding pool {} to database", pool_address);
@@ -104,13 +122,15 @@ impl<T: Transport + Clone, N: Network, P: Provider<T, N>> BlockStateDB<T, N, P>
 
         // fetch the onchain pool account and insert it into database
         // this is onchain because it has onchain state, the slots will be custom
-        let pool_account = <Self as DatabaseRef>::basic_ref(self, pool_address);
+        let pool_account = <Self as DatabaseRef>::basic_ref(self, pool_address)?
+            .ok_or(StateError::MissingAccount(pool_address))?;
         let new_db_account = BlockStateDBAccount {
-            info: pool_account.unwrap().unwrap(),
+            info: pool_account,
             insertion_type: InsertionType::OnChain,
             ..Default::default()
         };
         self.accounts.insert(pool_address, new_db_account);
+        Ok(())
     }
 
 
@@ -119,6 +139,15 @@ impl<T: Transport + Clone, N: Network, P: Provider<T, N>> BlockStateDB<T, N, P>
         self.pool_info.get(pool_address).unwrap()
     }
 
+    // Stop tracking a pool and drop its state
+    pub fn remove_pool(&mut self, pool_address: &Address) {
+        trace!("Removing pool {} from database", pool_address);
+        self.pools.remove(pool_address);
+        self.pool_info.remove(pool_address);
+        self.balancer_pools.remove(pool_address);
+        self.accounts.remove(pool_address);
+    }
+
 
     // Check if we are tracking the pool. This is our working set
     #[inline]
@@ -128,8 +157,11 @@ impl<T: Transport + Clone, N: Network, P: Provider<T, N>> BlockStateDB<T, N, P>
 
     // Compute zero to one for amount out computations
     #[inline]
-    pub fn zero_to_one(&self, pool: &Address, token_in: Address) -> Option<bool> {
-        self.pool_info.get(pool).map(|info| info.token0_address() == token_in)
+    pub fn zero_to_one(&self, pool: &Address, token_in: Address) -> Result<bool, StateError> {
+        self.pool_info
+            .get(pool)
+            .map(|info| info.token0_address() == token_in)
+            .ok_or(StateError::UntrackedPool(*pool))
     }
 
     // Go through a block trace and update all relevant slots
@@ -189,7 +221,9 @@ impl<T: Transport + Clone, N: Network, P: Provider<T, N>> BlockStateDB<T, N, P>
 
         // The account does not exist. Fetch account information from provider and insert account
         // into database
-        let account = self.basic(account_address)?.unwrap();
+        let account = self
+            .basic(account_address)?
+            .ok_or(StateError::MissingAccount(account_address))?;
         self.insert_account_info(account_address, account, insertion_type);
 
         // The account is now in the database, so fetch and insert the storage value
@@ -207,7 +241,7 @@ impl<T: Transport + Clone, N: Network, P: Provider<T, N>> BlockStateDB<T, N, P>
 
 // Implement the database trait for the BlockStateDB
 impl<T: Transport + Clone, N: Network, P: Provider<T, N>> Database for BlockStateDB<T, N, P> {
-    type Error = TransportError;
+    type Error = StateError;
 
     // Get basic account information
     fn basic(&mut self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
@@ -224,7 +258,7 @@ impl<T: Transport + Clone, N: Network, P: Provider<T, N>> Database for BlockStat
             address
         );
         // Fetch the account from the chain
-        let account_info = Self::basic_ref(self, address)?.unwrap();
+        let account_info = Self::basic_ref(self, address)?.ok_or(StateError::MissingAccount(address))?;
         match self.accounts.get_mut(&address) {
             Some(account) => account.info = account_info.clone(),
             None => self.insert_account_info(address, account_info.clone(), InsertionType::OnChain),
@@ -356,7 +390,7 @@ impl<T: Transport + Clone, N: Network, P: Provider<T, N>> Database for BlockStat
 
 // Implement required DatabaseRef trait, read references to the database (fetch from provider)
 impl<T: Transport + Clone, N: Network, P: Provider<T, N>> DatabaseRef for BlockStateDB<T, N, P> {
-    type Error = TransportError;
+    type Error = StateError;
 
     // Get basic account information
     fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
@@ -366,6 +400,12 @@ impl<T: Transport + Clone, N: Network, P: Provider<T, N>> DatabaseRef for BlockS
             return Ok(Some(account.info.clone()));
         }
 
+        // offline, there is no one to ask so the account is empty
+        if self.offline {
+            trace!("Database BasicRef: Account {} not found, offline so it is empty", address);
+            return Ok(Some(AccountInfo::default()));
+        }
+
         // we do not have the account, fetch from the provider
         trace!(
             "Database BasicRef: Account {} not found in cache. Fetching info from provider",
@@ -426,8 +466,8 @@ impl<T: Transport + Clone, N: Network, P: Provider<T, N>> DatabaseRef for BlockS
             return Ok(code.clone());
         }
 
-        // the code should already be loaded??
-        panic!("The code should already be loaded");
+        // code is loaded alongside its account, so there is nothing to fetch
+        Err(StateError::MissingCode(code_hash))
     }
 
     // Get storage value of address at index
@@ -448,6 +488,11 @@ impl<T: Transport + Clone, N: Network, P: Provider<T, N>> DatabaseRef for BlockS
             }
         }
 
+        if self.offline {
+            trace!("Database Storage Ref: Slot {} for {} not found, offline so it is zero", index, address);
+            return Ok(U256::ZERO);
+        }
+
         trace!(
             "Database Storage Ref: Account {} not found. Fetching slot {} from provider",
             address,
@@ -469,6 +514,10 @@ impl<T: Transport + Clone, N: Network, P: Provider<T, N>> DatabaseRef for BlockS
                 );
                 Ok(*entry)
             }
+            None if self.offline => {
+                warn!("No block hash for block number {:?} while offline", number);
+                Ok(B256::ZERO)
+            }
             None => {
                 debug!(
                     "Block hash not found in cache, fetching from provider for block number: {:?}",
@@ -1,4 +1,8 @@
+pub use balancer_db::{BalancerState, BALANCER_VAULT};
 pub use blockstate_db::{BlockStateDB, InsertionType};
+pub use snapshot::StateSnapshot;
+mod balancer_db;
 mod blockstate_db;
+mod snapshot;
 mod v2_db;
 mod v3_db;
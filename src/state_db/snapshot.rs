@@ -0,0 +1,104 @@
+use super::BlockStateDB;
+use alloy::network::Network;
+use alloy::primitives::{Address, Bytes, U256};
+use alloy::providers::Provider;
+use alloy::transports::Transport;
+use log::{trace, warn};
+use pool_sync::{Pool, PoolInfo};
+use revm::primitives::{AccountInfo, Bytecode};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+
+use crate::state_db::blockstate_db::{BlockStateDBSlot, InsertionType};
+
+// Everything the database holds at a point in time. Restoring this into an offline
+// database lets us run without a provider
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct StateSnapshot {
+    pub accounts: BTreeMap<Address, AccountSnapshot>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccountSnapshot {
+    pub balance: U256,
+    pub nonce: u64,
+    pub code: Bytes,
+    pub storage: BTreeMap<U256, U256>,
+}
+
+/// snapshot and restore related methods
+impl<T, N, P> BlockStateDB<T, N, P>
+where
+    T: Transport + Clone,
+    N: Network,
+    P: Provider<T, N>,
+{
+    // Take a snapshot of every account and slot in the database
+    pub fn snapshot(&self) -> StateSnapshot {
+        let accounts = self
+            .accounts
+            .iter()
+            .map(|(address, account)| {
+                let code = account
+                    .info
+                    .code
+                    .clone()
+                    .or_else(|| self.contracts.get(&account.info.code_hash).cloned())
+                    .map(|code| code.original_bytes())
+                    .unwrap_or_default();
+                let storage = account
+                    .storage
+                    .iter()
+                    .map(|(slot, value)| (*slot, value.value))
+                    .collect();
+                let snapshot = AccountSnapshot {
+                    balance: account.info.balance,
+                    nonce: account.info.nonce,
+                    code,
+                    storage,
+                };
+                (*address, snapshot)
+            })
+            .collect();
+        StateSnapshot { accounts }
+    }
+
+    // Load a snapshot into the database and track the pools without fetching anything
+    pub fn restore(&mut self, pools: Vec<Pool>, snapshot: StateSnapshot) {
+        let mut balancer_pools = Vec::new();
+        for pool in pools {
+            trace!("Restoring pool {}", pool.address());
+            if matches!(pool, Pool::BalancerV2(_)) {
+                balancer_pools.push(pool.address());
+            }
+            self.pools.insert(pool.address());
+            self.pool_info.insert(pool.address(), pool);
+        }
+
+        for (address, account) in snapshot.accounts {
+            let code = Bytecode::new_raw(account.code);
+            let code_hash = code.hash_slow();
+            self.contracts.insert(code_hash, code.clone());
+            let info = AccountInfo::new(account.balance, account.nonce, code_hash, code);
+            self.insert_account_info(address, info, InsertionType::OnChain);
+
+            let db_account = self.accounts.get_mut(&address).unwrap();
+            for (slot, value) in account.storage {
+                db_account.storage.insert(
+                    slot,
+                    BlockStateDBSlot {
+                        value,
+                        insertion_type: InsertionType::OnChain,
+                    },
+                );
+            }
+        }
+
+        // balancer state is decoded again from the restored pool and Vault storage
+        for pool in balancer_pools {
+            if let Err(e) = self.load_balancer(pool) {
+                warn!("Failed to restore balancer pool {}: {}", pool, e);
+            }
+        }
+    }
+}
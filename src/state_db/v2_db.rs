@@ -8,6 +8,7 @@ use log::trace;
 use pool_sync::{Pool, PoolInfo};
 use revm::DatabaseRef;
 use zerocopy::IntoBytes;
+use crate::errors::StateError;
 use crate::state_db::blockstate_db::{InsertionType, BlockStateDBSlot};
 
 lazy_static! {
@@ -22,18 +23,19 @@ where
     P: Provider<T, N>,
 {
     // insert a new uniswapv2 pool into the database
-    pub fn insert_v2(&mut self, pool: Pool) {
+    pub fn insert_v2(&mut self, pool: Pool) -> Result<(), StateError> {
         trace!("Adding new v2 pool {}", pool.address());
         let address = pool.address();
         let token0 = pool.token0_address();
         let token1 = pool.token1_address();
 
         // track the pool
-        self.add_pool(pool.clone());
-
+        let v2_pool = pool
+            .get_v2()
+            .ok_or_else(|| StateError::InvalidPool(address, "not a v2 pool".to_string()))?;
+        self.add_pool(pool.clone())?;
 
         // get v2 info
-        let v2_pool = pool.get_v2().unwrap();
         let reserve0 = U256::from(v2_pool.token0_reserves);
         let reserve1 = U256::from(v2_pool.token1_reserves);
 
@@ -42,30 +44,31 @@ where
         self.insert_reserves(address, reserve0, reserve1);
         self.insert_token0(address, token0);
         self.insert_token1(address, token1);
+        Ok(())
     }
 
     // Function to retrieve V2 Pool state
     #[inline]
-    pub fn get_reserves(&self, pool: &Address) -> (U256, U256) {
-        let value = self.storage_ref(*pool, U256::from(8)).unwrap();
-        ((value >> 0) & *U112_MASK, (value >> (112)) & *U112_MASK)
+    pub fn get_reserves(&self, pool: &Address) -> Result<(U256, U256), StateError> {
+        let value = self.storage_ref(*pool, U256::from(8))?;
+        Ok(((value >> 0) & *U112_MASK, (value >> (112)) & *U112_MASK))
     }
 
     // get token 0
-    pub fn get_token0(&self, pool: Address) -> Address {
-        let token0 = self.storage_ref(pool, U256::from(6)).unwrap();
-        Address::from_word(token0.into())
+    pub fn get_token0(&self, pool: Address) -> Result<Address, StateError> {
+        let token0 = self.storage_ref(pool, U256::from(6))?;
+        Ok(Address::from_word(token0.into()))
     }
 
     #[warn(dead_code)]
-    pub fn get_token1(&self, pool: Address) -> Address {
-        let token1 = self.storage_ref(pool, U256::from(7)).unwrap();
-        Address::from_word(token1.into())
+    pub fn get_token1(&self, pool: Address) -> Result<Address, StateError> {
+        let token1 = self.storage_ref(pool, U256::from(7))?;
+        Ok(Address::from_word(token1.into()))
     }
 
     #[warn(dead_code)]
-    pub fn get_tokens(&self, _pool: &Address) -> (Address, Address) {
-        todo!()
+    pub fn get_tokens(&self, pool: &Address) -> Result<(Address, Address), StateError> {
+        Ok((self.get_token0(*pool)?, self.get_token1(*pool)?))
     }
 
     // Functions to insert v2 pool state
@@ -195,12 +198,12 @@ mod test_db_v2 {
         let token1 = address!("C02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2");
 
         let pool = uni_v2_weth_usdc();
-        db.insert_v2(Pool::UniswapV2(pool));
+        db.insert_v2(Pool::UniswapV2(pool)).unwrap();
         db.insert_reserves(pool_addr, U256::from(10), U256::from(20));
-        let (res0, res1) = db.get_reserves(&pool_addr);
+        let (res0, res1) = db.get_reserves(&pool_addr).unwrap();
 
-        assert_eq!(db.get_token0(pool_addr), token0, "");
-        assert_eq!(db.get_token1(pool_addr), token1);
+        assert_eq!(db.get_token0(pool_addr).unwrap(), token0, "");
+        assert_eq!(db.get_token1(pool_addr).unwrap(), token1);
         assert_eq!(res0, U256::from(10));
         assert_eq!(res1, U256::from(20));
     }
@@ -221,8 +224,8 @@ mod test_db_v2 {
         let expected_token1 = address!("833589fCD6eDb6E08f4c7C32D4f71b54bdA02913");
 
         // Fetch and assert token addresses
-        let fetched_token1 = db.get_token1(pool_addr);
-        let fetched_token0 = db.get_token0(pool_addr);
+        let fetched_token1 = db.get_token1(pool_addr).unwrap();
+        let fetched_token0 = db.get_token0(pool_addr).unwrap();
         assert_eq!(
             fetched_token0,
             expected_token0,
@@ -235,7 +238,7 @@ mod test_db_v2 {
         );
 
         // Fetch reserves
-        let (reserve0, reserve1) = db.get_reserves(&pool_addr);
+        let (reserve0, reserve1) = db.get_reserves(&pool_addr).unwrap();
         assert!(reserve0 > U256::ZERO, "Reserve0 should be non-zero");
         assert!(reserve1 > U256::ZERO, "Reserve1 should be non-zero");
 
@@ -320,9 +323,9 @@ mod test_db_v2 {
 
         //insert some pools 
         let uni_pool = uni_v2_weth_usdc();
-        db.insert_v2(uni_pool);
+        db.insert_v2(uni_pool).unwrap();
         let sushi_pool = sushi_v2_weth_usdc();
-        db.insert_v2(sushi_pool);
+        db.insert_v2(sushi_pool).unwrap();
 
         // give the account some weth
         let one_ether = U256::from(1_000_000_000_000_000_000u128);
@@ -10,6 +10,7 @@ use log::trace;
 use pool_sync::{Pool, PoolInfo};
 use revm::DatabaseRef;
 use std::ops::{BitAnd, Shl, Shr};
+use crate::errors::StateError;
 use crate::state_db::blockstate_db::{InsertionType, BlockStateDBSlot};
 
 // Bitmasks for storage insertion
@@ -56,10 +57,11 @@ where
         let address = pool.address();
 
         // track the pool
-        self.add_pool(pool.clone());
-
         // extract the v3 pool
-        let v3_pool = pool.get_v3().unwrap();
+        let v3_pool = pool
+            .get_v3()
+            .ok_or_else(|| StateError::InvalidPool(address, "not a v3 pool".to_string()))?;
+        self.add_pool(pool.clone())?;
 
 
         // Insert slot, liquidity, tick spacing
@@ -188,18 +190,16 @@ where
     }
 
     #[inline]
-    pub fn tick_spacing(&self, address: &Address) -> Result<i32> {
-        let data = self.accounts.get(address).unwrap().storage.get(&U256::from(14)).unwrap();
-        let data = data.value;
+    pub fn tick_spacing(&self, address: &Address) -> Result<i32, StateError> {
+        let data = self.loaded_slot(*address, U256::from(14))?;
         let tick_spacing: i32 = data.saturating_to();
         Ok(tick_spacing)
     }
 
     // Get slot 0
     #[inline]
-    pub fn slot0(&self, address: Address) -> Result<UniswapV3::slot0Return> {
-        let cell = *self.accounts.get(&address).unwrap().storage.get(&U256::from(0)).unwrap();
-        let cell = cell.value;
+    pub fn slot0(&self, address: Address) -> Result<UniswapV3::slot0Return, StateError> {
+        let cell = self.loaded_slot(address, U256::from(0))?;
         let tick: Uint<24, 1> = ((Shr::<U256>::shr(cell, U256::from(160))) & *BITS24MASK).to();
         let tick: Signed<24, 1> = Signed::<24, 1>::from_raw(tick);
         let tick: i32 = tick.as_i32();
@@ -208,7 +208,9 @@ where
 
         Ok(UniswapV3::slot0Return {
             sqrtPriceX96: sqrt_price_x96,
-            tick: tick.try_into()?,
+            tick: tick
+                .try_into()
+                .map_err(|_| StateError::InvalidPool(address, format!("tick {tick} out of range")))?,
             observationIndex: ((Shr::<U256>::shr(cell, U256::from(160 + 24))) & *BITS16MASK).to(),
             observationCardinality: ((Shr::<U256>::shr(cell, U256::from(160 + 24 + 16)))
                 & *BITS16MASK)
@@ -226,20 +228,19 @@ where
     }
 
     #[inline]
-    pub fn liquidity(&self, address: Address) -> Result<u128> {
-        let cell = self.accounts.get(&address).unwrap().storage.get(&U256::from(4)).unwrap();
-        let cell = cell.value;
+    pub fn liquidity(&self, address: Address) -> Result<u128, StateError> {
+        let cell = self.loaded_slot(address, U256::from(4))?;
         let cell: u128 = cell.saturating_to();
         Ok(cell)
     }
 
     #[inline]
-    pub fn ticks_liquidity_net(&self, address: Address, tick: i32) -> Result<i128> {
+    pub fn ticks_liquidity_net(&self, address: Address, tick: i32) -> Result<i128, StateError> {
         //i24
         let cell = self.read_hashed_slot(
             &address,
             &U256::from(5),
-            &U256::from_be_bytes(I256::try_from(tick)?.to_be_bytes::<32>()),
+            &U256::from_be_bytes(Self::tick_key(address, tick)?.to_be_bytes::<32>()),
         )?;
         let unsigned_liqudity: Uint<128, 2> = cell.shr(U256::from(128)).to();
         let lu128: u128 = unsigned_liqudity.to();
@@ -249,12 +250,12 @@ where
     }
 
     #[inline]
-    pub fn tick_bitmap(&self, address: Address, tick: i16) -> Result<U256> {
+    pub fn tick_bitmap(&self, address: Address, tick: i16) -> Result<U256, StateError> {
         //i16
         let cell = self.read_hashed_slot(
             &address,
             &U256::from(6),
-            &U256::from_be_bytes(I256::try_from(tick)?.to_be_bytes::<32>()),
+            &U256::from_be_bytes(Self::tick_key(address, tick)?.to_be_bytes::<32>()),
         )?;
         Ok(cell)
     }
@@ -265,11 +266,32 @@ where
         account: &Address,
         hashmap_offset: &U256,
         item: &U256,
-    ) -> Result<U256> {
+    ) -> Result<U256, StateError> {
         let mut buf = item.to_be_bytes::<32>().to_vec();
         buf.append(&mut hashmap_offset.to_be_bytes::<32>().to_vec());
         let slot: U256 = keccak256(buf.as_slice()).into();
-        Ok(self.storage_ref(*account, slot)?)
+        self.storage_ref(*account, slot)
+    }
+
+    // A slot that was inserted with the pool, these are never fetched
+    #[inline]
+    fn loaded_slot(&self, address: Address, slot: U256) -> Result<U256, StateError> {
+        self.accounts
+            .get(&address)
+            .and_then(|account| account.storage.get(&slot))
+            .map(|cell| cell.value)
+            .ok_or(StateError::MissingSlot { address, slot })
+    }
+
+    // Sign extend a tick to use as a mapping key
+    #[inline]
+    fn tick_key<I>(address: Address, tick: I) -> Result<I256, StateError>
+    where
+        I256: TryFrom<I>,
+        I: std::fmt::Display + Copy,
+    {
+        I256::try_from(tick)
+            .map_err(|_| StateError::InvalidPool(address, format!("tick {tick} out of range")))
     }
 }
 
@@ -366,4 +388,19 @@ mod v3_db_test {
 
         Ok(())
     }
+
+    // Reading a pool we never inserted is an error instead of a panic
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_missing_pool() {
+        let provider = ProviderBuilder::new().on_http("http://localhost:8545".parse().unwrap());
+        let db = BlockStateDB::new_offline(provider).unwrap();
+        let pool_addr = address!("e375e4dd3fc5bf117aa00c5241dd89ddd979a2c4");
+
+        assert!(matches!(db.slot0(pool_addr), Err(StateError::MissingSlot { .. })));
+        assert!(matches!(db.liquidity(pool_addr), Err(StateError::MissingSlot { .. })));
+        assert!(matches!(
+            db.zero_to_one(&pool_addr, pool_addr),
+            Err(StateError::UntrackedPool(_))
+        ));
+    }
 }
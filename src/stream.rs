@@ -1,20 +1,40 @@
 use crate::events::Event;
+use crate::health::Health;
+use crate::supervisor::{wait_for_shutdown, ShutdownSignal};
 use alloy::providers::{Provider, ProviderBuilder, IpcConnect};
 use futures::StreamExt;
-use log::{debug, warn};
+use log::{debug, info, warn};
+use std::sync::Arc;
 use tokio::sync::broadcast::Sender;
 
-// Stream in new blocks
-pub async fn stream_new_blocks(block_sender: Sender<Event>) {
+// Stream in new blocks until shutdown
+pub async fn stream_new_blocks(
+    block_sender: Sender<Event>,
+    ipc_path: String,
+    health: Arc<Health>,
+    mut shutdown: ShutdownSignal,
+) {
     // Construct ipc provider
-    let ipc_conn = IpcConnect::new(std::env::var("IPC").unwrap());
+    let ipc_conn = IpcConnect::new(ipc_path);
     let ipc = ProviderBuilder::new().on_ipc(ipc_conn).await.unwrap();
 
     // Subscribe to new block stream
     let sub = ipc.subscribe_blocks().await.unwrap();
     let mut stream = sub.into_stream();
 
-    while let Some(block) = stream.next().await {
+    loop {
+        let block = tokio::select! {
+            _ = wait_for_shutdown(&mut shutdown) => {
+                info!("Block stream shutting down");
+                return;
+            }
+            block = stream.next() => block,
+        };
+        let Some(block) = block else {
+            warn!("Block stream ended");
+            return;
+        };
+        health.head_seen(block.inner.number);
         match block_sender.send(Event::NewBlock(block)) {
             Ok(_) => debug!("Block sent"),
             Err(e) => warn!("Block send failed: {:?}", e),
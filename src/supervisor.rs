@@ -0,0 +1,369 @@
+use anyhow::{bail, Result};
+use futures::future::BoxFuture;
+use futures::stream::FuturesUnordered;
+use futures::{FutureExt, StreamExt};
+use log::{error, info, warn};
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::signal::unix::{signal, SignalKind};
+use tokio::sync::watch;
+use tokio::task::{AbortHandle, JoinError, JoinHandle};
+
+use crate::config::SupervisorConfig;
+use crate::health::{Health, Stage};
+
+// What to do when a worker exits or panics
+#[derive(Copy, Clone, Debug)]
+pub enum RestartPolicy {
+    // respawn the worker, giving up and escalating after max_restarts
+    Restart { max_restarts: u32, backoff: Duration },
+    // shut the whole pipeline down
+    Escalate,
+}
+
+impl RestartPolicy {
+    // Restart policy for workers that can pick up where they left off
+    pub fn from_config(config: &SupervisorConfig) -> Self {
+        RestartPolicy::Restart {
+            max_restarts: config.max_restarts,
+            backoff: config.restart_backoff,
+        }
+    }
+}
+
+// Receiver side of the shutdown signal, workers stop once this flips to true
+pub type ShutdownSignal = watch::Receiver<bool>;
+
+// Resolves once shutdown has been requested. If the supervisor is gone without
+// requesting a shutdown, this never resolves
+pub async fn wait_for_shutdown(signal: &mut ShutdownSignal) {
+    loop {
+        if *signal.borrow_and_update() {
+            return;
+        }
+        if signal.changed().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    }
+}
+
+// A signal that is never triggered, for running workers outside of the supervisor
+pub fn detached_signal() -> ShutdownSignal {
+    watch::channel(false).1
+}
+
+type SpawnFn = Box<dyn FnMut() -> JoinHandle<()> + Send>;
+type Exit = (usize, Result<(), JoinError>);
+
+// A worker owned by the supervisor
+struct Worker {
+    name: &'static str,
+    policy: RestartPolicy,
+    // how to spawn the worker again, None if it can only run once
+    spawn: Option<SpawnFn>,
+    restarts: u32,
+    abort: Option<AbortHandle>,
+}
+
+// Owns every worker in the pipeline. Detects exits and panics, restarts or escalates per
+// policy and shuts everything down on SIGINT/SIGTERM
+pub struct Supervisor {
+    workers: Vec<Worker>,
+    running: FuturesUnordered<BoxFuture<'static, Exit>>,
+    // workers waiting out their backoff, resolving to the worker to respawn
+    restarting: FuturesUnordered<BoxFuture<'static, usize>>,
+    shutdown_tx: watch::Sender<bool>,
+    drain_timeout: Duration,
+    health: Arc<Health>,
+    // set once a signal, an escalation or every worker exiting ended supervision
+    stopped: bool,
+    failure: Option<String>,
+}
+
+impl Supervisor {
+    pub fn new(drain_timeout: Duration, health: Arc<Health>) -> Self {
+        let (shutdown_tx, _) = watch::channel(false);
+        Self {
+            workers: Vec::new(),
+            running: FuturesUnordered::new(),
+            restarting: FuturesUnordered::new(),
+            shutdown_tx,
+            drain_timeout,
+            health,
+            stopped: false,
+            failure: None,
+        }
+    }
+
+    // Get a signal that workers can listen on to know when to stop
+    pub fn shutdown_signal(&self) -> ShutdownSignal {
+        self.shutdown_tx.subscribe()
+    }
+
+    // Spawn a worker that can be restarted. The factory is called again for every restart
+    pub fn spawn_restartable<F, Fut>(&mut self, name: &'static str, policy: RestartPolicy, mut factory: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let spawn: SpawnFn = Box::new(move || tokio::spawn(factory()));
+        self.add_worker(name, policy, Some(spawn));
+    }
+
+    // Spawn a worker that only runs once, if it exits the pipeline is shut down
+    pub fn spawn<Fut>(&mut self, name: &'static str, future: Fut)
+    where
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let handle = tokio::spawn(future);
+        self.add_running(name, handle);
+    }
+
+    // Spawn a blocking worker that only runs once, if it exits the pipeline is shut down
+    pub fn spawn_blocking<F>(&mut self, name: &'static str, f: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let handle = tokio::task::spawn_blocking(f);
+        self.add_running(name, handle);
+    }
+
+    fn add_worker(&mut self, name: &'static str, policy: RestartPolicy, mut spawn: Option<SpawnFn>) {
+        let handle = (spawn.as_mut().unwrap())();
+        let index = self.workers.len();
+        self.workers.push(Worker {
+            name,
+            policy,
+            spawn,
+            restarts: 0,
+            abort: None,
+        });
+        self.watch(index, handle);
+    }
+
+    fn add_running(&mut self, name: &'static str, handle: JoinHandle<()>) {
+        let index = self.workers.len();
+        self.workers.push(Worker {
+            name,
+            policy: RestartPolicy::Escalate,
+            spawn: None,
+            restarts: 0,
+            abort: None,
+        });
+        self.watch(index, handle);
+    }
+
+    // Track a running handle so we are notified when it exits
+    fn watch(&mut self, index: usize, handle: JoinHandle<()>) {
+        let worker = &mut self.workers[index];
+        self.health.worker_started(worker.name, worker.restarts);
+        worker.abort = Some(handle.abort_handle());
+        self.running.push(handle.map(move |res| (index, res)).boxed());
+    }
+
+    // Supervise all workers until a shutdown signal is received or a worker escalates.
+    // Returns an error if the pipeline was shut down because of a worker failure
+    pub async fn run(mut self) -> Result<()> {
+        if !self.stopped {
+            self.supervise().await?;
+        }
+
+        self.shutdown().await;
+        match self.failure.take() {
+            Some(reason) => bail!(reason),
+            None => Ok(()),
+        }
+    }
+
+    // Supervise the workers started so far while waiting on a startup step. Returns None if
+    // the pipeline was stopped first, run then shuts it down and reports why
+    pub async fn run_until<Fut: Future>(&mut self, step: Fut) -> Result<Option<Fut::Output>> {
+        if self.stopped {
+            return Ok(None);
+        }
+        tokio::select! {
+            output = step => Ok(Some(output)),
+            stopped = self.supervise() => stopped.map(|_| None),
+        }
+    }
+
+    async fn supervise(&mut self) -> Result<()> {
+        let mut sigterm = signal(SignalKind::terminate())?;
+        loop {
+            tokio::select! {
+                _ = tokio::signal::ctrl_c() => {
+                    info!("Received SIGINT, shutting down...");
+                    break;
+                }
+                _ = sigterm.recv() => {
+                    info!("Received SIGTERM, shutting down...");
+                    break;
+                }
+                Some((index, res)) = self.running.next() => {
+                    if let Some(reason) = self.handle_exit(index, res) {
+                        self.failure = Some(reason);
+                        break;
+                    }
+                }
+                Some(index) = self.restarting.next() => self.respawn(index),
+                else => break,
+            }
+        }
+        self.stopped = true;
+        Ok(())
+    }
+
+    // Decide what to do with a worker that exited. Restarts are scheduled after the backoff
+    // so other exits and signals are handled meanwhile. Returns a reason if we have to escalate
+    fn handle_exit(&mut self, index: usize, res: Result<(), JoinError>) -> Option<String> {
+        let worker = &mut self.workers[index];
+        worker.abort = None;
+        self.health.worker_stopped(worker.name);
+        match &res {
+            Ok(()) => warn!("Worker {} exited", worker.name),
+            Err(e) if e.is_panic() => error!("Worker {} panicked", worker.name),
+            Err(e) => error!("Worker {} failed: {}", worker.name, e),
+        }
+
+        match worker.policy {
+            RestartPolicy::Restart { max_restarts, backoff } if worker.restarts < max_restarts => {
+                worker.restarts += 1;
+                info!(
+                    "Restarting worker {} in {:?} ({}/{})",
+                    worker.name, backoff, worker.restarts, max_restarts
+                );
+                self.restarting
+                    .push(tokio::time::sleep(backoff).map(move |_| index).boxed());
+                None
+            }
+            RestartPolicy::Restart { .. } => Some(format!(
+                "Worker {} exceeded its restart limit",
+                worker.name
+            )),
+            RestartPolicy::Escalate => Some(format!("Worker {} stopped", worker.name)),
+        }
+    }
+
+    fn respawn(&mut self, index: usize) {
+        if let Some(spawn) = self.workers[index].spawn.as_mut() {
+            let handle = spawn();
+            self.watch(index, handle);
+        }
+    }
+
+    // Signal all workers to stop and wait for them to drain
+    async fn shutdown(&mut self) {
+        self.health.set_stage(Stage::ShuttingDown);
+        self.shutdown_tx.send_replace(true);
+
+        // drop pending restarts and the factories so that any channel ends they hold are
+        // closed and downstream workers can drain and exit
+        self.restarting.clear();
+        for worker in self.workers.iter_mut() {
+            worker.spawn = None;
+        }
+
+        let drain = async {
+            while let Some((index, res)) = self.running.next().await {
+                let worker = &mut self.workers[index];
+                worker.abort = None;
+                self.health.worker_stopped(worker.name);
+                match res {
+                    Err(e) if e.is_panic() => error!("Worker {} panicked during shutdown", worker.name),
+                    _ => info!("Worker {} stopped", worker.name),
+                }
+            }
+        };
+        if tokio::time::timeout(self.drain_timeout, drain).await.is_err() {
+            warn!("Timed out waiting for workers to drain, aborting the rest");
+            for worker in self.workers.iter_mut() {
+                if let Some(abort) = worker.abort.take() {
+                    warn!("Aborting worker {}", worker.name);
+                    abort.abort();
+                }
+            }
+        }
+        info!("All workers stopped");
+    }
+}
+
+#[cfg(test)]
+mod supervisor_tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    // A worker that keeps failing is restarted until it hits its limit, then escalates
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_restart_then_escalate() {
+        let mut supervisor = Supervisor::new(Duration::from_secs(1), Arc::new(Health::new()));
+        let starts = Arc::new(AtomicU32::new(0));
+        let policy = RestartPolicy::Restart {
+            max_restarts: 2,
+            backoff: Duration::from_millis(1),
+        };
+        supervisor.spawn_restartable("flaky", policy, {
+            let starts = starts.clone();
+            move || {
+                let starts = starts.clone();
+                async move {
+                    starts.fetch_add(1, Ordering::SeqCst);
+                    panic!("boom");
+                }
+            }
+        });
+
+        assert!(supervisor.run().await.is_err());
+        assert_eq!(starts.load(Ordering::SeqCst), 3);
+    }
+
+    // A worker waiting out a long backoff does not hold up another worker escalating
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_exit_during_backoff() {
+        let mut supervisor = Supervisor::new(Duration::from_secs(1), Arc::new(Health::new()));
+        let policy = RestartPolicy::Restart {
+            max_restarts: 1,
+            backoff: Duration::from_secs(60),
+        };
+        supervisor.spawn_restartable("slow", policy, || async {});
+        supervisor.spawn("oneshot", async {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        });
+
+        let run = tokio::time::timeout(Duration::from_secs(5), supervisor.run()).await;
+        assert!(run.expect("supervisor waited out the backoff").is_err());
+    }
+
+    // A worker failing for good during startup ends the wait on a startup step
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_run_until() {
+        let mut supervisor = Supervisor::new(Duration::from_secs(1), Arc::new(Health::new()));
+        assert_eq!(supervisor.run_until(async { 1 }).await.unwrap(), Some(1));
+
+        supervisor.spawn("oneshot", async {});
+        let step = supervisor.run_until(std::future::pending::<()>());
+        let step = tokio::time::timeout(Duration::from_secs(5), step).await;
+        assert_eq!(step.expect("startup step never gave up").unwrap(), None);
+        assert!(supervisor.run().await.is_err());
+    }
+
+    // Workers listening on the shutdown signal stop once another worker escalates
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_shutdown_signal() {
+        let mut supervisor = Supervisor::new(Duration::from_secs(1), Arc::new(Health::new()));
+        let stopped = Arc::new(AtomicU32::new(0));
+
+        let mut shutdown = supervisor.shutdown_signal();
+        supervisor.spawn("listener", {
+            let stopped = stopped.clone();
+            async move {
+                let _ = shutdown.changed().await;
+                stopped.fetch_add(1, Ordering::SeqCst);
+            }
+        });
+        supervisor.spawn("oneshot", async {});
+
+        assert!(supervisor.run().await.is_err());
+        assert_eq!(stopped.load(Ordering::SeqCst), 1);
+    }
+}
@@ -1,17 +1,23 @@
 use crate::gen::FlashQuoter;
 use crate::gen::FlashSwap;
-use crate::AMOUNT;
-use alloy::primitives::Address;
+use alloy::primitives::{keccak256, Address, U256};
 use pool_sync::PoolType;
 use serde::{Deserialize, Serialize};
 use std::convert::From;
-use std::hash::Hash;
 
-// A full representation of a path that we can swap along with its hash
+// Shares of a split leg are in basis points of its input
+pub const SPLIT_PRECISION: u16 = 10_000;
+
+// A full representation of a path that we can swap along with its hash. The hash is
+// stable across runs and builds so paths can be referenced in logs and on disk
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
 pub struct SwapPath {
     pub steps: Vec<SwapStep>,
     pub hash: u64,
+    // share of its leg's input for every step, empty if no leg is split. Consecutive steps
+    // whose shares add up to SPLIT_PRECISION swap the same tokens in parallel pools
+    #[serde(default)]
+    pub splits: Vec<u16>,
 }
 
 // A step representing an individual swap
@@ -28,32 +34,238 @@ pub struct SwapStep {
 impl From<FlashQuoter::SwapParams> for FlashSwap::SwapParams {
     fn from(params: FlashQuoter::SwapParams) -> Self {
         FlashSwap::SwapParams {
+            tokenIn: params.tokenIn,
             pools: params.pools,
             poolVersions: params.poolVersions,
+            tokensOut: params.tokensOut,
+            splits: params.splits,
             amountIn: params.amountIn
         }
     }
 }
 
-// Convert from arb SwapPath into Quoter format
-impl From<SwapPath> for FlashQuoter::SwapParams {
-    fn from(path: SwapPath) -> Self {
+impl SwapPath {
+    // Construct a path from its steps, the hash identifies the path
+    pub fn new(steps: Vec<SwapStep>) -> Self {
+        Self {
+            hash: Self::path_hash(&steps, &[]),
+            steps,
+            splits: Vec::new(),
+        }
+    }
+
+    // Construct a path with split legs, the splits hold a share for every step. legs()
+    // pairs them up with the steps, so a short list would drop steps
+    pub fn with_splits(steps: Vec<SwapStep>, splits: Vec<u16>) -> Self {
+        debug_assert_eq!(splits.len(), steps.len(), "a split path needs a share for every step");
+        Self {
+            hash: Self::path_hash(&steps, &splits),
+            steps,
+            splits,
+        }
+    }
+
+    // The legs of the path, each a run of steps swapping the same tokens with the share of
+    // the leg input every step takes. Without splits every step is a leg of its own
+    pub fn legs(&self) -> Vec<Vec<(&SwapStep, u16)>> {
+        if self.splits.is_empty() {
+            return self.steps.iter().map(|step| vec![(step, SPLIT_PRECISION)]).collect();
+        }
+        let mut legs = Vec::new();
+        let mut leg = Vec::new();
+        let mut filled = 0u32;
+        for (step, &share) in self.steps.iter().zip(&self.splits) {
+            leg.push((step, share));
+            filled += share as u32;
+            if filled >= SPLIT_PRECISION as u32 {
+                legs.push(std::mem::take(&mut leg));
+                filled = 0;
+            }
+        }
+        legs
+    }
+
+    // First 8 bytes of the keccak of the packed steps, the protocol is packed by its
+    // protocol_id. Split paths pack the share of every step after it, so the same pools
+    // split differently hash differently, paths without splits hash as they always have
+    pub fn path_hash(steps: &[SwapStep], splits: &[u16]) -> u64 {
+        let mut packed = Vec::with_capacity(steps.len() * 67);
+        for (i, step) in steps.iter().enumerate() {
+            packed.extend_from_slice(step.pool_address.as_slice());
+            packed.extend_from_slice(step.token_in.as_slice());
+            packed.extend_from_slice(step.token_out.as_slice());
+            packed.push(protocol_id(step.protocol));
+            packed.extend_from_slice(&step.fee.to_be_bytes());
+            if let Some(share) = splits.get(i) {
+                packed.extend_from_slice(&share.to_be_bytes());
+            }
+        }
+        let digest = keccak256(&packed);
+        u64::from_be_bytes(digest[..8].try_into().unwrap())
+    }
+
+    // Token the path starts and ends in
+    pub fn base_token(&self) -> Address {
+        self.steps.first().map_or(Address::ZERO, |step| step.token_in)
+    }
+
+    // Convert from arb SwapPath into Quoter format with the given input amount
+    pub fn to_quote_params(&self, amount_in: U256) -> FlashQuoter::SwapParams {
         let mut pools: Vec<Address> = Vec::new();
         let mut protocol: Vec<u8> = Vec::new();
-        for step in path.steps {
+        let mut tokens_out: Vec<Address> = Vec::new();
+        for step in &self.steps {
             pools.push(step.pool_address);
-            if step.protocol.is_v3() {
-                protocol.push(1);
-            } else {
-                protocol.push(0);
-            }
+            protocol.push(pool_version(step.protocol));
+            tokens_out.push(step.token_out);
         }
         FlashQuoter::SwapParams {
+            tokenIn: self.base_token(),
             pools,
             poolVersions: protocol,
-            amountIn: *AMOUNT
+            tokensOut: tokens_out,
+            splits: self.splits.clone(),
+            amountIn: amount_in
         }
     }
 }
 
+// How the contracts swap through a pool, 0 for v2 style pairs, 1 for v3 and 2 for balancer
+// pools through the Vault
+pub fn pool_version(protocol: PoolType) -> u8 {
+    if protocol.is_v3() {
+        1
+    } else if protocol == PoolType::BalancerV2 {
+        2
+    } else {
+        0
+    }
+}
+
+// Id of a protocol in path hashes. Ids are fixed once assigned so hashes in journals and
+// caches survive upstream changes to PoolType, new protocols take the next free id
+pub fn protocol_id(protocol: PoolType) -> u8 {
+    match protocol {
+        PoolType::UniswapV2 => 0,
+        PoolType::SushiSwapV2 => 1,
+        PoolType::PancakeSwapV2 => 2,
+        PoolType::BaseSwapV2 => 3,
+        PoolType::SwapBasedV2 => 4,
+        PoolType::DackieSwapV2 => 5,
+        PoolType::AlienBaseV2 => 6,
+        PoolType::Aerodrome => 7,
+        PoolType::UniswapV3 => 8,
+        PoolType::SushiSwapV3 => 9,
+        PoolType::PancakeSwapV3 => 10,
+        PoolType::BaseSwapV3 => 11,
+        PoolType::SwapBasedV3 => 12,
+        PoolType::DackieSwapV3 => 13,
+        PoolType::AlienBaseV3 => 14,
+        PoolType::Slipstream => 15,
+        PoolType::BalancerV2 => 16,
+        PoolType::MaverickV1 => 17,
+        PoolType::MaverickV2 => 18,
+        PoolType::CurveTwoCrypto => 19,
+        PoolType::CurveTriCrypto => 20,
+    }
+}
+
+// Divide a leg's input between its pools the same way the contracts do, the last pool
+// takes the remainder
+pub fn split_amount(amount: U256, shares: &[u16]) -> Vec<U256> {
+    let mut parts = Vec::with_capacity(shares.len());
+    let mut spent = U256::ZERO;
+    let mut filled = 0u32;
+    for &share in shares {
+        filled += share as u32;
+        let part = if filled >= SPLIT_PRECISION as u32 {
+            amount - spent
+        } else {
+            amount * U256::from(share) / U256::from(SPLIT_PRECISION)
+        };
+        spent += part;
+        parts.push(part);
+    }
+    parts
+}
+
+#[cfg(test)]
+mod swap_tests {
+    use super::*;
+
+    fn step(pool: u8, token_in: u8, token_out: u8) -> SwapStep {
+        SwapStep {
+            pool_address: Address::with_last_byte(pool),
+            token_in: Address::with_last_byte(token_in),
+            token_out: Address::with_last_byte(token_out),
+            protocol: PoolType::UniswapV2,
+            fee: 0,
+        }
+    }
+
+    // Parallel steps are grouped into one leg, the rest are legs of their own
+    #[test]
+    fn test_legs() {
+        let steps = vec![step(1, 1, 2), step(2, 1, 2), step(3, 2, 1)];
+        assert_eq!(SwapPath::new(steps.clone()).legs().len(), 3);
+
+        let path = SwapPath::with_splits(steps, vec![7000, 3000, SPLIT_PRECISION]);
+        let legs = path.legs();
+        assert_eq!(legs.len(), 2);
+        assert_eq!(legs[0].iter().map(|(_, share)| *share).collect::<Vec<_>>(), vec![7000, 3000]);
+        assert_eq!(legs[1][0].0.pool_address, Address::with_last_byte(3));
+        assert_eq!(path.hash, SwapPath::path_hash(&path.steps, &path.splits));
+    }
+
+    // The same pools split differently are different paths
+    #[test]
+    fn test_split_hash() {
+        let steps = vec![step(1, 1, 2), step(2, 1, 2), step(3, 2, 1)];
+        let unsplit = SwapPath::new(steps.clone());
+        let split = SwapPath::with_splits(steps.clone(), vec![7000, 3000, SPLIT_PRECISION]);
+        let resplit = SwapPath::with_splits(steps, vec![6000, 4000, SPLIT_PRECISION]);
+        assert_ne!(split.hash, unsplit.hash);
+        assert_ne!(split.hash, resplit.hash);
+    }
 
+    // The last pool of a leg takes the rounding remainder
+    #[test]
+    fn test_split_amount() {
+        let parts = split_amount(U256::from(1001), &[3333, 3333, 3334]);
+        assert_eq!(parts, vec![U256::from(333), U256::from(333), U256::from(335)]);
+        assert_eq!(split_amount(U256::from(5), &[SPLIT_PRECISION]), vec![U256::from(5)]);
+    }
+
+    // Every step is encoded with its pool version and the token it swaps into
+    #[test]
+    fn test_quote_params() {
+        let mut balancer = step(2, 2, 3);
+        balancer.protocol = PoolType::BalancerV2;
+        let path = SwapPath::new(vec![step(1, 1, 2), balancer, step(3, 3, 1)]);
+        let params = path.to_quote_params(U256::from(7));
+        assert_eq!(params.tokenIn, Address::with_last_byte(1));
+        assert_eq!(params.poolVersions, vec![0, 2, 0]);
+        assert_eq!(
+            params.tokensOut,
+            vec![Address::with_last_byte(2), Address::with_last_byte(3), Address::with_last_byte(1)]
+        );
+        assert_eq!(pool_version(PoolType::UniswapV3), 1);
+    }
+
+    // The hash of a fixed path never changes, journals and cycle caches rely on it
+    #[test]
+    fn test_path_hash_golden() {
+        let mut v3 = step(2, 2, 1);
+        v3.protocol = PoolType::UniswapV3;
+        v3.fee = 500;
+        assert_eq!(SwapPath::path_hash(&[step(1, 1, 2), v3], &[]), 0x1d6048fc79ccb220);
+    }
+
+    // A split path without a share for every step is refused
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "a share for every step")]
+    fn test_splits_length() {
+        SwapPath::with_splits(vec![step(1, 1, 2), step(2, 1, 2)], vec![SPLIT_PRECISION]);
+    }
+}
@@ -0,0 +1,45 @@
+#[cfg(test)]
+pub mod pools {
+    use alloy::primitives::{address, Address, U256};
+    use pool_sync::{Pool, UniswapV2Pool};
+
+    pub const WETH: Address = address!("4200000000000000000000000000000000000006");
+    pub const USDC: Address = address!("833589fCD6eDb6E08f4c7C32D4f71b54bdA02913");
+    pub const DAI: Address = address!("50c5725949A6F0c72E6C4a641F24049A917DB0Cb");
+    pub const DEGEN: Address = address!("4ed4E862860beD51a9570b96d89aF5E1B0Efefed");
+
+    // V2 pool state with 18 decimals and 1_000_000 reserves on both sides
+    pub fn v2_pool(id: u8, token0: Address, token1: Address) -> UniswapV2Pool {
+        UniswapV2Pool {
+            address: Address::with_last_byte(id),
+            token0,
+            token1,
+            token0_name: String::new(),
+            token1_name: String::new(),
+            token0_decimals: 18,
+            token1_decimals: 18,
+            token0_reserves: U256::from(1_000_000),
+            token1_reserves: U256::from(1_000_000),
+            stable: None,
+            fee: None,
+        }
+    }
+
+    // Uniswap v2 pool from v2_pool
+    pub fn pool(id: u8, token0: Address, token1: Address) -> Pool {
+        Pool::UniswapV2(v2_pool(id, token0, token1))
+    }
+
+    // WETH/USDC pool with the real token names and decimals on the given protocol
+    pub fn weth_usdc(address: Address, protocol: fn(UniswapV2Pool) -> Pool, reserves: (u128, u128)) -> Pool {
+        protocol(UniswapV2Pool {
+            address,
+            token0_name: "WETH".to_string(),
+            token1_name: "USDC".to_string(),
+            token1_decimals: 6,
+            token0_reserves: U256::from(reserves.0),
+            token1_reserves: U256::from(reserves.1),
+            ..v2_pool(0, WETH, USDC)
+        })
+    }
+}
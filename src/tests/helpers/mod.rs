@@ -8,3 +8,5 @@ pub mod onchain_quote;
 pub mod test_utils;
 #[cfg(test)]
 pub mod state;
+#[cfg(test)]
+pub mod fixtures;
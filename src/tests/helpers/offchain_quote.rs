@@ -10,20 +10,22 @@ pub mod offchain_quote {
 
     use crate::market_state::MarketState;
     use crate::calculation::Calculator;
-    use crate::AMOUNT;
+    use crate::config::BotConfig;
 
     type Market = Arc<MarketState<Http<Client>, Ethereum, RootProvider<Http<Client>>>>;
 
     // Calcualte the output amount via offchain infra
-    pub fn offchain_quote(pool: &Pool, market: Market) -> U256 {
-        let calculator = Calculator::new(market);
+    pub fn offchain_quote(pool: &Pool, market: Market, config: Arc<BotConfig>) -> U256 {
+        let amount = config.amount;
+        let calculator = Calculator::new(market, config);
         let res = calculator.compute_amount_out(
-            *AMOUNT,
+            amount,
             pool.address(),
             pool.token0_address(),
+            pool.token1_address(),
             pool.pool_type(),
             pool.fee()
         );
-        res
+        res.unwrap()
     }
 }
\ No newline at end of file
@@ -9,16 +9,15 @@ pub mod onchain {
 
     use super::super::contract_gen::*;
     use super::super::test_utils::utils::evm_with_balance_and_approval;
-    use crate::AMOUNT;
+    use crate::config::BotConfig;
 
     type ProviderType = RootProvider<Http<Client>>;
 
     // Call the onchain quoter contract to get a quote
-    pub async fn onchain_quote(pool: &Pool) -> U256 {
-        dotenv::dotenv().ok();
+    pub async fn onchain_quote(pool: &Pool, config: &BotConfig) -> U256 {
         // construct a provider
-        let provider =
-            ProviderBuilder::new().on_http(std::env::var("FULL").unwrap().parse().unwrap());
+        let provider = ProviderBuilder::new().on_http(config.http_url.clone());
+        let amount = config.amount;
 
         let pool_type = pool.pool_type();
 
@@ -32,21 +31,26 @@ pub mod onchain {
             | PoolType::DackieSwapV2
             | PoolType::Aerodrome => {
                 let pool = pool.get_v2().unwrap();
-                onchain_v2(pool, pool_type, provider).await
+                onchain_v2(pool, pool_type, provider, amount).await
             }
             PoolType::UniswapV3
             | PoolType::SushiSwapV3
             | PoolType::PancakeSwapV3
             | PoolType::Slipstream => {
                 let pool = pool.get_v3().unwrap();
-                onchain_v3(pool, pool_type, provider).await
+                onchain_v3(pool, pool_type, provider, amount).await
             }
             _ => todo!(),
         }
     }
 
     // Quote the amount out for V2 Pool
-    async fn onchain_v2(pool: &UniswapV2Pool, pool_type: PoolType, provider: ProviderType) -> U256 {
+    async fn onchain_v2(
+        pool: &UniswapV2Pool,
+        pool_type: PoolType,
+        provider: ProviderType,
+        amount: U256,
+    ) -> U256 {
         // Get the router address
         let address = match pool_type {
             PoolType::UniswapV2 => address!("4752ba5dbc23f44d87826276bf6fd6b1c372ad24"),
@@ -62,7 +66,7 @@ pub mod onchain {
         if pool_type == PoolType::Aerodrome {
             let contract = Aerodrome::new(pool.address, provider);
             let Aerodrome::getAmountOutReturn { _0: amount_out } = contract
-                .getAmountOut(*AMOUNT, pool.token0)
+                .getAmountOut(amount, pool.token0)
                 .call()
                 .await
                 .unwrap();
@@ -70,7 +74,7 @@ pub mod onchain {
         } else {
             let v2_router = V2Router::new(address, provider);
             let V2Router::getAmountsOutReturn { amounts } = v2_router
-                .getAmountsOut(*AMOUNT, vec![pool.token0, pool.token1])
+                .getAmountsOut(amount, vec![pool.token0, pool.token1])
                 .call()
                 .await
                 .unwrap();
@@ -79,7 +83,12 @@ pub mod onchain {
     }
 
     // Quote the amount out for V3 Pool
-    async fn onchain_v3(pool: &UniswapV3Pool, pool_type: PoolType, provider: ProviderType) -> U256 {
+    async fn onchain_v3(
+        pool: &UniswapV3Pool,
+        pool_type: PoolType,
+        provider: ProviderType,
+        amount: U256,
+    ) -> U256 {
         // Get the quoter address
         let address = match pool_type {
             PoolType::UniswapV3 => address!("3d4e44Eb1374240CE5F1B871ab261CD16335B76a"),
@@ -103,7 +112,7 @@ pub mod onchain {
                 tokenIn: pool.token0,
                 tokenOut: pool.token1,
                 tickSpacing: tick_spacing.try_into().unwrap(),
-                amountIn: *AMOUNT,
+                amountIn: amount,
                 sqrtPriceLimitX96: U160::ZERO,
             };
             let contract = V3QuoterSlipstream::new(address, provider.clone());
@@ -118,7 +127,7 @@ pub mod onchain {
                 tokenIn: pool.token0,
                 tokenOut: pool.token1,
                 fee: pool.fee.try_into().unwrap(),
-                amountIn: *AMOUNT,
+                amountIn: amount,
                 sqrtPriceLimitX96: U160::ZERO,
             };
             let contract = V3Quoter::new(address, provider.clone());
@@ -136,7 +145,7 @@ pub mod onchain {
                         tokenOut: pool.token1,
                         fee: pool.fee.try_into().unwrap(),
                         recipient: address!("18B06aaF27d44B756FCF16Ca20C1f183EB49111f"),
-                        amountIn: *AMOUNT,
+                        amountIn: amount,
                         deadline: U256::MAX,
                         amountOutMinimum: U256::ZERO,
                         sqrtPriceLimitX96: U160::ZERO,
@@ -149,7 +158,7 @@ pub mod onchain {
                         tokenOut: pool.token1,
                         fee: pool.fee.try_into().unwrap(),
                         recipient: address!("18B06aaF27d44B756FCF16Ca20C1f183EB49111f"),
-                        amountIn: *AMOUNT,
+                        amountIn: amount,
                         amountOutMinimum: U256::ZERO,
                         sqrtPriceLimitX96: U160::ZERO,
                     };
@@ -5,34 +5,42 @@ pub mod utils {
     use alloy::sol_types::SolCall;
     use alloy::transports::http::{Client, Http};
     use pool_sync::*;
-    use std::sync::atomic::AtomicBool;
     use std::sync::Arc;
     use alloy::sol_types::SolValue;
     use std::collections::HashMap;
     use tokio::sync::broadcast;
-    use std::sync::mpsc;
+    use tokio::sync::mpsc;
     use alloy::primitives::Address;
     use revm::primitives::{address, U256, keccak256, TransactTo};
     use revm::Evm;
     use node_db::{NodeDB, InsertionType};
 
     use super::super::contract_gen::ERC20;
+    use crate::config::BotConfig;
     use crate::events::Event;
     use crate::filter::filter_pools;
+    use crate::health::Health;
     use crate::market_state::MarketState;
     use crate::stream::stream_new_blocks;
+    use crate::supervisor::detached_signal;
+
+    // Load the bot config from the environment
+    pub fn test_config() -> Arc<BotConfig> {
+        dotenv::dotenv().ok();
+        Arc::new(BotConfig::from_env().unwrap())
+    }
 
     // Load in all of the pools we want to use for testing
     pub async fn load_and_filter_pools(pool_type: Vec<PoolType>) -> (Vec<Pool>, u64) {
-        dotenv::dotenv().ok();
+        let config = test_config();
         let pool_sync = PoolSync::builder()
             .add_pools(&pool_type)
-            .chain(pool_sync::Chain::Base)
+            .chain(config.chain.chain)
             .rate_limit(1000)
             .build()
             .unwrap();
         let (pools, last_synced_block) = pool_sync.sync_pools().await.unwrap();
-        let pools = filter_pools(pools, 500, Chain::Base).await;
+        let pools = filter_pools(pools, 500, &config).await;
         (pools, last_synced_block)
     }
 
@@ -46,22 +54,31 @@ pub mod utils {
     }
 
     // Construct a new market from a set of pools
-    pub async fn construct_market(pools: Vec<Pool>, last_synced_block: u64) -> (
+    pub async fn construct_market(
+        pools: Vec<Pool>,
+        last_synced_block: u64,
+        config: Arc<BotConfig>,
+    ) -> (
         Arc<MarketState<Http<Client>, Ethereum, RootProvider<Http<Client>>>>,
         mpsc::Receiver<Event>,
     ) {
         // Create channels for communication
         let (block_sender, block_receiver) = broadcast::channel(10);
-        let (address_sender, address_receiver) = mpsc::channel();
+        let (address_sender, address_receiver) = mpsc::channel(config.channel_capacity);
 
         // Setup provider
-        let http_url = std::env::var("FULL").unwrap().parse().unwrap();
-        let provider = ProviderBuilder::new().on_http(http_url);
+        let provider = ProviderBuilder::new().on_http(config.http_url.clone());
+
+        let health = Arc::new(Health::new());
 
         // Start the block stream
-        tokio::task::spawn(stream_new_blocks(block_sender));
+        tokio::task::spawn(stream_new_blocks(
+            block_sender,
+            config.ipc_path.clone(),
+            health.clone(),
+            detached_signal(),
+        ));
 
-        let is_caught_up = Arc::new(AtomicBool::new(false));
         // Initialize market state with pools and channels
         let market_state = MarketState::init_state_and_start_stream(
             pools,
@@ -69,7 +86,8 @@ pub mod utils {
             address_sender,
             last_synced_block,
             provider,
-            is_caught_up.clone()
+            health,
+            config,
         )
         .await
         .unwrap();
@@ -82,9 +100,8 @@ pub mod utils {
     // setup an evnm instance with some weth and approve the router to spend it
     pub fn evm_with_balance_and_approval(router: Address, token: Address) -> Evm<'static, (), NodeDB> {
         // construct the db
-        dotenv::dotenv().ok();
-        let database_path = std::env::var("DB_PATH").unwrap();
-        let mut node_db = NodeDB::new(database_path).unwrap();
+        let config = test_config();
+        let mut node_db = NodeDB::new(config.db_path.clone()).unwrap();
     
         let account = address!("18B06aaF27d44B756FCF16Ca20C1f183EB49111f");
         let balance_slot = U256::from(3);
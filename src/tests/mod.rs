@@ -3,4 +3,4 @@ mod test_estimation;
 #[cfg(test)]
 mod test_quotes;
 #[cfg(test)]
-mod helpers;
\ No newline at end of file
+pub mod helpers;
\ No newline at end of file
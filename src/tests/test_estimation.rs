@@ -1,7 +1,9 @@
 // All offchain calculation tests
 #[cfg(test)]
 mod estimation {
-    use super::super::helpers::test_utils::utils::{construct_market, load_and_filter_pools};
+    use super::super::helpers::test_utils::utils::{
+        construct_market, load_and_filter_pools, test_config,
+    };
     use crate::calculation::Calculator;
     use crate::estimator::Estimator;
     use crate::events::Event;
@@ -13,7 +15,7 @@ mod estimation {
     // Manually compare swap path estimations to their calculated rate
     #[tokio::test(flavor = "multi_thread")]
     pub async fn test_path_estimations() {
-        dotenv::dotenv().ok();
+        let config = test_config();
         // load pools and get cycles
         let (pools, last_synced_block) =
             load_and_filter_pools(vec![
@@ -28,24 +30,24 @@ mod estimation {
                 //PoolType::Aerodrome,
                 PoolType::Slipstream
             ]).await;
-        let cycles = ArbGraph::generate_cycles(pools.clone()).await;
+        let cycles = ArbGraph::generate_cycles(pools.clone(), &config).await;
         println!("Generated {} cycles", cycles.len());
 
         // init a market state with the new relevant pools
-        let (market, address_rx) = construct_market(pools.clone(), last_synced_block).await;
+        let (market, mut address_rx) = construct_market(pools.clone(), last_synced_block, config.clone()).await;
 
         // construct the calculator and estimator
-        let mut estimator = Estimator::new(market.clone());
+        let mut estimator = Estimator::new(market.clone(), config.clone());
         estimator.process_pools(pools.clone());
-        let calculator = Calculator::new(market.clone());
+        let calculator = Calculator::new(market.clone(), config.clone());
 
         // while we get an update (new block), test onchain and offchain for all pools
-        while let Ok(Event::PoolsTouched(addresses, _)) = address_rx.recv() {
+        while let Some(Event::PoolsTouched(addresses, _)) = address_rx.recv().await {
             estimator.update_rates(&addresses);
             println!("Touched {} addresses", addresses.len());
             for path in &cycles {
                 let offchain_amt = calculator.calculate_output(&path.clone());
-                let est_amt = estimator.estimate_output_amount(path);
+                let est_amt = estimator.estimate_output_amount(path, config.amount);
                 println!("offchain {:?}, estimation {:?}", offchain_amt, est_amt);
             }
         }
@@ -55,7 +57,7 @@ mod estimation {
     // estimated and quoted rates
     #[tokio::test(flavor = "multi_thread")]
     async fn test_calculated_to_estimated() {
-        dotenv::dotenv().ok();
+        let config = test_config();
 
         // get the pools that we want to arb over
         let pool_addrs = [
@@ -69,19 +71,19 @@ mod estimation {
             .collect();
 
         // construct the market with the new pools
-        let (market, _ ) = construct_market(pools.clone(), last_synced_block).await;
+        let (market, _ ) = construct_market(pools.clone(), last_synced_block, config.clone()).await;
 
         // construct estimator and calculator
-        let mut estimator = Estimator::new(market.clone());
+        let mut estimator = Estimator::new(market.clone(), config.clone());
         estimator.process_pools(pools.clone());
-        let calculator = Calculator::new(market.clone());
+        let calculator = Calculator::new(market.clone(), config.clone());
 
         // there should be only 1 cycle 
-        let cycles = ArbGraph::generate_cycles(pools.clone()).await;
+        let cycles = ArbGraph::generate_cycles(pools.clone(), &config).await;
         let path = cycles.first().unwrap();
 
         let offchain = calculator.calculate_output(&path.clone());
-        let est = estimator.estimate_output_amount(path);
+        let est = estimator.estimate_output_amount(path, config.amount);
         println!("offchain {:?}, estimation {:?}", offchain, est);
     }
 }
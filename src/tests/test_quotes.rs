@@ -4,15 +4,18 @@ mod offchain_calculations {
     use super::super::helpers::offchain_quote::offchain_quote::offchain_quote;
     use super::super::helpers::onchain_quote::onchain::onchain_quote;
     use super::super::helpers::test_utils::utils::{
-        construct_market, construct_pool_map, load_and_filter_pools,
+        construct_market, construct_pool_map, load_and_filter_pools, test_config,
     };
     use crate::events::Event;
     use crate::gen::{ERC20Token, FlashQuoter};
+    use crate::market_state::MarketState;
+    use crate::quoter::Quoter;
+    use crate::swap::pool_version;
     use crate::state_db::{BlockStateDB, InsertionType};
     use alloy::primitives::{address, U256};
     use alloy::providers::ProviderBuilder;
     use alloy::sol_types::{SolCall, SolValue};
-    use pool_sync::PoolType;
+    use pool_sync::{PoolInfo, PoolType};
     use revm::primitives::keccak256;
     use revm::primitives::{AccountInfo, Bytecode, TransactTo};
     use revm::Evm;
@@ -92,8 +95,11 @@ mod offchain_calculations {
             for j in 0..pools.len() {
                 if i != j {
                     let swap_params = FlashQuoter::SwapParams {
+                        tokenIn: address!("4200000000000000000000000000000000000006"),
                         pools: vec![pools[i].0, pools[j].0],
                         poolVersions: vec![pools[i].1, pools[j].1],
+                        tokensOut: Vec::new(),
+                        splits: Vec::new(),
                         amountIn: U256::from(1e16),
                     };
                     let quote_call = FlashQuoter::quoteArbitrageCall {
@@ -112,27 +118,62 @@ mod offchain_calculations {
         }
     }
 
+    // The quoter the market state injects into the db, with the v2 factory fees seeded
+    // from the chain profile, quotes a path through a weth pool
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_injected_quoter() {
+        let config = test_config();
+        let weth = config.chain.weth;
+        let (pools, last_synced_block) = load_and_filter_pools(vec![PoolType::UniswapV2]).await;
+        let pool = pools
+            .into_iter()
+            .find(|pool| pool.token0_address() == weth || pool.token1_address() == weth)
+            .unwrap();
+        let token_out = if pool.token0_address() == weth {
+            pool.token1_address()
+        } else {
+            pool.token0_address()
+        };
+
+        let provider = ProviderBuilder::new().on_http(config.http_url.clone());
+        let market = MarketState::init_state(vec![pool.clone()], last_synced_block, provider, &config)
+            .await
+            .unwrap();
+        let swap_params = FlashQuoter::SwapParams {
+            tokenIn: weth,
+            pools: vec![pool.address()],
+            poolVersions: vec![pool_version(pool.pool_type())],
+            tokensOut: vec![token_out],
+            splits: Vec::new(),
+            amountIn: config.base_amount(&weth),
+        };
+        let amounts = Quoter::quote_path(swap_params, market).unwrap();
+        assert_eq!(amounts.len(), 2);
+        assert!(amounts[1] > U256::ZERO);
+    }
+
     // Test the outputs for all pools
     macro_rules! test_pool_out {
         ($test_name:ident, $pool_type:ident) => {
             #[tokio::test(flavor = "multi_thread")]
             pub async fn $test_name() {
-                dotenv::dotenv().ok();
+                let config = test_config();
                 // load and filter pools
                 let (pools, last_synced_block) =
                     load_and_filter_pools(vec![PoolType::$pool_type]).await;
                 // Pool map for references
                 let pool_map = construct_pool_map(pools.clone());
                 // init a market state with the new relevant pools
-                let (market, address_rx) = construct_market(pools.clone(), last_synced_block).await;
+                let (market, mut address_rx) =
+                    construct_market(pools.clone(), last_synced_block, config.clone()).await;
                 // while we get an update (new block), test onchain and offchain for all pools
-                while let Ok(Event::PoolsTouched(addresses, _)) = address_rx.recv() {
+                while let Some(Event::PoolsTouched(addresses, _)) = address_rx.recv().await {
                     println!("{} touched pools", addresses.len());
                     for address in addresses {
                         let pool = pool_map.get(&address).unwrap();
                         // Get both and offchain and an onchain amount out, they should be the same
-                        let offchain = offchain_quote(&pool, market.clone());
-                        let onchain = onchain_quote(&pool).await;
+                        let offchain = offchain_quote(&pool, market.clone(), config.clone());
+                        let onchain = onchain_quote(&pool, &config).await;
                         assert_eq!(offchain, onchain, "failed with pool {:#?}", pool);
                     }
                     println!("Iteration finished");
@@ -1,42 +1,54 @@
+use crate::config::{BaseToken, BotConfig};
+use crate::errors::SubmissionError;
 use crate::events::Event;
 use crate::gas_station::GasStation;
-use crate::gen::FlashSwap;
+use crate::health::Health;
+use crate::gen::{ERC20Token, FlashQuoter, FlashSwap};
+use crate::metrics::{
+    GAS_SPENT_WEI, REALIZED_PROFIT_WEI, STALE_EVENTS, TXS_FAILED, TXS_LANDED, TXS_SENT,
+};
+use crate::quoter::dispatches;
 use alloy::eips::eip2718::Encodable2718;
 use alloy::hex;
 use alloy::network::{EthereumWallet, TransactionBuilder};
-use alloy::primitives::{Address, FixedBytes};
+use alloy::primitives::{Address, FixedBytes, U256};
 use alloy::primitives::Bytes as AlloyBytes;
 use alloy::providers::{Provider, ProviderBuilder, RootProvider};
-use alloy::rpc::types::TransactionRequest;
+use alloy::rpc::types::{Log, TransactionRequest};
 use alloy::signers::k256::SecretKey;
 use alloy::signers::local::PrivateKeySigner;
 use alloy::sol_types::SolCall;
 use alloy::transports::http::{Client as AlloyClient, Http};
-use log::info;
+use anyhow::{ensure, Context, Result};
+use log::{info, warn};
 use reqwest::Client;
 use serde_json::Value;
 use std::str::FromStr;
-use std::sync::mpsc::Receiver;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
+use tokio::sync::mpsc::Receiver;
+use tokio::task::JoinSet;
 
 
 // Handles sending transactions
 pub struct TransactionSender {
     wallet: EthereumWallet,
+    chain_id: u64,
+    submission_url: String,
     gas_station: Arc<GasStation>,
     contract_address: Address,
     client: Arc<Client>,
     provider: Arc<RootProvider<Http<AlloyClient>>>,
     nonce: u64,
+    config: Arc<BotConfig>,
 }
 
 impl TransactionSender {
-    pub async fn new(gas_station: Arc<GasStation>) -> Self {
+    pub async fn new(gas_station: Arc<GasStation>, config: Arc<BotConfig>) -> Result<Self> {
         // construct a wallet
-        let key = std::env::var("PRIVATE_KEY").unwrap();
-        let key_hex = hex::decode(key).unwrap();
-        let key = SecretKey::from_bytes((&key_hex[..]).into()).unwrap();
+        let key_hex = hex::decode(&config.private_key).context("Private key is not valid hex")?;
+        ensure!(key_hex.len() == 32, "Private key must be 32 bytes, got {}", key_hex.len());
+        let key = SecretKey::from_bytes((&key_hex[..]).into()).context("Invalid private key")?;
         let signer = PrivateKeySigner::from(key);
         let wallet = EthereumWallet::from(signer);
 
@@ -49,7 +61,7 @@ impl TransactionSender {
             .timeout(Duration::from_secs(10))
             .connect_timeout(Duration::from_secs(5))
             .build()
-            .expect("Failed to create HTTP client");
+            .context("Failed to create HTTP client")?;
         // Warm up connection by sending a simple eth_blockNumber request
         let warmup_json = serde_json::json!({
             "jsonrpc": "2.0",
@@ -58,97 +70,180 @@ impl TransactionSender {
             "id": 1
         });
         let _ = client
-            .post("https://mainnet-sequencer.base.org")
+            .post(&config.chain.submission_url)
             .json(&warmup_json)
             .send()
             .await
-            .unwrap();
+            .context("Failed to reach the submission endpoint")?;
 
         // construct a provider for tx receipts and nonce
-        let provider = Arc::new(
-            ProviderBuilder::new().on_http(std::env::var("FULL").unwrap().parse().unwrap()),
-        );
+        let provider = Arc::new(ProviderBuilder::new().on_http(config.http_url.clone()));
         let nonce = provider
-            .get_transaction_count(std::env::var("ACCOUNT").unwrap().parse().unwrap())
+            .get_transaction_count(config.account)
             .await
-            .unwrap();
+            .context("Failed to fetch the account nonce")?;
+
+        // a swap contract deployed from an older FlashSwap.sol rejects our calldata, so
+        // every path would revert on chain
+        if !config.simulate_only {
+            let code = provider.get_code_at(config.swap_contract).await?;
+            ensure!(
+                dispatches(&code, FlashSwap::executeArbitrageCall::SELECTOR),
+                "swap contract {} does not match the FlashSwap abi, redeploy it",
+                config.swap_contract
+            );
+        }
 
-        Self {
+        Ok(Self {
             wallet,
+            chain_id: config.chain.chain_id,
+            submission_url: config.chain.submission_url.clone(),
             gas_station,
-            contract_address: std::env::var("SWAP_CONTRACT").unwrap().parse().unwrap(),
+            contract_address: config.swap_contract,
             client: Arc::new(client),
             provider,
             nonce,
-        }
+            config,
+        })
     }
 
 
-    // Receive a path that has passed simulation to be sent to the sequencer
-    pub async fn send_transactions(&mut self, tx_receiver: Receiver<Event>) {
+    // Receive a path that has passed simulation to be sent to the sequencer. Paths for
+    // a block older than the head are dropped. Once the simulator hangs up, wait for all
+    // in flight transactions to be resolved
+    pub async fn send_transactions(&mut self, mut tx_receiver: Receiver<Event>, health: Arc<Health>) {
+        let mut in_flight = JoinSet::new();
         // wait for a new transaction that has passed simulation
-        while let Ok(Event::ValidPath((arb_path, profit, block_number))) = tx_receiver.recv()
+        while let Some(Event::ValidPath((arb_path, profit, block_number))) = tx_receiver.recv().await
         {
-            info!("Sending path...");
-
-            // Setup the calldata
-            let converted_path: FlashSwap::SwapParams = arb_path.clone().into();
-            let calldata = FlashSwap::executeArbitrageCall {
-                arb: converted_path
+            if health.is_stale(block_number) {
+                warn!("Dropping stale path from block {}", block_number);
+                STALE_EVENTS.with_label_values(&["tx_sender"]).inc();
+                continue;
             }
-            .abi_encode();
-
-            // Construct, sign, and encode transaction
-            let (max_fee, priority_fee) = self.gas_station.get_gas_fees(profit);
-            let tx = TransactionRequest::default()
-                .with_to(self.contract_address)
-                .with_nonce(self.nonce)
-                .with_gas_limit(2_000_000)
-                .with_chain_id(8453)
-                .with_max_fee_per_gas(max_fee)
-                .with_max_priority_fee_per_gas(priority_fee)
-                .transaction_type(2)
-                .with_input(AlloyBytes::from(calldata));
-            self.nonce += 1;
-            let tx_envelope = tx.build(&self.wallet).await.unwrap();
-            let mut encoded_tx = vec![];
-            tx_envelope.encode_2718(&mut encoded_tx);
-            let rlp_hex = hex::encode_prefixed(encoded_tx);
-
-            let tx_data = serde_json::json!({
-                "jsonrpc": "2.0",
-                "method": "eth_sendRawTransaction",
-                "params": [rlp_hex],
-                "id": 1
-            });
+            info!("Sending path...");
+            let base = self.config.base(&arb_path.tokenIn).cloned();
 
             // Send the transaciton off and monitor its status
-            info!("Sending on block {}", block_number);
-            let start = Instant::now();
-
-            // construct the request and send it
-            let req = self.client
-                .post("https://mainnet-sequencer.base.org")
-                .json(&tx_data)
-                .send()
-                .await
-                .unwrap();
-            let req_response: Value = req.json().await.unwrap();
-            info!("Took {:?} to send tx and receive response", start.elapsed());
-            let tx_hash = FixedBytes::<32>::from_str(req_response["result"].as_str().unwrap()).unwrap();
-
+            let tx_hash = match self.submit(arb_path, profit, block_number).await {
+                Ok(tx_hash) => tx_hash,
+                Err(e) => {
+                    warn!("Failed to send path from block {}: {}", block_number, e);
+                    TXS_FAILED.inc();
+                    continue;
+                }
+            };
+            TXS_SENT.inc();
+
+            // clear out any monitors that have finished
+            while in_flight.try_join_next().is_some() {}
             let provider = self.provider.clone();
-            tokio::spawn(async move {
-                Self::send_and_monitor(provider,tx_hash, block_number).await;
+            let (contract, account) = (self.contract_address, self.config.account);
+            in_flight.spawn(async move {
+                Self::send_and_monitor(provider, tx_hash, block_number, base, contract, account).await;
             });
         }
+
+        info!("Waiting on {} in flight transactions...", in_flight.len());
+        while in_flight.join_next().await.is_some() {}
+    }
+
+    // Build, sign, and submit the transaction for a path, returning its hash once the
+    // sequencer has accepted it
+    async fn submit(
+        &mut self,
+        arb_path: FlashQuoter::SwapParams,
+        profit: U256,
+        block_number: u64,
+    ) -> Result<FixedBytes<32>, SubmissionError> {
+        // Setup the calldata
+        let converted_path: FlashSwap::SwapParams = arb_path.into();
+        let calldata = FlashSwap::executeArbitrageCall {
+            arb: converted_path
+        }
+        .abi_encode();
+
+        // Construct, sign, and encode transaction
+        let (max_fee, priority_fee) = self.gas_station.get_gas_fees(profit);
+        let tx = TransactionRequest::default()
+            .with_to(self.contract_address)
+            .with_nonce(self.nonce)
+            .with_gas_limit(2_000_000)
+            .with_chain_id(self.chain_id)
+            .with_max_fee_per_gas(max_fee)
+            .with_max_priority_fee_per_gas(priority_fee)
+            .transaction_type(2)
+            .with_input(AlloyBytes::from(calldata));
+        let tx_envelope = tx
+            .build(&self.wallet)
+            .await
+            .map_err(|e| SubmissionError::Signing(e.to_string()))?;
+        let mut encoded_tx = vec![];
+        tx_envelope.encode_2718(&mut encoded_tx);
+        let rlp_hex = hex::encode_prefixed(encoded_tx);
+
+        // only move on to the next nonce once this one was taken
+        match self.send_raw(rlp_hex, block_number).await {
+            Ok(tx_hash) => {
+                self.nonce += 1;
+                Ok(tx_hash)
+            }
+            Err(e) => {
+                self.resync_nonce().await;
+                Err(e)
+            }
+        }
     }
 
-    // Send the transaction and monitor its status
+    // Submit a signed transaction, returning its hash once the sequencer has accepted it
+    async fn send_raw(&self, rlp_hex: String, block_number: u64) -> Result<FixedBytes<32>, SubmissionError> {
+        let tx_data = serde_json::json!({
+            "jsonrpc": "2.0",
+            "method": "eth_sendRawTransaction",
+            "params": [rlp_hex],
+            "id": 1
+        });
+
+        info!("Sending on block {}", block_number);
+        let start = Instant::now();
+
+        // construct the request and send it
+        let req = self.client
+            .post(&self.submission_url)
+            .json(&tx_data)
+            .send()
+            .await
+            .map_err(|e| SubmissionError::Request(e.to_string()))?;
+        let req_response: Value = req
+            .json()
+            .await
+            .map_err(|e| SubmissionError::InvalidResponse(e.to_string()))?;
+        info!("Took {:?} to send tx and receive response", start.elapsed());
+        let Some(tx_hash) = req_response["result"].as_str() else {
+            return Err(SubmissionError::Rejected(req_response["error"].to_string()));
+        };
+        FixedBytes::<32>::from_str(tx_hash)
+            .map_err(|_| SubmissionError::InvalidResponse(format!("bad transaction hash {tx_hash}")))
+    }
+
+    // A transaction that failed to submit may still have reached the sequencer, so take
+    // the nonce from the chain again instead of guessing
+    async fn resync_nonce(&mut self) {
+        match self.provider.get_transaction_count(self.config.account).pending().await {
+            Ok(nonce) => self.nonce = nonce,
+            Err(e) => warn!("Failed to resync the nonce: {}", e),
+        }
+    }
+
+    // Monitor the status of a sent transaction. The realized profit is what the swap
+    // contract paid out to us in the base token, less the gas spent
     pub async fn send_and_monitor(
         provider: Arc<RootProvider<Http<AlloyClient>>>,
         tx_hash: FixedBytes<32>,
         block_number: u64,
+        base: Option<BaseToken>,
+        contract: Address,
+        account: Address,
     ) {
         // loop while waiting for tx receipt
         let mut attempts = 0;
@@ -156,63 +251,111 @@ impl TransactionSender {
             // try to fetch the receipt
             let receipt = provider.get_transaction_receipt(tx_hash).await;
             if let Ok(Some(inner)) = receipt {
-                info!("Send on block {:?}, Landed on block {:?}", block_number, inner.block_number.unwrap());
+                info!("Send on block {:?}, Landed on block {:?}", block_number, inner.block_number);
+                let gas_wei = inner.gas_used as f64 * inner.effective_gas_price as f64;
+                GAS_SPENT_WEI.inc_by(gas_wei);
+                REALIZED_PROFIT_WEI.sub(gas_wei);
+                if inner.status() {
+                    TXS_LANDED.inc();
+                    if let Some(base) = base {
+                        let paid = paid_out(inner.inner.logs(), base.token, contract, account);
+                        REALIZED_PROFIT_WEI.add(f64::from(base.to_eth(paid)));
+                    }
+                } else {
+                    warn!("Transaction {} reverted", tx_hash);
+                    TXS_FAILED.inc();
+                }
                 return;
             }
 
             tokio::time::sleep(Duration::from_secs(2)).await;
             attempts += 1;
         }
+        warn!("Transaction {} never landed", tx_hash);
+        TXS_FAILED.inc();
 
     }
 }
 
 
 
+// Amount of a token the swap contract transferred to us in a receipt's logs
+fn paid_out(logs: &[Log], token: Address, contract: Address, account: Address) -> U256 {
+    logs.iter()
+        .filter(|log| log.address() == token)
+        .filter_map(|log| log.log_decode::<ERC20Token::Transfer>().ok())
+        .map(|log| log.inner.data)
+        .filter(|transfer| transfer.from == contract && transfer.to == account)
+        .fold(U256::ZERO, |paid, transfer| paid + transfer.value)
+}
+
 // Test transaction sending functionality
 #[cfg(test)]
 mod tx_signing_tests {
     use alloy::primitives::{address, U256};
     use alloy::providers::{Provider, ProviderBuilder};
+    use alloy::sol_types::SolEvent;
     use env_logger;
     use crate::gen::FlashQuoter;
     use pool_sync::PoolType;
     use std::time::Instant;
-    use crate::AMOUNT;
     use super::*;
 
     // Create mock swap params
-    fn dummy_swap_params() ->  FlashQuoter::SwapParams {
+    fn dummy_swap_params(amount: U256) ->  FlashQuoter::SwapParams {
         let p1 = address!("4C36388bE6F416A29C8d8Eee81C771cE6bE14B18");
         let p2 = address!("9A834b70C07C81a9FCB695573D9008d0eF23A998");
         FlashQuoter::SwapParams {
+            tokenIn: address!("4200000000000000000000000000000000000006"),
             pools: vec![p1, p2],
             poolVersions: vec![0, 0],
-            amountIn: *AMOUNT
+            tokensOut: Vec::new(),
+            splits: Vec::new(),
+            amountIn: amount
         }
     }
 
 
-    // Test the time it takes to create a transaction
+    // Only transfers of the base token from the swap contract to us count as profit
+    #[test]
+    fn test_paid_out() {
+        let (token, other) = (Address::with_last_byte(1), Address::with_last_byte(2));
+        let (contract, account) = (Address::with_last_byte(3), Address::with_last_byte(4));
+        let transfer = |token: Address, from: Address, to: Address, value: u64| {
+            let data = ERC20Token::Transfer { from, to, value: U256::from(value) }.encode_log_data();
+            Log {
+                inner: alloy::primitives::Log { address: token, data },
+                ..Default::default()
+            }
+        };
+        let logs = vec![
+            transfer(token, contract, account, 70),
+            transfer(token, other, contract, 1_000),
+            transfer(other, contract, account, 5),
+            transfer(token, contract, account, 30),
+        ];
+        assert_eq!(paid_out(&logs, token, contract, account), U256::from(100));
+    }
+
+        // Test the time it takes to create a transaction
     #[tokio::test(flavor = "multi_thread")]
     async fn test_sign() {
         // init and get all dummy state
         dotenv::dotenv().ok();
-        let key = std::env::var("PRIVATE_KEY").unwrap();
-        let key_hex = hex::decode(key).unwrap();
-        let key = SecretKey::from_bytes((&key_hex[..]).into()).unwrap();
+        let config = BotConfig::from_env().unwrap();
+        let key_hex = hex::decode(&config.private_key).context("Private key is not valid hex")?;
+        ensure!(key_hex.len() == 32, "Private key must be 32 bytes, got {}", key_hex.len());
+        let key = SecretKey::from_bytes((&key_hex[..]).into()).context("Invalid private key")?;
         let signer = PrivateKeySigner::from(key);
         let wallet = EthereumWallet::from(signer);
-        let url = std::env::var("FULL").unwrap();
         let wallet_provider = Arc::new(
             ProviderBuilder::new()
                 .with_recommended_fillers()
                 .wallet(wallet)
-                .on_http(url),
+                .on_http(config.http_url.clone()),
         );
-        let contract_address = std::env::var("SWAP_CONTRACT").unwrap();
-        let contract = FlashSwap::new(contract_address.parse().unwrap(), wallet_provider.clone());
-        let path: FlashSwap::SwapParams = dummy_swap_params().into();
+        let contract = FlashSwap::new(config.swap_contract, wallet_provider.clone());
+        let path: FlashSwap::SwapParams = dummy_swap_params(config.amount).into();
 
         // benchmark tx construction
         let gas = wallet_provider.estimate_eip1559_fees(None).await.unwrap();
@@ -224,7 +367,7 @@ mod tx_signing_tests {
             .executeArbitrage(path)
             .max_fee_per_gas(max_fee)
             .max_priority_fee_per_gas(priority_fee)
-            .chain_id(8453)
+            .chain_id(config.chain.chain_id)
             .gas(4_000_000)
             .into_transaction_request();
         println!("Tx construction took {:?}", tx_time.elapsed());
@@ -235,28 +378,30 @@ mod tx_signing_tests {
         // init environment
         env_logger::builder().filter_level(log::LevelFilter::Info);
         dotenv::dotenv().ok();
+        let config = Arc::new(BotConfig::from_env().unwrap());
 
         // Create gas station
-        let gas_station = Arc::new(GasStation::new());
+        let gas_station = Arc::new(GasStation::new(config.chain.base_fee_params));
 
         // Create transaction sender
-        let mut tx_sender = TransactionSender::new(gas_station).await;
+        let mut tx_sender = TransactionSender::new(gas_station, config.clone()).await.unwrap();
 
         // Create a channel for sending events
-        let (tx, rx) = std::sync::mpsc::channel();
+        let (tx, rx) = tokio::sync::mpsc::channel(1);
 
         // Create and send a test event
-        let swap_path = dummy_swap_params();
+        let swap_path = dummy_swap_params(config.amount);
         let test_event = Event::ValidPath((
             swap_path,
             alloy::primitives::U256::from(10000000), // test input amount
             100u64,                                  // dummy block number
         ));
 
-        tx.send(test_event).unwrap();
+        tx.send(test_event).await.unwrap();
+        drop(tx);
 
         // Send the transaction (this will only process one transaction and then exit)
-        tx_sender.send_transactions(rx).await;
+        tx_sender.send_transactions(rx, Arc::new(Health::new())).await;
     }
 }
 